@@ -21,11 +21,13 @@ use nix::unistd::sync;
 use procfs::sys::vm::{drop_caches, DropCache};
 use rand::{Rng, SeedableRng};
 use rand_xorshift::XorShiftRng;
+use regtail::config::Config;
 use regtail::tail::{from_file_to_sink, tail_from_reader};
+use regtail::watcher::DirectoryWatcher;
 use std::cmp::min;
 use std::fs;
 use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::io::{self, BufWriter, Write};
 use std::path::PathBuf;
 
 fn setup_bench(bench_directory: &str) -> PathBuf {
@@ -75,6 +77,12 @@ fn big_file_tail(path: &PathBuf, lines: u64) {
     tail_from_reader(&mut state, lines).unwrap();
 }
 
+// Walk the directory and print the last lines of every file, as regtail does on startup
+fn many_files_initial_tail(config: &Config) {
+    let mut watcher = DirectoryWatcher::with_writer(config, io::sink()).unwrap();
+    watcher.tail_initial_files(config).unwrap();
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     const LINES: u64 = 10000;
     let parent_path = setup_bench("big_file");
@@ -88,6 +96,28 @@ fn criterion_benchmark(c: &mut Criterion) {
     put_random_file(&path, 8 * 1024 * 1024, seed);
 
     c.bench_function("big_file_tail", |b| b.iter(|| big_file_tail(&path, LINES)));
+
+    // Create 2000 small files of 4KB
+    const FILES: usize = 2000;
+    let many_files_path = setup_bench("many_files");
+    for i in 0..FILES {
+        let mut seed = seed;
+        seed[0] = i as u8;
+        seed[1] = (i >> 8) as u8;
+        put_random_file(
+            &many_files_path.join(format!("file{}.log", i)),
+            4 * 1024,
+            seed,
+        );
+    }
+    let config = Config::builder()
+        .path(many_files_path.clone())
+        .lines(10)
+        .build();
+
+    c.bench_function("many_files_initial_tail", |b| {
+        b.iter(|| many_files_initial_tail(&config))
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,178 @@
+/*
+ * Copyright 2019 StoneDot (Hiroaki Goto)
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+
+use lru::LruCache;
+
+// Groups the files by their directories for the fair cache
+#[allow(clippy::ptr_arg)]
+pub fn parent_dir(path: &PathBuf) -> PathBuf {
+    path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+// Least recently used cache of the open handles, bounded by the capacity.
+// When fair, the keys are partitioned into groups such as the parent directories,
+// and the handle is evicted from the group holding the most handles, so that
+// a busy group cannot evict the only handle of a quiet group.
+pub struct HandleCache<K: Hash + Eq, V> {
+    handles: LruCache<K, V>,
+    capacity: usize,
+    group_of: Option<fn(&K) -> K>,
+    // Keys evicted for the capacity, which are counted as reopened when put again
+    evicted: HashSet<K>,
+    reopened: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> HandleCache<K, V> {
+    pub fn new(capacity: usize) -> HandleCache<K, V> {
+        HandleCache {
+            handles: LruCache::unbounded(),
+            capacity,
+            group_of: None,
+            evicted: HashSet::new(),
+            reopened: 0,
+        }
+    }
+
+    pub fn fair(capacity: usize, group_of: fn(&K) -> K) -> HandleCache<K, V> {
+        HandleCache {
+            group_of: Some(group_of),
+            ..Self::new(capacity)
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.handles.get(key)
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.handles.contains(key)
+    }
+
+    pub fn put(&mut self, key: K, value: V) {
+        if !self.handles.contains(&key) {
+            while self.handles.len() >= self.capacity.max(1) {
+                self.evict();
+            }
+            if self.evicted.remove(&key) {
+                self.reopened += 1;
+            }
+        }
+        self.handles.put(key, value);
+    }
+
+    // The handle is no longer needed, such as for a removed file
+    pub fn pop(&mut self, key: &K) -> Option<V> {
+        self.evicted.remove(key);
+        self.handles.pop(key)
+    }
+
+    // Close a handle to make room, the least recently used one unless fair
+    pub fn evict(&mut self) -> Option<(K, V)> {
+        let victim = match self.group_of {
+            Some(group_of) => self.fair_victim(group_of),
+            None => self.handles.peek_lru().map(|(key, _)| key.clone()),
+        }?;
+        let value = self.handles.pop(&victim)?;
+        self.evicted.insert(victim.clone());
+        Some((victim, value))
+    }
+
+    // The least recently used handle of the group holding the most handles
+    fn fair_victim(&self, group_of: fn(&K) -> K) -> Option<K> {
+        let mut counts: HashMap<K, usize> = HashMap::new();
+        for (key, _) in self.handles.iter() {
+            *counts.entry(group_of(key)).or_insert(0) += 1;
+        }
+        let largest = counts.values().copied().max()?;
+        self.handles
+            .iter()
+            .rev()
+            .find(|(key, _)| counts[&group_of(key)] == largest)
+            .map(|(key, _)| key.clone())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.handles.clear();
+        self.evicted.clear();
+    }
+
+    // Handles opened again after evicted for the capacity
+    pub fn reopened(&self) -> u64 {
+        self.reopened
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{parent_dir, HandleCache};
+
+    // The active directory is written repeatedly while the quiet one is written once
+    fn run(cache: &mut HandleCache<PathBuf, ()>) {
+        cache.put(PathBuf::from("quiet/important.log"), ());
+        for round in 0..3 {
+            for index in 0..4 {
+                let path = PathBuf::from(format!("active/{}.log", index));
+                if cache.get(&path).is_none() {
+                    cache.put(path, ());
+                }
+            }
+            assert_eq!(cache.len(), 3, "round {}", round);
+        }
+    }
+
+    #[test]
+    fn test_lru_evicts_quiet_handle() {
+        let mut cache = HandleCache::new(3);
+        run(&mut cache);
+        assert!(!cache.contains(&PathBuf::from("quiet/important.log")));
+    }
+
+    #[test]
+    fn test_fair_keeps_quiet_handle() {
+        let mut cache = HandleCache::fair(3, parent_dir);
+        run(&mut cache);
+        assert!(cache.contains(&PathBuf::from("quiet/important.log")));
+        // Only the active directory churns within its two handles
+        assert_eq!(cache.reopened(), 8);
+    }
+
+    #[test]
+    fn test_reopened_count() {
+        let mut cache = HandleCache::new(1);
+        cache.put(1, ());
+        cache.put(2, ());
+        cache.put(1, ());
+        assert_eq!(cache.reopened(), 1);
+        // A removed file opened again is not a reopen for the capacity
+        cache.pop(&1);
+        cache.put(1, ());
+        assert_eq!(cache.reopened(), 1);
+    }
+}
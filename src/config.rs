@@ -0,0 +1,1015 @@
+/*
+ * Copyright 2019 StoneDot (Hiroaki Goto)
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+
+use encoding_rs::Encoding;
+use notify::RecursiveMode;
+use regex::bytes::Regex;
+use serde::{Serialize, Serializer};
+
+use crate::error::RegtailError;
+use crate::filter::DEFAULT_INSPECT_BYTES;
+use crate::line::{parse_highlight, Alert, LineOptions, RateLimiter, DEFAULT_DELIMITER};
+use crate::merge::{Merger, DEFAULT_TIMESTAMP_REGEX, MERGE_WINDOW};
+use crate::theme::Theme;
+
+lazy_static! {
+    static ref CURRENT_DIR: PathBuf = PathBuf::from_str(".").unwrap();
+}
+
+const DEFAULT_LINES: u64 = 10;
+
+fn serialize_encoding<S: Serializer>(
+    encoding: &Option<&'static Encoding>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    encoding.map(Encoding::name).serialize(serializer)
+}
+
+// Durations are shown in seconds like the command line options
+fn serialize_seconds<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    duration
+        .map(|duration| duration.as_secs_f64())
+        .serialize(serializer)
+}
+
+fn serialize_theme<S: Serializer>(theme: &Theme, serializer: S) -> Result<S::Ok, S::Error> {
+    theme.name().serialize(serializer)
+}
+
+// Configuration of the watcher, independent of the command line parser.
+// Serialized as resolved from the options, the environment and the config file by --print-config.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    pub(crate) lines: u64,
+    // Patterns and the lines to show for the matched files, overriding lines
+    pub(crate) lines_config: Option<PathBuf>,
+    pub(crate) all: bool,
+    pub(crate) recursive: bool,
+    pub(crate) show_binary: bool,
+    pub(crate) inspect_bytes: usize,
+    pub(crate) inspect_full: bool,
+    pub(crate) hidden: bool,
+    pub(crate) reverse_sort: bool,
+    pub(crate) batch_initial: bool,
+    pub(crate) no_initial: bool,
+    pub(crate) rotation: bool,
+    pub(crate) follow_symlinks: bool,
+    pub(crate) container_logs: bool,
+    pub(crate) delimiter: u8,
+    pub(crate) strip_cr: bool,
+    pub(crate) crlf: bool,
+    pub(crate) strip_ansi: bool,
+    pub(crate) sanitize: bool,
+    pub(crate) squeeze: bool,
+    pub(crate) wrap: Option<usize>,
+    pub(crate) wrap_indent: usize,
+    pub(crate) grep: Option<String>,
+    pub(crate) before_context: usize,
+    pub(crate) after_context: usize,
+    pub(crate) count: bool,
+    pub(crate) raw: bool,
+    pub(crate) merge: bool,
+    pub(crate) timestamp_regex: Option<String>,
+    pub(crate) no_eol_fix: bool,
+    pub(crate) eof_marker: bool,
+    pub(crate) line_buffered: bool,
+    pub(crate) max_rate: Option<u64>,
+    pub(crate) bell: bool,
+    pub(crate) desktop_notification: bool,
+    pub(crate) exit_when_empty: bool,
+    pub(crate) once: bool,
+    pub(crate) max_output: Option<u64>,
+    pub(crate) max_scan_bytes: Option<u64>,
+    pub(crate) max_read_per_event: Option<u64>,
+    #[serde(serialize_with = "serialize_seconds")]
+    pub(crate) heartbeat: Option<Duration>,
+    pub(crate) quiet: bool,
+    pub(crate) verbose: bool,
+    pub(crate) watch_events: bool,
+    pub(crate) status: bool,
+    pub(crate) fair_cache: bool,
+    #[serde(serialize_with = "serialize_seconds")]
+    pub(crate) flush_interval: Option<Duration>,
+    #[serde(serialize_with = "serialize_seconds")]
+    pub(crate) interval: Option<Duration>,
+    pub(crate) poll: bool,
+    pub(crate) poll_on_error: bool,
+    #[serde(serialize_with = "serialize_seconds")]
+    pub(crate) self_heal: Option<Duration>,
+    pub(crate) reload_on_sighup: bool,
+    #[serde(serialize_with = "serialize_seconds")]
+    pub(crate) idle_timeout: Option<Duration>,
+    #[serde(serialize_with = "serialize_seconds")]
+    pub(crate) debounce: Option<Duration>,
+    pub(crate) depth: Option<usize>,
+    pub(crate) max_files: Option<usize>,
+    pub(crate) regex: Option<String>,
+    pub(crate) basename: bool,
+    pub(crate) full_match: bool,
+    pub(crate) follow_renamed: bool,
+    pub(crate) regex_size_limit: Option<usize>,
+    pub(crate) extensions: Vec<String>,
+    pub(crate) content_match: Option<String>,
+    // User name or uid of the followed files
+    pub(crate) owner: Option<String>,
+    // Permission bits which the followed files must all have
+    pub(crate) mode: Option<u32>,
+    pub(crate) ignore_case: bool,
+    pub(crate) path: Option<PathBuf>,
+    // Directories expanded from a glob, the first one is also the path
+    pub(crate) paths: Vec<PathBuf>,
+    pub(crate) stdin: bool,
+    pub(crate) tee: Option<PathBuf>,
+    // Start the tee file with the UTF-8 BOM
+    pub(crate) bom: bool,
+    pub(crate) snapshot: Option<PathBuf>,
+    pub(crate) socket: Option<PathBuf>,
+    pub(crate) state_file: Option<PathBuf>,
+    pub(crate) accept_unknown_host: bool,
+    #[serde(serialize_with = "serialize_encoding")]
+    pub(crate) encoding: Option<&'static Encoding>,
+    pub(crate) decompress: bool,
+    pub(crate) colorize: bool,
+    pub(crate) highlights: Vec<String>,
+    pub(crate) multicolor: bool,
+    #[serde(serialize_with = "serialize_theme")]
+    pub(crate) theme: Theme,
+    pub(crate) separator: Option<String>,
+    pub(crate) absolute_paths: bool,
+    pub(crate) dereference: bool,
+    pub(crate) relative_to: Option<PathBuf>,
+    pub(crate) truncate_header: Option<usize>,
+    pub(crate) strip_prefix: Option<PathBuf>,
+    pub(crate) header_meta: bool,
+    pub(crate) hyperlinks: bool,
+    pub(crate) tag: bool,
+    pub(crate) prefix: bool,
+    pub(crate) combined_lines: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            lines: DEFAULT_LINES,
+            lines_config: None,
+            all: false,
+            recursive: false,
+            show_binary: false,
+            inspect_bytes: DEFAULT_INSPECT_BYTES,
+            inspect_full: false,
+            hidden: false,
+            reverse_sort: false,
+            batch_initial: false,
+            no_initial: false,
+            rotation: false,
+            follow_symlinks: false,
+            container_logs: false,
+            delimiter: DEFAULT_DELIMITER,
+            strip_cr: false,
+            crlf: false,
+            strip_ansi: false,
+            sanitize: false,
+            squeeze: false,
+            wrap: None,
+            wrap_indent: 0,
+            grep: None,
+            before_context: 0,
+            after_context: 0,
+            count: false,
+            raw: false,
+            merge: false,
+            timestamp_regex: None,
+            no_eol_fix: false,
+            eof_marker: false,
+            line_buffered: false,
+            max_rate: None,
+            bell: false,
+            desktop_notification: false,
+            exit_when_empty: false,
+            once: false,
+            max_output: None,
+            max_scan_bytes: None,
+            max_read_per_event: None,
+            heartbeat: None,
+            quiet: false,
+            verbose: false,
+            watch_events: false,
+            status: false,
+            fair_cache: false,
+            flush_interval: None,
+            interval: None,
+            poll: false,
+            poll_on_error: false,
+            self_heal: None,
+            reload_on_sighup: false,
+            idle_timeout: None,
+            debounce: None,
+            depth: None,
+            max_files: None,
+            regex: None,
+            basename: false,
+            full_match: false,
+            follow_renamed: false,
+            regex_size_limit: None,
+            extensions: Vec::new(),
+            content_match: None,
+            owner: None,
+            mode: None,
+            ignore_case: false,
+            path: None,
+            paths: Vec::new(),
+            stdin: false,
+            tee: None,
+            bom: false,
+            snapshot: None,
+            socket: None,
+            state_file: None,
+            accept_unknown_host: false,
+            encoding: None,
+            decompress: false,
+            colorize: false,
+            highlights: Vec::new(),
+            multicolor: false,
+            theme: Theme::default(),
+            separator: None,
+            absolute_paths: false,
+            dereference: false,
+            relative_to: None,
+            truncate_header: None,
+            strip_prefix: None,
+            header_meta: false,
+            hyperlinks: false,
+            tag: false,
+            prefix: false,
+            combined_lines: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    pub fn recursive_mode(self: &Config) -> RecursiveMode {
+        if self.recursive && self.watch_path_is_dir() {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        }
+    }
+
+    pub fn is_stdin(self: &Config) -> bool {
+        self.stdin
+    }
+
+    // File to mirror the output
+    pub fn tee_path(self: &Config) -> Option<&PathBuf> {
+        self.tee.as_ref()
+    }
+
+    pub fn tee_bom(self: &Config) -> bool {
+        self.bom
+    }
+
+    // Unix socket to send the lines to
+    pub fn socket_path(self: &Config) -> Option<&PathBuf> {
+        self.socket.as_ref()
+    }
+
+    pub fn accept_unknown_host(self: &Config) -> bool {
+        self.accept_unknown_host
+    }
+
+    pub fn watch_path(self: &Config) -> &PathBuf {
+        self.path.as_ref().unwrap_or(&CURRENT_DIR)
+    }
+
+    pub fn watch_path_is_dir(self: &Config) -> bool {
+        self.watch_path().is_dir()
+    }
+
+    // Directory to be watched, the parent directory if a single file is supplied
+    pub fn watch_dir(self: &Config) -> PathBuf {
+        let watch_path = self.watch_path();
+        if self.watch_path_is_dir() {
+            return watch_path.to_owned();
+        }
+        match watch_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+            _ => CURRENT_DIR.to_owned(),
+        }
+    }
+
+    // Supplied paths to walk on startup
+    pub fn watch_paths(self: &Config) -> Vec<PathBuf> {
+        if self.paths.is_empty() {
+            vec![self.watch_path().to_owned()]
+        } else {
+            self.paths.clone()
+        }
+    }
+
+    // Directories to be watched, multiple if a glob is expanded
+    pub fn watch_dirs(self: &Config) -> Vec<PathBuf> {
+        if self.paths.is_empty() {
+            vec![self.watch_dir()]
+        } else {
+            self.paths.clone()
+        }
+    }
+
+    pub fn line_options(self: &Config) -> Result<LineOptions, RegtailError> {
+        let grep = match &self.grep {
+            Some(pattern) => Some(
+                Regex::new(pattern)
+                    .map_err(|error| RegtailError::InvalidGrep(error.to_string()))?,
+            ),
+            None => None,
+        };
+        let mut highlights = Vec::new();
+        for spec in &self.highlights {
+            highlights.push(parse_highlight(spec).map_err(RegtailError::InvalidHighlight)?);
+        }
+        if !self.colorize {
+            highlights.clear();
+        }
+        let merger = if self.merge {
+            let pattern = self
+                .timestamp_regex
+                .as_deref()
+                .unwrap_or(DEFAULT_TIMESTAMP_REGEX);
+            let timestamp = Regex::new(pattern)
+                .map_err(|error| RegtailError::InvalidTimestampRegex(error.to_string()))?;
+            Some(Rc::new(RefCell::new(Merger::new(timestamp, MERGE_WINDOW))))
+        } else {
+            None
+        };
+        Ok(LineOptions {
+            delimiter: self.delimiter,
+            strip_cr: self.strip_cr,
+            crlf: self.crlf,
+            strip_ansi: self.strip_ansi,
+            sanitize: self.sanitize,
+            squeeze: self.squeeze,
+            wrap: self.wrap,
+            wrap_indent: self.wrap_indent,
+            grep,
+            before_context: self.before_context,
+            after_context: self.after_context,
+            count: self.count,
+            highlights,
+            encoding: self.encoding,
+            decompress: self.decompress,
+            max_scan_bytes: self.max_scan_bytes,
+            line_buffered: self.line_buffered,
+            tag: self.tag,
+            prefix: self.prefix,
+            rate_limiter: self
+                .max_rate
+                .map(|rate| Rc::new(RefCell::new(RateLimiter::new(rate)))),
+            alert: if self.bell || self.desktop_notification {
+                Some(Rc::new(RefCell::new(Alert::new(
+                    self.bell,
+                    self.desktop_notification,
+                ))))
+            } else {
+                None
+            },
+            merger,
+            tee_target: None,
+        })
+    }
+
+    // Lines to show on startup from all the files as a whole, None if per file
+    pub fn combined_lines(self: &Config) -> Option<u64> {
+        if self.count {
+            None
+        } else {
+            self.combined_lines
+        }
+    }
+
+    // Lines to show on startup, None if the entire content is shown
+    pub fn initial_lines(self: &Config) -> Option<u64> {
+        if self.count && self.once {
+            // Nothing is appended with --once, so the entire content is counted instead
+            None
+        } else if self.count {
+            // Only the lines appended after startup are counted
+            Some(0)
+        } else if self.all {
+            None
+        } else {
+            Some(self.lines)
+        }
+    }
+
+    // Bytes to read for the binary detection, None if the entire file is read
+    pub fn inspect_limit(self: &Config) -> Option<usize> {
+        if self.inspect_full {
+            None
+        } else {
+            Some(self.inspect_bytes)
+        }
+    }
+
+    pub fn depth(self: &Config) -> Option<usize> {
+        if self.recursive {
+            self.depth
+        } else {
+            Some(1)
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    // Lines to show from the tail of each file on startup
+    pub fn lines(mut self, lines: u64) -> Self {
+        self.config.lines = lines;
+        self
+    }
+
+    // Show the entire content of each file on startup, which takes precedence over lines
+    pub fn all(mut self, all: bool) -> Self {
+        self.config.all = all;
+        self
+    }
+
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.config.recursive = recursive;
+        self
+    }
+
+    pub fn show_binary(mut self, show_binary: bool) -> Self {
+        self.config.show_binary = show_binary;
+        self
+    }
+
+    // Bytes from the beginning of each file to tell whether it is binary
+    pub fn inspect_bytes(mut self, inspect_bytes: usize) -> Self {
+        self.config.inspect_bytes = inspect_bytes;
+        self
+    }
+
+    // Search the entire file for the null byte to tell whether it is binary, overriding inspect_bytes
+    pub fn inspect_full(mut self, inspect_full: bool) -> Self {
+        self.config.inspect_full = inspect_full;
+        self
+    }
+
+    // Follow dotfiles and descend into dot directories, excluded by default
+    pub fn hidden(mut self, hidden: bool) -> Self {
+        self.config.hidden = hidden;
+        self
+    }
+
+    // Walk the directories in the descending order of the paths, which orders the initial output
+    pub fn reverse_sort(mut self, reverse_sort: bool) -> Self {
+        self.config.reverse_sort = reverse_sort;
+        self
+    }
+
+    pub fn batch_initial(mut self, batch_initial: bool) -> Self {
+        self.config.batch_initial = batch_initial;
+        self
+    }
+
+    // Start every file from its end without printing anything, not even the headers.
+    // Unlike lines(0), the offsets saved in the state file are ignored as well.
+    pub fn no_initial(mut self, no_initial: bool) -> Self {
+        self.config.no_initial = no_initial;
+        self
+    }
+
+    // Print the rotated files such as app.log.1 before app.log under the header of app.log
+    pub fn rotation(mut self, rotation: bool) -> Self {
+        self.config.rotation = rotation;
+        self
+    }
+
+    // Walk into the symbolic links to directories and follow the files through the links
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.config.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    // Chain the restarts and rotations of a container log such as 0.log and 1.log into one stream
+    // headed by its directory
+    pub fn container_logs(mut self, container_logs: bool) -> Self {
+        self.config.container_logs = container_logs;
+        self
+    }
+
+    // Byte to separate the content into lines
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.config.delimiter = delimiter;
+        self
+    }
+
+    // Remove the carriage return at the end of each line
+    pub fn strip_cr(mut self, strip_cr: bool) -> Self {
+        self.config.strip_cr = strip_cr;
+        self
+    }
+
+    // End each line with CRLF for Windows consumers
+    pub fn crlf(mut self, crlf: bool) -> Self {
+        self.config.crlf = crlf;
+        self
+    }
+
+    // Remove ANSI escape sequences contained in the content
+    pub fn strip_ansi(mut self, strip_ansi: bool) -> Self {
+        self.config.strip_ansi = strip_ansi;
+        self
+    }
+
+    // Replace the non-printable bytes, which also lets the binary-looking files be followed
+    pub fn sanitize(mut self, sanitize: bool) -> Self {
+        self.config.sanitize = sanitize;
+        self
+    }
+
+    pub fn squeeze(mut self, squeeze: bool) -> Self {
+        self.config.squeeze = squeeze;
+        self
+    }
+
+    // Hard wrap the lines longer than the columns, not wrapped if None
+    pub fn wrap(mut self, columns: Option<usize>) -> Self {
+        self.config.wrap = columns;
+        self
+    }
+
+    // Spaces before the continued lines of a wrapped line
+    pub fn wrap_indent(mut self, indent: usize) -> Self {
+        self.config.wrap_indent = indent;
+        self
+    }
+
+    // Show only the lines matching the pattern
+    pub fn grep<S: Into<String>>(mut self, pattern: S) -> Self {
+        self.config.grep = Some(pattern.into());
+        self
+    }
+
+    // Lines to show before each matched line, only effective with grep
+    pub fn before_context(mut self, lines: usize) -> Self {
+        self.config.before_context = lines;
+        self
+    }
+
+    // Lines to show after each matched line, only effective with grep
+    pub fn after_context(mut self, lines: usize) -> Self {
+        self.config.after_context = lines;
+        self
+    }
+
+    // Print the number of new lines of each file periodically instead of the lines
+    pub fn count(mut self, count: bool) -> Self {
+        self.config.count = count;
+        self
+    }
+
+    // Write the bytes of the files as they are, without the headers and the added line feeds
+    pub fn raw(mut self, raw: bool) -> Self {
+        self.config.raw = raw;
+        self
+    }
+
+    // Never end an incomplete line with a line feed when another file is shown
+    pub fn no_eol_fix(mut self, no_eol_fix: bool) -> Self {
+        self.config.no_eol_fix = no_eol_fix;
+        self
+    }
+
+    // Interleave the lines of all files in the order of their timestamps, without headers
+    pub fn merge(mut self, merge: bool) -> Self {
+        self.config.merge = merge;
+        self
+    }
+
+    // The timestamp to merge the lines by, the first capture group if any
+    pub fn timestamp_regex<S: Into<String>>(mut self, pattern: S) -> Self {
+        self.config.timestamp_regex = Some(pattern.into());
+        self
+    }
+
+    // Print a marker line like <== EOF path ==> when a followed file is removed or renamed away
+    pub fn eof_marker(mut self, eof_marker: bool) -> Self {
+        self.config.eof_marker = eof_marker;
+        self
+    }
+
+    // Flush after every line at the cost of a system call per line
+    pub fn line_buffered(mut self, line_buffered: bool) -> Self {
+        self.config.line_buffered = line_buffered;
+        self
+    }
+
+    // Ring the bell when a line matches the grep pattern
+    pub fn bell(mut self, bell: bool) -> Self {
+        self.config.bell = bell;
+        self
+    }
+
+    // Show a desktop notification when a line matches the grep pattern,
+    // only effective with the desktop-notification feature
+    pub fn desktop_notification(mut self, desktop_notification: bool) -> Self {
+        self.config.desktop_notification = desktop_notification;
+        self
+    }
+
+    pub fn max_rate(mut self, lines_per_sec: Option<u64>) -> Self {
+        self.config.max_rate = lines_per_sec;
+        self
+    }
+
+    // Stop following once all the followed files are removed
+    pub fn exit_when_empty(mut self, exit_when_empty: bool) -> Self {
+        self.config.exit_when_empty = exit_when_empty;
+        self
+    }
+
+    // Exit after the initial output without following the files
+    pub fn once(mut self, once: bool) -> Self {
+        self.config.once = once;
+        self
+    }
+
+    // Stop following once the total output reaches the bytes
+    pub fn max_output(mut self, max_output: Option<u64>) -> Self {
+        self.config.max_output = max_output;
+        self
+    }
+
+    // Scan backward for the initial lines up to the bytes, then start in the middle of the line
+    pub fn max_scan_bytes(mut self, max_scan_bytes: Option<u64>) -> Self {
+        self.config.max_scan_bytes = max_scan_bytes;
+        self
+    }
+
+    // Read at most the bytes of a file at once, then the other files are read before the rest
+    pub fn max_read_per_event(mut self, max_read_per_event: Option<u64>) -> Self {
+        self.config.max_read_per_event = max_read_per_event;
+        self
+    }
+
+    // Print a message to stderr when nothing is printed for the interval
+    pub fn heartbeat(mut self, heartbeat: Option<Duration>) -> Self {
+        self.config.heartbeat = heartbeat;
+        self
+    }
+
+    // Suppress informational messages on stderr such as the heartbeat
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.config.quiet = quiet;
+        self
+    }
+
+    // Explain on stderr why files are not followed
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.config.verbose = verbose;
+        self
+    }
+
+    // Log every event received from the watcher on stderr, including the ignored ones
+    pub fn watch_events(mut self, watch_events: bool) -> Self {
+        self.config.watch_events = watch_events;
+        self
+    }
+
+    // Show the number of followed files on stderr, updated in place
+    pub fn status(mut self, status: bool) -> Self {
+        self.config.status = status;
+        self
+    }
+
+    // Share the open file handles among the directories, instead of the least recently used
+    pub fn fair_cache(mut self, fair_cache: bool) -> Self {
+        self.config.fair_cache = fair_cache;
+        self
+    }
+
+    // Maximum time to hold the output in the buffer, zero flushes after every write
+    pub fn flush_interval(mut self, flush_interval: Option<Duration>) -> Self {
+        self.config.flush_interval = flush_interval;
+        self
+    }
+
+    // Detect changes by scanning the files periodically instead of the OS notification
+    pub fn poll(mut self, poll: bool) -> Self {
+        self.config.poll = poll;
+        self
+    }
+
+    // Fall back to polling if the OS notification is not available
+    pub fn poll_on_error(mut self, poll_on_error: bool) -> Self {
+        self.config.poll_on_error = poll_on_error;
+        self
+    }
+
+    // Watch again if no events arrive for the duration while the files are modified
+    pub fn self_heal(mut self, self_heal: Option<Duration>) -> Self {
+        self.config.self_heal = self_heal;
+        self
+    }
+
+    // Reopen the files and walk the directories again on SIGHUP, as sent after a rotation.
+    // Otherwise SIGHUP terminates regtail by default.
+    pub fn reload_on_sighup(mut self, reload_on_sighup: bool) -> Self {
+        self.config.reload_on_sighup = reload_on_sighup;
+        self
+    }
+
+    // Release the handle of a file with no writes for the duration, followed again on its next
+    // write from where it was left
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.config.idle_timeout = idle_timeout;
+        self
+    }
+
+    // Wakeup interval of the event loop for the periodic work such as pending deletes,
+    // one second if None
+    pub fn interval(mut self, interval: Option<Duration>) -> Self {
+        self.config.interval = interval;
+        self
+    }
+
+    // Merge the events of a file within the delay, the raw events are handled if None
+    pub fn debounce(mut self, debounce: Option<Duration>) -> Self {
+        self.config.debounce = debounce;
+        self
+    }
+
+    // Maximum recursive depth, only effective in recursive mode
+    pub fn depth(mut self, depth: Option<usize>) -> Self {
+        self.config.depth = depth;
+        self
+    }
+
+    // Follow at most this number of files, the others are skipped with a warning
+    pub fn max_files(mut self, max_files: Option<usize>) -> Self {
+        self.config.max_files = max_files;
+        self
+    }
+
+    // Regex to filter target files
+    pub fn regex<S: Into<String>>(mut self, regex: S) -> Self {
+        self.config.regex = Some(regex.into());
+        self
+    }
+
+    // Match the regex against the file name only instead of the whole path
+    pub fn basename(mut self, basename: bool) -> Self {
+        self.config.basename = basename;
+        self
+    }
+
+    // The regex must match the whole path, or the whole file name with basename
+    pub fn full_match(mut self, full_match: bool) -> Self {
+        self.config.full_match = full_match;
+        self
+    }
+
+    // Keep following a file renamed to a path not matching the regex
+    pub fn follow_renamed(mut self, follow_renamed: bool) -> Self {
+        self.config.follow_renamed = follow_renamed;
+        self
+    }
+
+    // Maximum size in bytes of the compiled regex, the regex crate default if None
+    pub fn regex_size_limit(mut self, size_limit: Option<usize>) -> Self {
+        self.config.regex_size_limit = size_limit;
+        self
+    }
+
+    // Follow only the files with one of the extensions, in addition to the regex
+    pub fn extensions<S: Into<String>>(mut self, extensions: Vec<S>) -> Self {
+        self.config.extensions = extensions.into_iter().map(Into::into).collect();
+        self
+    }
+
+    // Follow only the files whose first bytes match the pattern on startup.
+    // The skipped files, the empty ones included, are followed once they are written.
+    pub fn content_match<S: Into<String>>(mut self, pattern: S) -> Self {
+        self.config.content_match = Some(pattern.into());
+        self
+    }
+
+    // Follow only the files owned by the user name or uid, Unix only
+    pub fn owner<S: Into<String>>(mut self, owner: S) -> Self {
+        self.config.owner = Some(owner.into());
+        self
+    }
+
+    // Follow only the files having all the permission bits of the mask, Unix only
+    pub fn mode(mut self, mode: Option<u32>) -> Self {
+        self.config.mode = mode;
+        self
+    }
+
+    // Match the regex and the extensions case insensitively
+    pub fn ignore_case(mut self, ignore_case: bool) -> Self {
+        self.config.ignore_case = ignore_case;
+        self
+    }
+
+    // Target directory, the current directory is used if not supplied
+    pub fn path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config.path = Some(path.into());
+        self
+    }
+
+    // Multiple target directories, which replace the path
+    pub fn paths<P: Into<PathBuf>>(mut self, paths: Vec<P>) -> Self {
+        self.config.paths = paths.into_iter().map(Into::into).collect();
+        self.config.path = self.config.paths.first().cloned();
+        self
+    }
+
+    // Read the standard input instead of watching the path
+    pub fn stdin(mut self, stdin: bool) -> Self {
+        self.config.stdin = stdin;
+        self
+    }
+
+    // Also write the output to the file, where the headers are never colorized
+    pub fn tee<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config.tee = Some(path.into());
+        self
+    }
+
+    // Write the UTF-8 BOM once at the start of the tee file, for tools requiring it
+    pub fn bom(mut self, bom: bool) -> Self {
+        self.config.bom = bom;
+        self
+    }
+
+    // Write the initial lines of each file to its own file in the directory on startup
+    pub fn snapshot<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.config.snapshot = Some(dir.into());
+        self
+    }
+
+    // Also send each line as a length-prefixed JSON message to the clients of the Unix socket
+    pub fn socket<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config.socket = Some(path.into());
+        self
+    }
+
+    // Transcode the content from the encoding to UTF-8, instead of detecting text files
+    pub fn encoding(mut self, encoding: &'static Encoding) -> Self {
+        self.config.encoding = Some(encoding);
+        self
+    }
+
+    // Follow .xz and .bz2 files as their decompressed content
+    pub fn decompress(mut self, decompress: bool) -> Self {
+        self.config.decompress = decompress;
+        self
+    }
+
+    // Vary the lines shown on startup by the file, falling back to lines
+    pub fn lines_config<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config.lines_config = Some(path.into());
+        self
+    }
+
+    // Persist the offsets read so far to resume from them after a restart
+    pub fn state_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config.state_file = Some(path.into());
+        self
+    }
+
+    // Connect to an SFTP host whose key is not in ~/.ssh/known_hosts yet.
+    // A key which differs from the known one is rejected regardless.
+    pub fn accept_unknown_host(mut self, accept_unknown_host: bool) -> Self {
+        self.config.accept_unknown_host = accept_unknown_host;
+        self
+    }
+
+    pub fn colorize(mut self, colorize: bool) -> Self {
+        self.config.colorize = colorize;
+        self
+    }
+
+    // Color the matches of PATTERN=COLOR rules in the content, only effective if colorized
+    pub fn highlights<S: Into<String>>(mut self, highlights: Vec<S>) -> Self {
+        self.config.highlights = highlights.into_iter().map(Into::into).collect();
+        self
+    }
+
+    // Color the header of each file differently, only effective if colorized
+    pub fn multicolor(mut self, multicolor: bool) -> Self {
+        self.config.multicolor = multicolor;
+        self
+    }
+
+    // Styles of the headers and the matches in them
+    pub fn theme(mut self, theme: Theme) -> Self {
+        self.config.theme = theme;
+        self
+    }
+
+    // Line printed between the output of different files, a blank line by default
+    pub fn separator<S: Into<String>>(mut self, separator: S) -> Self {
+        self.config.separator = Some(separator.into());
+        self
+    }
+
+    // Show the canonical absolute path in the headers
+    pub fn absolute_paths(mut self, absolute_paths: bool) -> Self {
+        self.config.absolute_paths = absolute_paths;
+        self
+    }
+
+    // Show the target of a symbolic link in the headers instead of the link. A target reached by
+    // several links is followed once either way, under the first link in the walk by default.
+    pub fn dereference(mut self, dereference: bool) -> Self {
+        self.config.dereference = dereference;
+        self
+    }
+
+    // Show the paths in the headers relative to the directory instead of the current directory
+    pub fn relative_to<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.config.relative_to = Some(dir.into());
+        self
+    }
+
+    // Show the last lines of all the files combined instead of per file,
+    // ordered by file then position since lines have no timestamps
+    pub fn combined_lines(mut self, combined_lines: Option<u64>) -> Self {
+        self.config.combined_lines = combined_lines;
+        self
+    }
+
+    // Show only the last characters of a longer path in the headers, preceded by an ellipsis
+    pub fn truncate_header(mut self, max_chars: Option<usize>) -> Self {
+        self.config.truncate_header = max_chars;
+        self
+    }
+
+    // Hide the leading directories of the paths in the headers, the regex still sees them
+    pub fn strip_prefix<P: Into<PathBuf>>(mut self, prefix: P) -> Self {
+        self.config.strip_prefix = Some(prefix.into());
+        self
+    }
+
+    // Show the size and the last modified time of each file in the headers
+    pub fn header_meta(mut self, header_meta: bool) -> Self {
+        self.config.header_meta = header_meta;
+        self
+    }
+
+    // Make the path in the headers a clickable link to the file, only effective if colorized
+    pub fn hyperlinks(mut self, hyperlinks: bool) -> Self {
+        self.config.hyperlinks = hyperlinks;
+        self
+    }
+
+    // Prefix each line and header with the shortest path suffix telling the file from the others
+    pub fn tag(mut self, tag: bool) -> Self {
+        self.config.tag = tag;
+        self
+    }
+
+    // Prefix each line with the path of its file as shown in the header, after the tag if any
+    pub fn prefix(mut self, prefix: bool) -> Self {
+        self.config.prefix = prefix;
+        self
+    }
+
+    pub fn build(self) -> Config {
+        self.config
+    }
+}
@@ -0,0 +1,278 @@
+/*
+ * Copyright 2019 StoneDot (Hiroaki Goto)
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+
+// Failures which prevent the files from being followed
+#[derive(Debug)]
+pub enum RegtailError {
+    // The regex to filter the file paths cannot be compiled
+    InvalidRegex(String),
+    // The compiled regex exceeds --regex-size-limit
+    RegexTooBig(usize),
+    InvalidGrep(String),
+    InvalidContentMatch(String),
+    InvalidHighlight(String),
+    InvalidTimestampRegex(String),
+    // The user of --owner does not exist
+    UnknownOwner(String),
+    // The supplied path is neither a directory nor a file
+    PathNotFound(PathBuf),
+    NotDirectory(PathBuf),
+    UnresolvablePath(io::Error),
+    InvalidStateFile(String),
+    InvalidLinesConfig(String),
+    InvalidIgnoreFile(String),
+    // The options cannot be applied to an sftp:// path
+    UnsupportedRemoteOptions(String),
+    Io(io::Error),
+    Notify(notify::Error),
+}
+
+impl Display for RegtailError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            RegtailError::InvalidRegex(message) => {
+                write!(f, "invalid regex supplied:\n{}", message)
+            }
+            RegtailError::RegexTooBig(size) => write!(
+                f,
+                "too big regex: {}, raise the limit by --regex-size-limit",
+                size
+            ),
+            RegtailError::InvalidGrep(message) => {
+                write!(f, "invalid grep pattern supplied:\n{}", message)
+            }
+            RegtailError::InvalidContentMatch(message) => {
+                write!(f, "invalid content pattern supplied:\n{}", message)
+            }
+            RegtailError::InvalidHighlight(message) => {
+                write!(f, "invalid highlight supplied:\n{}", message)
+            }
+            RegtailError::InvalidTimestampRegex(message) => {
+                write!(f, "invalid timestamp regex supplied:\n{}", message)
+            }
+            RegtailError::UnknownOwner(owner) => write!(f, "unknown owner: {}", owner),
+            RegtailError::PathNotFound(path) => {
+                write!(f, "supplied path does not exist: {}", path.display())
+            }
+            RegtailError::NotDirectory(path) => write!(f, "not a directory: {}", path.display()),
+            RegtailError::UnresolvablePath(error) => {
+                write!(f, "cannot resolve the supplied path: {}", error)
+            }
+            RegtailError::InvalidStateFile(message)
+            | RegtailError::InvalidLinesConfig(message)
+            | RegtailError::InvalidIgnoreFile(message) => write!(f, "{}", message),
+            RegtailError::UnsupportedRemoteOptions(options) => {
+                write!(f, "not supported with an sftp:// path: {}", options)
+            }
+            RegtailError::Io(error) => write!(f, "io error: {}", error),
+            RegtailError::Notify(notify::Error::Generic(message)) => {
+                write!(f, "generic error: {}", message)
+            }
+            RegtailError::Notify(notify::Error::Io(error)) => write!(f, "io error: {}", error),
+            RegtailError::Notify(notify::Error::PathNotFound) => write!(f, "path not found"),
+            RegtailError::Notify(notify::Error::WatchNotFound) => write!(f, "watch not found"),
+        }
+    }
+}
+
+impl Error for RegtailError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RegtailError::UnresolvablePath(error) | RegtailError::Io(error) => Some(error),
+            RegtailError::Notify(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RegtailError {
+    fn from(error: io::Error) -> Self {
+        RegtailError::Io(error)
+    }
+}
+
+impl From<notify::Error> for RegtailError {
+    fn from(error: notify::Error) -> Self {
+        RegtailError::Notify(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::watcher::DirectoryWatcher;
+    use std::fs;
+
+    fn create_test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("regtail-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn watcher_error(config: &Config) -> RegtailError {
+        match DirectoryWatcher::with_writer(config, Vec::new()) {
+            Ok(_) => panic!("the watcher must not be created"),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        let dir = create_test_dir("error-invalid-regex");
+        let config = Config::builder().path(&dir).regex("(").build();
+        match watcher_error(&config) {
+            RegtailError::InvalidRegex(message) => assert!(message.contains("unclosed group")),
+            error => panic!("unexpected error: {:?}", error),
+        }
+    }
+
+    #[test]
+    fn test_regex_too_big() {
+        let dir = create_test_dir("error-regex-too-big");
+        let config = Config::builder()
+            .path(&dir)
+            .regex(r"\w{100}")
+            .regex_size_limit(Some(1))
+            .build();
+        assert!(matches!(
+            watcher_error(&config),
+            RegtailError::RegexTooBig(_)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_grep() {
+        let dir = create_test_dir("error-invalid-grep");
+        let config = Config::builder().path(&dir).grep("[").build();
+        assert!(matches!(
+            watcher_error(&config),
+            RegtailError::InvalidGrep(_)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_content_match() {
+        let dir = create_test_dir("error-invalid-content-match");
+        let config = Config::builder().path(&dir).content_match("(").build();
+        assert!(matches!(
+            watcher_error(&config),
+            RegtailError::InvalidContentMatch(_)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_highlight() {
+        let dir = create_test_dir("error-invalid-highlight");
+        let config = Config::builder()
+            .path(&dir)
+            .highlights(vec!["ERROR=nocolor"])
+            .build();
+        assert!(matches!(
+            watcher_error(&config),
+            RegtailError::InvalidHighlight(_)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_timestamp_regex() {
+        let dir = create_test_dir("error-invalid-timestamp-regex");
+        let config = Config::builder()
+            .path(&dir)
+            .merge(true)
+            .timestamp_regex("(")
+            .build();
+        assert!(matches!(
+            watcher_error(&config),
+            RegtailError::InvalidTimestampRegex(_)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_ignore_file() {
+        let dir = create_test_dir("error-invalid-ignore-file");
+        fs::write(dir.join(".regtailignore"), "[*.log\n").unwrap();
+        let config = Config::builder().path(&dir).build();
+        match watcher_error(&config) {
+            RegtailError::InvalidIgnoreFile(message) => assert!(message.contains("line 1")),
+            error => panic!("unexpected error: {:?}", error),
+        }
+    }
+
+    #[test]
+    fn test_path_not_found() {
+        let dir = create_test_dir("error-path-not-found");
+        let missing = dir.join("missing");
+        let config = Config::builder().path(&missing).build();
+        match watcher_error(&config) {
+            RegtailError::PathNotFound(path) => assert_eq!(path, missing),
+            error => panic!("unexpected error: {:?}", error),
+        }
+    }
+
+    #[test]
+    fn test_not_directory() {
+        let dir = create_test_dir("error-not-directory");
+        let file = dir.join("file");
+        fs::write(&file, "").unwrap();
+        let config = Config::builder().path(&dir).relative_to(&file).build();
+        match watcher_error(&config) {
+            RegtailError::NotDirectory(path) => assert_eq!(path, file),
+            error => panic!("unexpected error: {:?}", error),
+        }
+    }
+
+    #[test]
+    fn test_relative_to_not_found() {
+        let dir = create_test_dir("error-relative-to-not-found");
+        let missing = dir.join("missing");
+        let config = Config::builder().path(&dir).relative_to(&missing).build();
+        match watcher_error(&config) {
+            RegtailError::PathNotFound(path) => assert_eq!(path, missing),
+            error => panic!("unexpected error: {:?}", error),
+        }
+    }
+
+    #[test]
+    fn test_invalid_state_file() {
+        let dir = create_test_dir("error-invalid-state-file");
+        let state_file = dir.join("state");
+        fs::write(&state_file, "broken").unwrap();
+        let config = Config::builder().path(&dir).state_file(&state_file).build();
+        assert!(matches!(
+            watcher_error(&config),
+            RegtailError::InvalidStateFile(_)
+        ));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            RegtailError::RegexTooBig(10).to_string(),
+            "too big regex: 10, raise the limit by --regex-size-limit"
+        );
+        assert_eq!(
+            RegtailError::Notify(notify::Error::PathNotFound).to_string(),
+            "path not found"
+        );
+    }
+}
@@ -0,0 +1,209 @@
+/*
+ * Copyright 2019 StoneDot (Hiroaki Goto)
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+const LOCAL_CONFIG_FILE: &str = "regtail.toml";
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    Auto,
+    Never,
+    Always,
+}
+
+impl ColorMode {
+    fn parse(value: &str) -> Option<ColorMode> {
+        match value {
+            "auto" => Some(ColorMode::Auto),
+            "never" => Some(ColorMode::Never),
+            "always" => Some(ColorMode::Always),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self: &ColorMode) -> &'static str {
+        match self {
+            ColorMode::Auto => "auto",
+            ColorMode::Never => "never",
+            ColorMode::Always => "always",
+        }
+    }
+}
+
+// Default options read from regtail.toml, overridden by the command line
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FileConfig {
+    pub lines: Option<u64>,
+    pub lines_config: Option<PathBuf>,
+    pub combined_lines: Option<u64>,
+    pub all: Option<bool>,
+    pub recursive: Option<bool>,
+    pub show_binary: Option<bool>,
+    pub inspect_bytes: Option<usize>,
+    pub inspect_full: Option<bool>,
+    pub hidden: Option<bool>,
+    pub reverse_sort: Option<bool>,
+    pub batch_initial: Option<bool>,
+    pub no_initial: Option<bool>,
+    pub rotation: Option<bool>,
+    pub follow_symlinks: Option<bool>,
+    pub container_logs: Option<bool>,
+    pub delimiter: Option<String>,
+    pub strip_cr: Option<bool>,
+    pub crlf: Option<bool>,
+    pub strip_ansi: Option<bool>,
+    pub sanitize: Option<bool>,
+    pub squeeze: Option<bool>,
+    pub wrap: Option<String>,
+    pub wrap_indent: Option<usize>,
+    pub grep: Option<String>,
+    pub before_context: Option<usize>,
+    pub after_context: Option<usize>,
+    pub count: Option<bool>,
+    pub raw: Option<bool>,
+    pub merge: Option<bool>,
+    pub timestamp_regex: Option<String>,
+    pub no_eol_fix: Option<bool>,
+    pub eof_marker: Option<bool>,
+    pub line_buffered: Option<bool>,
+    pub max_rate: Option<u64>,
+    pub bell: Option<bool>,
+    pub notify: Option<bool>,
+    pub exit_when_empty: Option<bool>,
+    pub once: Option<bool>,
+    pub max_output: Option<u64>,
+    pub max_scan_bytes: Option<u64>,
+    pub max_read_per_event: Option<u64>,
+    pub heartbeat: Option<u64>,
+    pub quiet: Option<bool>,
+    pub verbose: Option<bool>,
+    pub watch_events: Option<bool>,
+    pub status: Option<bool>,
+    pub fair_cache: Option<bool>,
+    pub flush_interval: Option<u64>,
+    pub interval: Option<u64>,
+    pub poll: Option<bool>,
+    pub poll_on_error: Option<bool>,
+    pub self_heal: Option<u64>,
+    pub reload_on_sighup: Option<bool>,
+    pub idle_timeout: Option<u64>,
+    pub debounce: Option<u64>,
+    pub depth: Option<usize>,
+    pub max_files: Option<usize>,
+    pub regex: Option<String>,
+    pub regex_size_limit: Option<usize>,
+    pub ext: Option<Vec<String>>,
+    pub content_match: Option<String>,
+    pub owner: Option<String>,
+    pub mode: Option<String>,
+    pub ignore_case: Option<bool>,
+    pub basename: Option<bool>,
+    pub full_match: Option<bool>,
+    pub follow_renamed: Option<bool>,
+    pub path: Option<PathBuf>,
+    pub tee: Option<PathBuf>,
+    pub bom: Option<bool>,
+    pub snapshot: Option<PathBuf>,
+    pub socket: Option<PathBuf>,
+    pub state_file: Option<PathBuf>,
+    pub accept_unknown_host: Option<bool>,
+    pub encoding: Option<String>,
+    pub decompress: Option<bool>,
+    pub color: Option<ColorMode>,
+    pub multicolor: Option<bool>,
+    pub theme: Option<String>,
+    pub highlight: Option<Vec<String>>,
+    pub separator: Option<String>,
+    pub absolute_paths: Option<bool>,
+    pub dereference: Option<bool>,
+    pub relative_to: Option<PathBuf>,
+    pub truncate_header: Option<usize>,
+    pub strip_prefix: Option<PathBuf>,
+    pub header_meta: Option<bool>,
+    pub hyperlinks: Option<bool>,
+    pub tag: Option<bool>,
+    pub prefix: Option<bool>,
+}
+
+impl FileConfig {
+    // Load the first config file found, or the empty config if there is none
+    pub fn load() -> Result<FileConfig, String> {
+        for path in Self::candidate_paths() {
+            match fs::read_to_string(&path) {
+                Ok(content) => {
+                    return toml::from_str(&content).map_err(|error| {
+                        format!("invalid config file {}: {}", path.display(), error)
+                    });
+                }
+                Err(error) if error.kind() == ErrorKind::NotFound => continue,
+                Err(error) => {
+                    return Err(format!(
+                        "cannot read config file {}: {}",
+                        path.display(),
+                        error
+                    ));
+                }
+            }
+        }
+        Ok(FileConfig::default())
+    }
+
+    // Environment variables take precedence over the config file
+    pub fn override_with_env(self: &mut FileConfig) -> Result<(), String> {
+        if let Some(lines) = Self::env_var("REGTAIL_LINES") {
+            self.lines = Some(
+                lines
+                    .parse()
+                    .map_err(|_| format!("invalid REGTAIL_LINES: {}", lines))?,
+            );
+        }
+        if let Some(color) = Self::env_var("REGTAIL_COLOR") {
+            self.color =
+                Some(ColorMode::parse(&color).ok_or(format!("invalid REGTAIL_COLOR: {}", color))?);
+        }
+        if let Some(regex) = Self::env_var("REGTAIL_REGEX") {
+            self.regex = Some(regex);
+        }
+        if let Some(path) = env::var_os("REGTAIL_PATH").filter(|value| !value.is_empty()) {
+            self.path = Some(PathBuf::from(path));
+        }
+        Ok(())
+    }
+
+    fn env_var(name: &str) -> Option<String> {
+        env::var(name).ok().filter(|value| !value.is_empty())
+    }
+
+    fn candidate_paths() -> Vec<PathBuf> {
+        let mut paths = vec![PathBuf::from(LOCAL_CONFIG_FILE)];
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+        if let Some(config_home) = config_home {
+            paths.push(config_home.join("regtail").join("config.toml"));
+        }
+        paths
+    }
+}
@@ -14,38 +14,142 @@
  * limitations under the License.
  */
 
-use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{is_separator, Path, PathBuf};
 
-use ansi_term::Colour::{Blue, Green};
+use ansi_term::Style;
 use content_inspector::{inspect, ContentType};
-use regex::Regex;
+use encoding_rs::{DecoderResult, Encoding};
+use regex::{bytes, Regex, RegexBuilder};
 use walkdir::{DirEntry, WalkDir};
 
-use super::Opt;
-use std::fs::File;
-use std::io::Read;
+use crate::config::Config;
+use crate::error::RegtailError;
+use crate::ignore_file::IgnoreFile;
+use crate::tail::{is_fifo, Compression};
+use crate::theme::Theme;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
 use std::str::from_utf8;
 
-const MAX_BUFFER_SIZE: usize = 1024;
+pub const DEFAULT_INSPECT_BYTES: usize = 1024;
+// The rest of a fully inspected file is searched for the null byte in chunks of this size
+const SCAN_CHUNK_SIZE: usize = 64 * 1024;
 
 pub struct PathFilter {
     regex: Regex,
+    // Match the regex against the file name instead of the whole path
+    basename: bool,
     filter_binary: bool,
+    // Bytes read from the beginning of the files to tell the binary, unlimited if None
+    inspect_limit: Option<usize>,
+    // Tell which files are skipped and why
+    verbose: bool,
+    // Allowed extensions without the leading dot, lowercased if the case is ignored
+    extensions: Vec<String>,
+    ignore_case: bool,
+    // Canonical path of the file if a single file is supplied instead of a directory
+    target_file: Option<PathBuf>,
+    // Follow files and directories whose name starts with a dot
+    hidden: bool,
+    // Walk the directories in the descending order of the paths
+    reverse_sort: bool,
+    // Walk into the symbolic links to directories
+    follow_symlinks: bool,
+    // Watched directory as supplied and canonicalized, so that its own name is never checked
+    roots: Vec<PathBuf>,
+    // Watched directories whose .regtailignore is loaded, not for a single file
+    ignore_dirs: Vec<PathBuf>,
+    // The .regtailignore of each root, overriding the regex
+    ignores: Vec<(PathBuf, IgnoreFile)>,
+    // Overrides the detection of the text files
+    encoding: Option<&'static Encoding>,
+    // Accept the compressed files which are followed decompressed
+    decompress: bool,
+    // The matches in the headers are painted with the theme
+    theme: Theme,
+    // Searched in the inspected bytes of the files found on startup
+    content_match: Option<bytes::Regex>,
+    // Uid of the followed files
+    owner: Option<u32>,
+    // Permission bits which the followed files must all have
+    mode: Option<u32>,
 }
 
-fn is_text(path: &Path) -> bool {
-    let mut file = match File::open(path) {
-        Ok(file) => file,
-        Err(_) => return false,
-    };
-    let mut buf = [0u8; MAX_BUFFER_SIZE];
-    let inspect_buf = match file.read(&mut buf) {
-        Ok(size) => &buf[0..size],
-        Err(_) => return false,
-    };
+pub(crate) fn is_hidden_name(name: &OsStr) -> bool {
+    name.to_string_lossy().starts_with('.')
+}
+
+// Content decodable by the encoding is text, the end of the buffer may split a character
+fn is_decodable(buf: &[u8], encoding: &'static Encoding) -> bool {
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut decoded = String::with_capacity(buf.len() * 3);
+    let (result, _) = decoder.decode_to_string_without_replacement(buf, &mut decoded, false);
+    !matches!(result, DecoderResult::Malformed(_, _))
+}
+
+// First bytes of the file up to the limit, which may be shorter, and the file positioned after them.
+// None if the file cannot be opened.
+fn read_sample(path: &Path, limit: usize) -> Option<io::Result<(Vec<u8>, File)>> {
+    let file = File::open(path).ok()?;
+    let mut buf = Vec::new();
+    let mut sample = file.take(limit as u64);
+    Some(
+        sample
+            .read_to_end(&mut buf)
+            .map(|_| (buf, sample.into_inner())),
+    )
+}
+
+// Read chunk by chunk, so that a large file is not loaded at once. A read error counts as found.
+fn scan_null_byte(reader: &mut dyn Read) -> bool {
+    let mut chunk = vec![0u8; SCAN_CHUNK_SIZE];
+    loop {
+        match reader.read(&mut chunk) {
+            Ok(0) => return false,
+            Ok(size) if chunk[..size].contains(&0) => return true,
+            Ok(_) => {}
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return true,
+        }
+    }
+}
+
+// The null byte is searched only in the first KB by inspect, so the bytes after them are
+// searched only if --inspect-bytes or --inspect-full extends the inspected range
+fn contains_null_byte(inspect_buf: &[u8], rest: Option<&mut dyn Read>) -> bool {
+    if inspect_buf.len() > DEFAULT_INSPECT_BYTES
+        && inspect_buf[DEFAULT_INSPECT_BYTES..].contains(&0)
+    {
+        return true;
+    }
+    match rest {
+        Some(reader) => scan_null_byte(reader),
+        None => false,
+    }
+}
+
+// The rest is the unread part of the file, searched only for the null byte
+fn is_text(
+    path: &Path,
+    inspect_buf: &[u8],
+    rest: Option<&mut dyn Read>,
+    encoding: Option<&'static Encoding>,
+    decompress: bool,
+) -> bool {
+    if decompress {
+        if let Some(compression) = Compression::from_path(path) {
+            return compression.has_magic(inspect_buf);
+        }
+    }
+    if let Some(encoding) = encoding {
+        return is_decodable(inspect_buf, encoding);
+    }
     let file_type = inspect(inspect_buf);
     match file_type {
         ContentType::BINARY => false,
+        ContentType::UTF_8 if contains_null_byte(inspect_buf, rest) => false,
         ContentType::UTF_8 | ContentType::UTF_8_BOM => match from_utf8(inspect_buf) {
             Ok(_) => true,
             Err(e) => e.error_len().is_none(),
@@ -60,91 +164,617 @@ fn is_text(path: &Path) -> bool {
     }
 }
 
+// Told like `cannot access logs/private: permission denied`
+fn report_walk_error(error: &walkdir::Error) {
+    let path = error.path().unwrap_or_else(|| Path::new(""));
+    match error.io_error() {
+        Some(io_error) => eprintln!("cannot access {}: {}", path.display(), io_error.kind()),
+        // A symbolic link loop
+        None => eprintln!("cannot access {}: {}", path.display(), error),
+    }
+}
+
+// Suffixes of the compressed rotated files, stripped before the number
+const COMPRESSED_SUFFIXES: [&str; 3] = [".gz", ".xz", ".bz2"];
+
+// Returns the path the file was rotated from and its number, e.g. app.log and 2 for app.log.2.gz
+fn rotation_of(path: &Path) -> Option<(PathBuf, u64)> {
+    let name = path.file_name()?.to_str()?;
+    let name = COMPRESSED_SUFFIXES
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .unwrap_or(name);
+    let dot = name.rfind('.')?;
+    let (base, number) = (&name[..dot], &name[dot + 1..]);
+    if base.is_empty() || number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((path.with_file_name(base), number.parse().ok()?))
+}
+
+// Restart count and rotation suffix of a container log, e.g. 1 and 20240101-000000 for
+// 1.log.20240101-000000, as the kubelet names them
+fn container_log_of(path: &Path) -> Option<(u64, Option<String>)> {
+    let name = path.file_name()?.to_str()?;
+    let name = COMPRESSED_SUFFIXES
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .unwrap_or(name);
+    let (restarts, rotated) = match name.find(".log") {
+        Some(index) => (&name[..index], &name[index + ".log".len()..]),
+        None => return None,
+    };
+    if restarts.is_empty() || !restarts.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let rotated = match rotated.strip_prefix('.') {
+        Some(suffix) if !suffix.is_empty() => Some(suffix.to_owned()),
+        Some(_) => return None,
+        None if rotated.is_empty() => None,
+        None => return None,
+    };
+    Some((restarts.parse().ok()?, rotated))
+}
+
+// The logical stream of a container log is its directory, whatever the restart count is
+pub fn container_stream_of(path: &Path) -> Option<&Path> {
+    container_log_of(path)?;
+    path.parent()
+}
+
+// Group the container logs by their directory, ordered by the restart count and then by the
+// rotation, the live file being the last of a restart. Other files are grouped by rotation.
+pub fn group_container_logs(paths: Vec<PathBuf>) -> Vec<Vec<PathBuf>> {
+    let mut groups: Vec<Vec<(u64, Option<String>, PathBuf)>> = Vec::new();
+    let mut group_index: HashMap<PathBuf, usize> = HashMap::new();
+    let mut others = Vec::new();
+    for path in paths {
+        let (stream, (restarts, rotated)) =
+            match (container_stream_of(&path), container_log_of(&path)) {
+                (Some(stream), Some(log)) => (stream.to_owned(), log),
+                _ => {
+                    others.push(path);
+                    continue;
+                }
+            };
+        let index = *group_index.entry(stream).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[index].push((restarts, rotated, path));
+    }
+    groups
+        .into_iter()
+        .map(|mut group| {
+            group.sort_by(|(l_restarts, l_rotated, _), (r_restarts, r_rotated, _)| {
+                (l_restarts, l_rotated.is_none(), l_rotated).cmp(&(
+                    r_restarts,
+                    r_rotated.is_none(),
+                    r_rotated,
+                ))
+            });
+            group.into_iter().map(|(_, _, path)| path).collect()
+        })
+        .chain(group_rotated(others))
+        .collect()
+}
+
+// Group the rotated files with the file they were rotated from, in the order of the paths.
+// Each group is ordered from the oldest, so the current file is the last one.
+pub fn group_rotated(paths: Vec<PathBuf>) -> Vec<Vec<PathBuf>> {
+    let mut groups: Vec<Vec<(u64, PathBuf)>> = Vec::new();
+    let mut group_index: HashMap<PathBuf, usize> = HashMap::new();
+    for path in paths {
+        let (base, number) = rotation_of(&path).unwrap_or_else(|| (path.to_owned(), 0));
+        let index = *group_index.entry(base).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[index].push((number, path));
+    }
+    groups
+        .into_iter()
+        .map(|mut group| {
+            group.sort_by(|(l, _), (r, _)| r.cmp(l));
+            group.into_iter().map(|(_, path)| path).collect()
+        })
+        .collect()
+}
+
+// Uid of the user name, or the uid itself
+#[cfg(unix)]
+fn resolve_owner(owner: &str) -> Option<u32> {
+    if let Ok(uid) = owner.parse() {
+        return Some(uid);
+    }
+    let name = std::ffi::CString::new(owner).ok()?;
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if passwd.is_null() {
+        None
+    } else {
+        Some(unsafe { (*passwd).pw_uid })
+    }
+}
+
+#[cfg(not(unix))]
+fn resolve_owner(owner: &str) -> Option<u32> {
+    owner.parse().ok()
+}
+
 impl PathFilter {
-    pub fn new(opt: &Opt) -> Result<PathFilter, i32> {
+    pub fn new(config: &Config) -> Result<PathFilter, RegtailError> {
         // Create regex filter
-        let regex = match Self::generate_filter_regex(&opt) {
-            Ok(regex) => regex,
-            Err(error) => match error {
-                regex::Error::Syntax(message) => {
-                    eprintln!("invalid regex supplied:\n{}", message);
-                    return Err(1);
-                }
-                regex::Error::CompiledTooBig(size) => {
-                    eprintln!("too big regex: {}", size);
-                    return Err(1);
-                }
-                regex::Error::__Nonexhaustive => {
-                    eprintln!("unexpected regex supplied");
-                    return Err(1);
-                }
-            },
+        let regex = Self::generate_filter_regex(&config).map_err(|error| match error {
+            regex::Error::CompiledTooBig(size) => RegtailError::RegexTooBig(size),
+            regex::Error::Syntax(message) => RegtailError::InvalidRegex(message),
+            error => RegtailError::InvalidRegex(error.to_string()),
+        })?;
+
+        let target_file = if config.watch_path_is_dir() {
+            None
+        } else {
+            let path = config
+                .watch_path()
+                .canonicalize()
+                .map_err(RegtailError::UnresolvablePath)?;
+            Some(path)
+        };
+
+        let content_match = match &config.content_match {
+            Some(pattern) => Some(
+                bytes::Regex::new(pattern)
+                    .map_err(|error| RegtailError::InvalidContentMatch(error.to_string()))?,
+            ),
+            None => None,
+        };
+
+        let owner = match &config.owner {
+            Some(owner) => Some(
+                resolve_owner(owner).ok_or_else(|| RegtailError::UnknownOwner(owner.clone()))?,
+            ),
+            None => None,
+        };
+
+        let mut roots = Vec::new();
+        for watch_dir in config.watch_dirs() {
+            if let Ok(canonical) = watch_dir.canonicalize() {
+                roots.push(canonical);
+            }
+            roots.push(watch_dir);
+        }
+
+        // An explicitly supplied file is followed whatever its directory ignores
+        let ignore_dirs = if target_file.is_none() {
+            config.watch_dirs()
+        } else {
+            Vec::new()
         };
+        let ignores = Self::load_ignores(&ignore_dirs).map_err(RegtailError::InvalidIgnoreFile)?;
 
         Ok(PathFilter {
             regex,
-            filter_binary: !opt.show_binary,
+            basename: config.basename,
+            // The sanitized content is safe to show
+            filter_binary: !config.show_binary && !config.sanitize,
+            inspect_limit: config.inspect_limit(),
+            verbose: config.verbose,
+            extensions: config
+                .extensions
+                .iter()
+                .map(|extension| {
+                    let extension = extension.trim_start_matches('.');
+                    if config.ignore_case {
+                        extension.to_lowercase()
+                    } else {
+                        extension.to_owned()
+                    }
+                })
+                .collect(),
+            ignore_case: config.ignore_case,
+            target_file,
+            hidden: config.hidden,
+            reverse_sort: config.reverse_sort,
+            follow_symlinks: config.follow_symlinks,
+            roots,
+            ignore_dirs,
+            ignores,
+            encoding: config.encoding,
+            decompress: config.decompress,
+            theme: config.theme,
+            content_match,
+            owner,
+            mode: config.mode,
         })
     }
 
-    fn generate_filter_regex(opt: &Opt) -> Result<Regex, regex::Error> {
-        match &opt.regex {
-            Some(regex) => Regex::new(regex),
-            None => Regex::new(".*"),
+    pub(crate) fn generate_filter_regex(config: &Config) -> Result<Regex, regex::Error> {
+        let pattern = config.regex.as_deref().unwrap_or(".*");
+        let pattern = if config.full_match {
+            format!("^(?:{})$", pattern)
+        } else {
+            pattern.to_owned()
+        };
+        let mut builder = RegexBuilder::new(&pattern);
+        builder.case_insensitive(config.ignore_case);
+        if let Some(size_limit) = config.regex_size_limit {
+            builder.size_limit(size_limit);
         }
+        builder.build()
+    }
+
+    // The .regtailignore of each watched directory, keyed by the directory as supplied and
+    // canonicalized
+    fn load_ignores(dirs: &[PathBuf]) -> Result<Vec<(PathBuf, IgnoreFile)>, String> {
+        let mut ignores = Vec::new();
+        for dir in dirs {
+            let ignore = IgnoreFile::load(dir)?;
+            if ignore.is_empty() {
+                continue;
+            }
+            if let Ok(canonical) = dir.canonicalize() {
+                ignores.push((canonical, ignore.clone()));
+            }
+            ignores.push((dir.to_owned(), ignore));
+        }
+        Ok(ignores)
+    }
+
+    // Read the .regtailignore files again, the previous rules are kept if any of them is invalid
+    pub fn reload_ignores(self: &mut PathFilter) {
+        match Self::load_ignores(&self.ignore_dirs) {
+            Ok(ignores) => self.ignores = ignores,
+            Err(message) => eprintln!("{}", message),
+        }
+    }
+
+    fn is_ignored(self: &PathFilter, path: &Path) -> bool {
+        let ignored = self
+            .ignores
+            .iter()
+            .any(|(root, ignore)| match path.strip_prefix(root) {
+                Ok(relative_path) => ignore.is_ignored(relative_path),
+                Err(_) => false,
+            });
+        if ignored && self.verbose {
+            eprintln!(
+                "skipping file ignored by .regtailignore: {}",
+                path.display()
+            );
+        }
+        ignored
     }
 
     pub fn match_path(self: &PathFilter, path: &Path) -> bool {
-        match path.to_str() {
-            Some(path_str) => self.regex.is_match(path_str),
+        if let Some(target_file) = &self.target_file {
+            // Events of the siblings are also delivered because the parent directory is watched
+            let is_target =
+                path == target_file || path.canonicalize().ok().as_ref() == Some(target_file);
+            if !is_target {
+                return false;
+            }
+        } else if (!self.hidden && self.is_hidden(path)) || self.is_ignored(path) {
+            return false;
+        }
+        if !self.match_extension(path) {
+            return false;
+        }
+        if !self.match_metadata(path) {
+            return false;
+        }
+        let target = if self.basename {
+            path.file_name().and_then(OsStr::to_str)
+        } else {
+            path.to_str()
+        };
+        match target {
+            Some(target) => self.regex.is_match(target),
             None => false,
         }
     }
 
+    // Compared with the end of the file name, so that an extension like tar.gz also matches
+    fn match_extension(self: &PathFilter, path: &Path) -> bool {
+        if self.extensions.is_empty() {
+            return true;
+        }
+        let file_name = match path.file_name() {
+            Some(file_name) => file_name.to_string_lossy(),
+            None => return false,
+        };
+        let file_name = if self.ignore_case {
+            file_name.to_lowercase()
+        } else {
+            file_name.into_owned()
+        };
+        self.extensions.iter().any(|extension| {
+            file_name.len() > extension.len()
+                && file_name.ends_with(extension.as_str())
+                && file_name[..file_name.len() - extension.len()].ends_with('.')
+        })
+    }
+
+    // The owner and the permission bits. A removed file matches, so that its removal is handled.
+    #[cfg(unix)]
+    fn match_metadata(self: &PathFilter, path: &Path) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        if self.owner.is_none() && self.mode.is_none() {
+            return true;
+        }
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => return true,
+        };
+        let owner_matched = match self.owner {
+            Some(owner) => metadata.uid() == owner,
+            None => true,
+        };
+        let mode_matched = match self.mode {
+            Some(mode) => metadata.mode() & mode == mode,
+            None => true,
+        };
+        let matched = owner_matched && mode_matched;
+        if !matched && self.verbose {
+            eprintln!(
+                "skipping file not matching the owner or the mode: {}",
+                path.display()
+            );
+        }
+        matched
+    }
+
+    #[cfg(not(unix))]
+    fn match_metadata(self: &PathFilter, _path: &Path) -> bool {
+        true
+    }
+
+    // Path below the watched directory, None if the path is not under it
+    pub fn relative_path<'a>(self: &PathFilter, path: &'a Path) -> Option<&'a Path> {
+        self.roots
+            .iter()
+            .find_map(|root| path.strip_prefix(root).ok())
+    }
+
+    // Whether any component below the watched directory starts with a dot
+    fn is_hidden(self: &PathFilter, path: &Path) -> bool {
+        match self.relative_path(path) {
+            Some(relative) => relative.iter().any(is_hidden_name),
+            None => match path.file_name() {
+                Some(name) => is_hidden_name(name),
+                None => false,
+            },
+        }
+    }
+
     pub fn filtered_files<'a>(
         self: &'a PathFilter,
-        opt: &Opt,
+        config: &Config,
     ) -> impl Iterator<Item = std::path::PathBuf> + 'a {
-        let walk_path = opt.watch_path();
-        let depth = opt.depth();
-        let walker = WalkDir::new(&walk_path).sort_by(|l, r| l.path().cmp(r.path()));
+        let depth = config.depth();
+        config
+            .watch_paths()
+            .into_iter()
+            .flat_map(move |walk_path| self.files_under(&walk_path, depth))
+    }
+
+    // Matched files under the directory, depth is relative to the directory
+    pub fn files_under<'a>(
+        self: &'a PathFilter,
+        walk_path: &Path,
+        depth: Option<usize>,
+    ) -> impl Iterator<Item = std::path::PathBuf> + 'a {
+        let reverse_sort = self.reverse_sort;
+        let walker = WalkDir::new(&walk_path)
+            .follow_links(self.follow_symlinks)
+            .sort_by(move |l, r| {
+                if reverse_sort {
+                    r.path().cmp(l.path())
+                } else {
+                    l.path().cmp(r.path())
+                }
+            });
         let walker = match depth {
             Some(depth) => walker.max_depth(depth),
             None => walker,
         };
+        let hidden = self.hidden;
+        let verbose = self.verbose;
         walker
             .into_iter()
-            .filter_map(|e| e.ok())
+            // Skipping a hidden directory prunes its whole subtree, the root is always walked
+            .filter_entry(move |e| hidden || e.depth() == 0 || !is_hidden_name(e.file_name()))
+            // An unreadable directory is skipped and the walk goes on with its siblings
+            .filter_map(move |e| match e {
+                Ok(e) => Some(e),
+                Err(error) => {
+                    if verbose {
+                        report_walk_error(&error);
+                    }
+                    None
+                }
+            })
             .filter_map(move |e: DirEntry| {
-                let path = e.path();
-                if !path.is_file() {
+                // The type read with the directory entry saves a stat for most files,
+                // while a symlink is resolved to its target
+                let fifo = if e.file_type().is_file() || e.path().is_file() {
+                    false
+                } else if is_fifo(e.path()) {
+                    true
+                } else {
                     return None;
-                }
-                if self.match_path(&path) {
-                    Some(path.to_owned())
+                };
+                if self.match_path(e.path()) {
+                    Some((e.into_path(), fifo))
                 } else {
                     None
                 }
             })
-            .filter(move |path: &PathBuf| {
+            .filter(move |(path, fifo)| {
+                // Reading a named pipe consumes its content
+                if (!self.filter_binary && self.content_match.is_none()) || *fifo {
+                    return true;
+                }
+                // With --inspect-full, the first KB is sampled and the rest is only scanned
+                let limit = self.inspect_limit.unwrap_or(DEFAULT_INSPECT_BYTES);
+                let mut sample = match read_sample(path, limit) {
+                    Some(sample) => sample,
+                    // Leave it to the tail, which reports the failure
+                    None => return true,
+                };
                 if self.filter_binary {
-                    is_text(path)
-                } else {
-                    true
+                    let text = match &mut sample {
+                        Ok((sample, file)) => {
+                            let rest = match self.inspect_limit {
+                                Some(_) => None,
+                                None => Some(file as &mut dyn Read),
+                            };
+                            is_text(path, sample, rest, self.encoding, self.decompress)
+                        }
+                        Err(_) => false,
+                    };
+                    if !text {
+                        if self.verbose {
+                            eprintln!("skipping binary file: {}", path.display());
+                        }
+                        return false;
+                    }
                 }
+                if let Some(content_match) = &self.content_match {
+                    if !matches!(&sample, Ok((sample, _)) if content_match.is_match(sample)) {
+                        if self.verbose {
+                            eprintln!("skipping file not matching the content: {}", path.display());
+                        }
+                        return false;
+                    }
+                }
+                true
             })
+            .map(|(path, _)| path)
     }
 
-    pub fn print_path_with_color(&self, path: &str) {
-        let mut prev_end_point = 0;
-        for m in self.regex.find_iter(path) {
-            let prev_str = &path[prev_end_point..m.start()];
-            print!("{}", Blue.bold().paint(prev_str));
-            print!("{}", Green.bold().paint(m.as_str()));
-            prev_end_point = m.end();
+    pub fn write_path_with_color<W: Write>(
+        &self,
+        writer: &mut W,
+        path: &str,
+        visible_from: usize,
+        style: Style,
+    ) -> io::Result<()> {
+        // The whole path is shown, but only the matches in the file name are colored
+        let match_start = if self.basename {
+            path.rfind(is_separator)
+                .map_or(0, |separator| separator + 1)
+        } else {
+            0
+        };
+        // The path before visible_from is not shown, even if it is a part of a match
+        let mut prev_end_point = visible_from;
+        for m in self.regex.find_iter(&path[match_start..]) {
+            let (start, end) = (match_start + m.start(), match_start + m.end());
+            if end <= visible_from {
+                continue;
+            }
+            let start = start.max(visible_from);
+            let prev_str = &path[prev_end_point..start];
+            write!(writer, "{}", style.paint(prev_str))?;
+            write!(writer, "{}", self.theme.path_match.paint(&path[start..end]))?;
+            prev_end_point = end;
         }
         let len = path.len();
         let last_str = &path[prev_end_point..len];
-        print!("{}", Blue.bold().paint(last_str));
+        write!(writer, "{}", style.paint(last_str))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{group_container_logs, group_rotated, is_text, PathFilter, SCAN_CHUNK_SIZE};
+    use crate::config::Config;
+
+    fn matches_file_name(regex: &str, full_match: bool, path: &str) -> bool {
+        let config = Config::builder()
+            .regex(regex)
+            .basename(true)
+            .full_match(full_match)
+            .build();
+        PathFilter::new(&config)
+            .unwrap()
+            .match_path(Path::new(path))
+    }
+
+    #[test]
+    fn test_full_match() {
+        assert!(matches_file_name("foo", false, "logs/foo"));
+        assert!(matches_file_name("foo", false, "logs/foobar.log"));
+        assert!(matches_file_name("foo", true, "logs/foo"));
+        assert!(!matches_file_name("foo", true, "logs/foobar.log"));
+        // An alternation is anchored as a whole
+        assert!(matches_file_name("foo|bar", true, "logs/bar"));
+        assert!(!matches_file_name("foo|bar", true, "logs/foobar"));
+    }
+
+    #[test]
+    fn test_group_rotated() {
+        let paths = vec![
+            PathBuf::from("logs/app.log"),
+            PathBuf::from("logs/app.log.1"),
+            PathBuf::from("logs/app.log.10.gz"),
+            PathBuf::from("logs/app.log.2"),
+            PathBuf::from("logs/other.log.1"),
+            PathBuf::from("logs/version.txt"),
+        ];
+        assert_eq!(
+            group_rotated(paths),
+            vec![
+                vec![
+                    PathBuf::from("logs/app.log.10.gz"),
+                    PathBuf::from("logs/app.log.2"),
+                    PathBuf::from("logs/app.log.1"),
+                    PathBuf::from("logs/app.log"),
+                ],
+                vec![PathBuf::from("logs/other.log.1")],
+                vec![PathBuf::from("logs/version.txt")],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_group_container_logs() {
+        let paths = vec![
+            PathBuf::from("pods/app/0.log"),
+            PathBuf::from("pods/app/0.log.20240102-000000"),
+            PathBuf::from("pods/app/0.log.20240101-000000.gz"),
+            PathBuf::from("pods/app/1.log"),
+            PathBuf::from("pods/sidecar/0.log"),
+            PathBuf::from("pods/app.log"),
+        ];
+        assert_eq!(
+            group_container_logs(paths),
+            vec![
+                vec![
+                    PathBuf::from("pods/app/0.log.20240101-000000.gz"),
+                    PathBuf::from("pods/app/0.log.20240102-000000"),
+                    PathBuf::from("pods/app/0.log"),
+                    PathBuf::from("pods/app/1.log"),
+                ],
+                vec![PathBuf::from("pods/sidecar/0.log")],
+                vec![PathBuf::from("pods/app.log")],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_null_byte_in_rest() {
+        let path = Path::new("app.log");
+        let sample = "text\n".repeat(200).into_bytes();
+        let mut rest = vec![b'a'; SCAN_CHUNK_SIZE + 10];
+        assert!(is_text(path, &sample, Some(&mut &rest[..]), None, false));
+        rest[SCAN_CHUNK_SIZE + 5] = 0;
+        assert!(!is_text(path, &sample, Some(&mut &rest[..]), None, false));
+        // The null byte after the first KB is not searched by default
+        let mut late = "text\n".repeat(300).into_bytes();
+        late.push(0);
+        assert!(is_text(path, &late[..1024], None, None, false));
+        assert!(!is_text(path, &late, None, None, false));
     }
 }
@@ -0,0 +1,122 @@
+/*
+ * Copyright 2019 StoneDot (Hiroaki Goto)
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use glob::{MatchOptions, Pattern};
+use regex::Regex;
+
+// Name of the file in a watched directory which lists the files not to be followed
+pub const IGNORE_FILE_NAME: &str = ".regtailignore";
+
+#[derive(Clone, Debug)]
+enum IgnoreRule {
+    Glob(Pattern),
+    Regex(Regex),
+}
+
+// Files excluded by the .regtailignore of a watched directory. Each line is a glob, matched with
+// the file name unless it contains a slash, or a regex prefixed by `regex:`. A glob with a slash
+// and a regex are matched with the path below the watched directory.
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreFile {
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreFile {
+    // A missing file ignores nothing
+    pub fn load(dir: &Path) -> Result<IgnoreFile, String> {
+        let path = dir.join(IGNORE_FILE_NAME);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(IgnoreFile::default()),
+            Err(error) => return Err(format!("cannot read {}: {}", path.display(), error)),
+        };
+        Self::parse(&content).map_err(|error| format!("invalid {}: {}", path.display(), error))
+    }
+
+    // Blank lines and the lines starting with # are ignored
+    pub fn parse(content: &str) -> Result<IgnoreFile, String> {
+        let mut rules = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = match line.strip_prefix("regex:") {
+                Some(pattern) => Regex::new(pattern.trim())
+                    .map(IgnoreRule::Regex)
+                    .map_err(|error| format!("line {}: {}", number + 1, error))?,
+                None => Pattern::new(line)
+                    .map(IgnoreRule::Glob)
+                    .map_err(|error| format!("line {}: {}", number + 1, error))?,
+            };
+            rules.push(rule);
+        }
+        Ok(IgnoreFile { rules })
+    }
+
+    pub fn is_empty(self: &IgnoreFile) -> bool {
+        self.rules.is_empty()
+    }
+
+    // The path is relative to the watched directory
+    pub fn is_ignored(self: &IgnoreFile, relative_path: &Path) -> bool {
+        // A wildcard does not match the separator, as in .gitignore
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        let file_name = relative_path.file_name().and_then(|name| name.to_str());
+        self.rules.iter().any(|rule| match rule {
+            IgnoreRule::Glob(pattern) if pattern.as_str().contains('/') => {
+                pattern.matches_path_with(relative_path, options)
+            }
+            IgnoreRule::Glob(pattern) => match file_name {
+                Some(file_name) => pattern.matches_with(file_name, options),
+                None => false,
+            },
+            IgnoreRule::Regex(regex) => regex.is_match(&relative_path.to_string_lossy()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::IgnoreFile;
+
+    #[test]
+    fn test_is_ignored() {
+        let ignore =
+            IgnoreFile::parse("# comment\n\n*.debug.log\narchive/*.log\nregex:^tmp/\n").unwrap();
+        assert!(ignore.is_ignored(Path::new("app.debug.log")));
+        assert!(ignore.is_ignored(Path::new("nested/app.debug.log")));
+        assert!(ignore.is_ignored(Path::new("archive/app.log")));
+        assert!(!ignore.is_ignored(Path::new("archive/nested/app.log")));
+        assert!(ignore.is_ignored(Path::new("tmp/app.log")));
+        assert!(!ignore.is_ignored(Path::new("app.log")));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert!(IgnoreFile::parse("[*.log").is_err());
+        assert!(IgnoreFile::parse("regex:access(.log").is_err());
+    }
+}
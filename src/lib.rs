@@ -1 +1,52 @@
+/*
+ * Copyright 2019 StoneDot (Hiroaki Goto)
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Regex base tail which follows every matched file in a directory.
+//!
+//! The watcher can be embedded without the command line interface:
+//!
+//! ```no_run
+//! use regtail::config::Config;
+//! use regtail::watcher::DirectoryWatcher;
+//!
+//! let config = Config::builder()
+//!     .path("/var/log")
+//!     .regex(r"\.log$")
+//!     .lines(20)
+//!     .build();
+//! let mut watcher = DirectoryWatcher::new(&config).expect("invalid configuration");
+//! watcher.follow_dir(&config).expect("failed to follow the directory");
+//! ```
+
+#[macro_use]
+extern crate lazy_static;
+
+pub mod cache;
+pub mod config;
+pub mod error;
+pub mod filter;
+pub mod ignore_file;
+pub mod line;
+pub mod lines_config;
+pub mod merge;
+#[cfg(feature = "sftp")]
+pub mod sftp;
+#[cfg(unix)]
+pub mod socket;
+pub mod state;
 pub mod tail;
+pub mod theme;
+pub mod watcher;
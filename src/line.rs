@@ -0,0 +1,1252 @@
+/*
+ * Copyright 2019 StoneDot (Hiroaki Goto)
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{Result, Write};
+use std::mem::take;
+use std::rc::Rc;
+use std::str::from_utf8;
+use std::time::{Duration, Instant};
+
+use ansi_term::Colour;
+use chrono::NaiveDateTime;
+use encoding_rs::Encoding;
+use regex::bytes::Regex;
+use unicode_width::UnicodeWidthStr;
+
+use super::merge::Merger;
+use super::tail::TeeTarget;
+
+pub const DEFAULT_DELIMITER: u8 = b'\n';
+const CR: u8 = b'\r';
+const LF: u8 = b'\n';
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+// Alerts for a flood of matches are merged into one
+const ALERT_INTERVAL: Duration = Duration::from_secs(1);
+// Printed between groups of matches which are not adjacent, as grep does
+const CONTEXT_SEPARATOR: &[u8] = b"--\n";
+
+#[derive(Clone)]
+pub struct LineOptions {
+    // Byte to separate the content into lines
+    pub delimiter: u8,
+    // Remove the carriage return preceding the delimiter
+    pub strip_cr: bool,
+    // End every output line with CRLF instead of LF
+    pub crlf: bool,
+    pub strip_ansi: bool,
+    // Replace the control characters and the invalid UTF-8 with U+FFFD
+    pub sanitize: bool,
+    pub squeeze: bool,
+    // Only lines matching the pattern are shown, with the surrounding context lines
+    pub grep: Option<Regex>,
+    pub before_context: usize,
+    pub after_context: usize,
+    // Count the lines instead of printing them
+    pub count: bool,
+    // Matches are colored with the first rule matching there, empty if not colorized
+    pub highlights: Vec<(Regex, Colour)>,
+    // Encoding of the files, which are transcoded to UTF-8
+    pub encoding: Option<&'static Encoding>,
+    // Read the compressed files as their decompressed content
+    pub decompress: bool,
+    // Bytes scanned backward for the initial lines at most, unlimited if None
+    pub max_scan_bytes: Option<u64>,
+    // Flush after every line, which costs a write system call per line
+    pub line_buffered: bool,
+    // Each line is prefixed with the tag of its file, set by LineProcessor::set_prefix
+    pub tag: bool,
+    // Each line is prefixed with the path of its file, set in the same way as the tag
+    pub prefix: bool,
+    // Shared by all files to bound the whole output
+    pub rate_limiter: Option<Rc<RefCell<RateLimiter>>>,
+    // Fired when a line matches the grep pattern, shared by all files
+    pub alert: Option<Rc<RefCell<Alert>>>,
+    // Holds the lines of all files to write them in the order of their timestamps
+    pub merger: Option<Rc<RefCell<Merger>>>,
+    // Hard wrap the lines longer than the columns, the continued lines indented by wrap_indent
+    pub wrap: Option<usize>,
+    pub wrap_indent: usize,
+    // Switch of the mirrored output, where the highlights and the bell are never written
+    pub tee_target: Option<Rc<Cell<TeeTarget>>>,
+}
+
+impl Default for LineOptions {
+    fn default() -> Self {
+        LineOptions {
+            delimiter: DEFAULT_DELIMITER,
+            strip_cr: false,
+            crlf: false,
+            strip_ansi: false,
+            sanitize: false,
+            squeeze: false,
+            grep: None,
+            before_context: 0,
+            after_context: 0,
+            count: false,
+            highlights: Vec::new(),
+            encoding: None,
+            decompress: false,
+            max_scan_bytes: None,
+            line_buffered: false,
+            tag: false,
+            prefix: false,
+            rate_limiter: None,
+            alert: None,
+            merger: None,
+            wrap: None,
+            wrap_indent: 0,
+            tee_target: None,
+        }
+    }
+}
+
+impl LineOptions {
+    // Returns true if the output has to be processed line by line
+    pub fn is_line_aware(&self) -> bool {
+        self.strip_cr
+            || self.crlf
+            || self.strip_ansi
+            || self.sanitize
+            || self.squeeze
+            || self.grep.is_some()
+            || self.count
+            || !self.highlights.is_empty()
+            || self.line_buffered
+            || self.tag
+            || self.prefix
+            || self.rate_limiter.is_some()
+            || self.alert.is_some()
+            || self.merger.is_some()
+            || self.wrap.is_some()
+    }
+
+    // The line as it is matched and printed, without the CR, the ANSI sequences or the control
+    // characters if they are removed
+    pub fn normalize<'a>(&self, line: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut line = Cow::Borrowed(line);
+        if self.strip_cr {
+            line = Cow::Owned(strip_cr(&line, self.delimiter));
+        }
+        if self.strip_ansi {
+            line = Cow::Owned(strip_ansi(&line));
+        }
+        if self.sanitize {
+            line = Cow::Owned(sanitize(&line, self.delimiter));
+        }
+        line
+    }
+
+    // Whether the normalized line is selected by --grep, every line is without it
+    pub fn is_selected(&self, line: &[u8]) -> bool {
+        match &self.grep {
+            Some(grep) => grep.is_match(&self.normalize(line)),
+            None => true,
+        }
+    }
+
+    // Write the merged lines held long enough, or all of them if no more lines are expected
+    pub fn write_merged<W: Write>(&self, writer: &mut W, all: bool) -> Result<()> {
+        match &self.merger {
+            Some(merger) if all => merger.borrow_mut().write_all(writer),
+            Some(merger) => merger.borrow_mut().write_ready(writer, Instant::now()),
+            None => Ok(()),
+        }
+    }
+}
+
+// The count followed by the noun, which is plural unless the count is one
+pub fn plural(count: u64, noun: &str) -> String {
+    if count == 1 {
+        format!("1 {}", noun)
+    } else {
+        format!("{} {}s", count, noun)
+    }
+}
+
+// Remove ANSI escape sequences such as colors (CSI), titles (OSC) and other two byte sequences.
+// The line must be complete, otherwise a sequence may be split.
+pub fn strip_ansi(line: &[u8]) -> Vec<u8> {
+    let mut stripped = Vec::with_capacity(line.len());
+    let mut i = 0;
+    while i < line.len() {
+        if line[i] == ESC {
+            i += escape_len(&line[i..]);
+        } else {
+            stripped.push(line[i]);
+            i += 1;
+        }
+    }
+    stripped
+}
+
+// Replace the bytes which are not printable text with U+FFFD, except tabs and the line ending.
+// Invalid UTF-8 sequences are replaced as a whole, as String::from_utf8_lossy does.
+pub fn sanitize(line: &[u8], delimiter: u8) -> Vec<u8> {
+    let (body, ending) = match line {
+        [body @ .., CR, last] if *last == delimiter => (body, &line[line.len() - 2..]),
+        [body @ .., last] if *last == delimiter => (body, &line[line.len() - 1..]),
+        _ => (line, &line[line.len()..]),
+    };
+    let mut sanitized = String::with_capacity(line.len());
+    for c in String::from_utf8_lossy(body).chars() {
+        if c.is_control() && c != '\t' {
+            sanitized.push(char::REPLACEMENT_CHARACTER);
+        } else {
+            sanitized.push(c);
+        }
+    }
+    let mut sanitized = sanitized.into_bytes();
+    sanitized.extend_from_slice(ending);
+    sanitized
+}
+
+// Length of the ANSI escape sequence at the start of the bytes, up to the end if it is split
+fn escape_len(bytes: &[u8]) -> usize {
+    let mut i = 1;
+    match bytes.get(i) {
+        // CSI: parameters and intermediates end with a byte in 0x40..=0x7e
+        Some(b'[') => {
+            i += 1;
+            while i < bytes.len() && !(0x40..=0x7e).contains(&bytes[i]) {
+                i += 1;
+            }
+            i + 1
+        }
+        // OSC: terminated by BEL or ESC \
+        Some(b']') => {
+            i += 1;
+            while i < bytes.len() {
+                if bytes[i] == BEL {
+                    return i + 1;
+                }
+                if bytes[i] == ESC && bytes.get(i + 1) == Some(&b'\\') {
+                    return i + 2;
+                }
+                i += 1;
+            }
+            i
+        }
+        Some(_) => 2,
+        None => 1,
+    }
+    .min(bytes.len())
+}
+
+// Terminal cells taken by the bytes, where the escape sequences take none
+pub fn display_width(bytes: &[u8]) -> usize {
+    String::from_utf8_lossy(&strip_ansi(bytes)).width()
+}
+
+// Split the line into the pieces of the columns at most, counted in terminal cells, so that a wide
+// character takes two and an escape sequence takes none. The first piece follows the prefix of
+// the width, and the others leave the indent. The last piece keeps the ending.
+pub fn wrap(
+    line: &[u8],
+    columns: usize,
+    prefix_width: usize,
+    indent: usize,
+    delimiter: u8,
+) -> Vec<&[u8]> {
+    let body_len = match line {
+        [.., CR, last] if *last == delimiter => line.len() - 2,
+        [.., last] if *last == delimiter => line.len() - 1,
+        _ => line.len(),
+    };
+    let body = &line[..body_len];
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut width = columns.saturating_sub(prefix_width).max(1);
+    let mut count = 0;
+    let mut i = 0;
+    while i < body.len() {
+        if body[i] == ESC {
+            i += escape_len(&body[i..]);
+            continue;
+        }
+        // A character is never split, an invalid byte takes a cell as the replacement character
+        let char_len = match body[i] {
+            0xf0..=0xff => 4,
+            0xe0..=0xef => 3,
+            0xc0..=0xdf => 2,
+            _ => 1,
+        };
+        let (char_len, char_width) = match body.get(i..i + char_len).map(from_utf8) {
+            Some(Ok(c)) => (char_len, c.width()),
+            _ => (1, 1),
+        };
+        if count > 0 && count + char_width > width {
+            pieces.push(&line[start..i]);
+            start = i;
+            count = 0;
+            width = columns.saturating_sub(indent).max(1);
+        }
+        count += char_width;
+        i += char_len;
+    }
+    pieces.push(&line[start..]);
+    pieces
+}
+
+// Remove a carriage return just before the delimiter at the end of the line
+pub fn strip_cr(line: &[u8], delimiter: u8) -> Vec<u8> {
+    match line {
+        [body @ .., CR, last] if *last == delimiter => {
+            let mut stripped = body.to_vec();
+            stripped.push(delimiter);
+            stripped
+        }
+        _ => line.to_vec(),
+    }
+}
+
+pub fn parse_colour(name: &str) -> Option<Colour> {
+    match name.to_ascii_lowercase().as_str() {
+        "black" => Some(Colour::Black),
+        "red" => Some(Colour::Red),
+        "green" => Some(Colour::Green),
+        "yellow" => Some(Colour::Yellow),
+        "blue" => Some(Colour::Blue),
+        "purple" | "magenta" => Some(Colour::Purple),
+        "cyan" => Some(Colour::Cyan),
+        "white" => Some(Colour::White),
+        // 256 colors palette
+        number => number.parse().ok().map(Colour::Fixed),
+    }
+}
+
+// Parse PATTERN=COLOR, where the pattern itself may contain '='
+pub fn parse_highlight(spec: &str) -> std::result::Result<(Regex, Colour), String> {
+    let separator = match spec.rfind('=') {
+        Some(separator) => separator,
+        None => return Err(format!("expected PATTERN=COLOR: {}", spec)),
+    };
+    let (pattern, colour) = (&spec[..separator], &spec[separator + 1..]);
+    let colour = parse_colour(colour).ok_or(format!("unknown color: {}", colour))?;
+    let regex = Regex::new(pattern).map_err(|error| error.to_string())?;
+    Ok((regex, colour))
+}
+
+// Wrap the matches of the highlight rules with the colors.
+// Overlapping matches are dropped except the one by the earliest rule.
+pub fn highlight(line: &[u8], highlights: &[(Regex, Colour)]) -> Vec<u8> {
+    let mut ranges: Vec<(usize, usize, Colour)> = Vec::new();
+    for (regex, colour) in highlights {
+        for found in regex.find_iter(line) {
+            let overlapped = ranges
+                .iter()
+                .any(|&(start, end, _)| found.start() < end && start < found.end());
+            if found.start() < found.end() && !overlapped {
+                ranges.push((found.start(), found.end(), *colour));
+            }
+        }
+    }
+    ranges.sort_by_key(|&(start, _, _)| start);
+    let mut highlighted = Vec::with_capacity(line.len());
+    let mut position = 0;
+    for (start, end, colour) in ranges {
+        highlighted.extend_from_slice(&line[position..start]);
+        highlighted.extend_from_slice(colour.prefix().to_string().as_bytes());
+        highlighted.extend_from_slice(&line[start..end]);
+        highlighted.extend_from_slice(colour.suffix().to_string().as_bytes());
+        position = end;
+    }
+    highlighted.extend_from_slice(&line[position..]);
+    highlighted
+}
+
+// Token bucket which allows bursts up to one second worth of lines
+pub struct RateLimiter {
+    lines_per_sec: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(lines_per_sec: u64) -> RateLimiter {
+        RateLimiter {
+            lines_per_sec: lines_per_sec as f64,
+            tokens: lines_per_sec as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.lines_per_sec).min(self.lines_per_sec);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Rings the bell or shows a desktop notification on a match
+pub struct Alert {
+    bell: bool,
+    desktop_notification: bool,
+    last_alert: Option<Instant>,
+}
+
+impl Alert {
+    pub fn new(bell: bool, desktop_notification: bool) -> Alert {
+        Alert {
+            bell,
+            desktop_notification,
+            last_alert: None,
+        }
+    }
+
+    // Returns false if the last alert was too recent
+    pub fn try_fire(&mut self, now: Instant) -> bool {
+        match self.last_alert {
+            Some(last_alert) if now.saturating_duration_since(last_alert) < ALERT_INTERVAL => false,
+            _ => {
+                self.last_alert = Some(now);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(feature = "desktop-notification")]
+fn notify_desktop(line: &[u8]) {
+    let body = String::from_utf8_lossy(line);
+    // Tailing continues even if no notification server is running
+    let _ = notify_rust::Notification::new()
+        .summary("regtail")
+        .body(body.trim_end())
+        .show();
+}
+
+#[cfg(not(feature = "desktop-notification"))]
+fn notify_desktop(_line: &[u8]) {}
+
+// Processes the tailed content line by line.
+// An incomplete line is held until its delimiter arrives.
+pub struct LineProcessor {
+    options: Rc<LineOptions>,
+    partial_line: Vec<u8>,
+    last_line: Option<Vec<u8>>,
+    repeat_count: u64,
+    suppressed_count: u64,
+    // Lines preceding the next match, at most before_context lines
+    before_lines: VecDeque<Vec<u8>>,
+    // Lines still to be shown after the last match
+    after_remaining: usize,
+    // Whether a line was dropped since the last shown line
+    skipped: bool,
+    matched_any: bool,
+    // Lines counted since the last report in count mode
+    line_count: u64,
+    // Written before each line, such as the tag of the file
+    prefix: Vec<u8>,
+    // The prefix without the color codes, written to the mirrored output
+    plain_prefix: Vec<u8>,
+    // Inherited by the following lines without a timestamp when merging
+    last_timestamp: Option<NaiveDateTime>,
+}
+
+impl LineProcessor {
+    pub fn new(options: Rc<LineOptions>) -> LineProcessor {
+        LineProcessor {
+            options,
+            partial_line: Vec::new(),
+            last_line: None,
+            repeat_count: 0,
+            suppressed_count: 0,
+            before_lines: VecDeque::new(),
+            after_remaining: 0,
+            skipped: false,
+            matched_any: false,
+            line_count: 0,
+            prefix: Vec::new(),
+            plain_prefix: Vec::new(),
+            last_timestamp: None,
+        }
+    }
+
+    pub fn set_prefix(&mut self, prefix: Vec<u8>) {
+        self.plain_prefix = strip_ansi(&prefix);
+        self.prefix = prefix;
+    }
+
+    pub fn write<W: Write>(&mut self, writer: &mut W, buf: &[u8]) -> Result<()> {
+        let mut rest = buf;
+        let delimiter = self.options.delimiter;
+        while let Some(pos) = rest.iter().position(|&byte| byte == delimiter) {
+            let (line, remaining) = rest.split_at(pos + 1);
+            if self.partial_line.is_empty() {
+                self.write_line(writer, line)?;
+            } else {
+                self.partial_line.extend_from_slice(line);
+                let line = take(&mut self.partial_line);
+                self.write_line(writer, &line)?;
+            }
+            rest = remaining;
+        }
+        self.partial_line.extend_from_slice(rest);
+        Ok(())
+    }
+
+    // Returns the lines counted since the last call
+    pub fn take_line_count(&mut self) -> u64 {
+        take(&mut self.line_count)
+    }
+
+    // Returns true if an incomplete line is held until its delimiter arrives
+    pub fn has_partial_line(&self) -> bool {
+        !self.partial_line.is_empty()
+    }
+
+    // Returns true if anything is left to be written by end_of_input
+    pub fn has_held_output(&self) -> bool {
+        !self.partial_line.is_empty() || self.repeat_count > 0 || self.suppressed_count > 0
+    }
+
+    // Called when all the appended content is read
+    pub fn end_of_read<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        // The run may be continued by the next append, so its count waits until the run ends
+        self.write_suppressed_count(writer)
+    }
+
+    // Called when no more content follows, the incomplete last line is processed as is
+    pub fn end_of_input<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        if !self.partial_line.is_empty() {
+            let line = take(&mut self.partial_line);
+            self.write_line(writer, &line)?;
+        }
+        self.write_repeat_count(writer)?;
+        self.end_of_read(writer)
+    }
+
+    fn write_line<W: Write>(&mut self, writer: &mut W, line: &[u8]) -> Result<()> {
+        let options = Rc::clone(&self.options);
+        let normalized = options.normalize(line);
+        let line = &normalized[..];
+        if self.options.count {
+            let matched = match &self.options.grep {
+                Some(grep) => grep.is_match(line),
+                None => true,
+            };
+            if matched {
+                self.line_count += 1;
+            }
+            return Ok(());
+        }
+        let mut matched = false;
+        if let Some(grep) = &self.options.grep {
+            if grep.is_match(line) {
+                matched = true;
+                self.after_remaining = self.options.after_context;
+                let has_context = self.options.before_context > 0 || self.options.after_context > 0;
+                if has_context && self.skipped && self.matched_any {
+                    self.write_context_separator(writer)?;
+                }
+                self.skipped = false;
+                self.matched_any = true;
+                for context in take(&mut self.before_lines) {
+                    self.write_selected_line(writer, &context)?;
+                }
+            } else if self.after_remaining > 0 {
+                self.after_remaining -= 1;
+            } else {
+                if self.options.before_context > 0 {
+                    if self.before_lines.len() == self.options.before_context {
+                        self.before_lines.pop_front();
+                        self.skipped = true;
+                    }
+                    self.before_lines.push_back(line.to_vec());
+                } else {
+                    self.skipped = true;
+                }
+                return Ok(());
+            }
+        }
+        self.write_selected_line(writer, line)?;
+        if matched {
+            self.alert(writer, line)?;
+        }
+        Ok(())
+    }
+
+    fn alert<W: Write>(&self, writer: &mut W, line: &[u8]) -> Result<()> {
+        let mut alert = match &self.options.alert {
+            Some(alert) => alert.borrow_mut(),
+            None => return Ok(()),
+        };
+        if !alert.try_fire(Instant::now()) {
+            return Ok(());
+        }
+        if alert.bell {
+            match &self.options.tee_target {
+                Some(tee_target) => {
+                    tee_target.set(TeeTarget::Primary);
+                    writer.write_all(&[BEL])?;
+                    tee_target.set(TeeTarget::Both);
+                }
+                None => writer.write_all(&[BEL])?,
+            }
+        }
+        if alert.desktop_notification {
+            notify_desktop(line);
+        }
+        Ok(())
+    }
+
+    fn write_selected_line<W: Write>(&mut self, writer: &mut W, line: &[u8]) -> Result<()> {
+        if self.options.squeeze {
+            if self.last_line.as_deref() == Some(line) {
+                self.repeat_count += 1;
+                return Ok(());
+            }
+            self.write_repeat_count(writer)?;
+            self.last_line = Some(line.to_vec());
+        }
+        if let Some(rate_limiter) = &self.options.rate_limiter {
+            // Never block here, otherwise file events cannot be handled
+            if !rate_limiter.borrow_mut().try_acquire(Instant::now()) {
+                self.suppressed_count += 1;
+                return Ok(());
+            }
+        }
+        if let Some(merger) = &self.options.merger {
+            let mut merger = merger.borrow_mut();
+            if let Some(time) = merger.timestamp_of(line) {
+                self.last_timestamp = Some(time);
+            }
+            // The merged lines are written to both outputs at once, so they are mirrored plain
+            let decorate = self.options.tee_target.is_none();
+            let mut merged = Vec::new();
+            self.write_prefixed(&mut merged, line, decorate)?;
+            merger.push(self.last_timestamp, merged, Instant::now());
+            return Ok(());
+        }
+        self.write_prefixed(writer, line, true)
+    }
+
+    fn write_prefixed<W: Write>(&self, writer: &mut W, line: &[u8], decorate: bool) -> Result<()> {
+        self.write_prefix(writer, decorate)?;
+        let columns = match self.options.wrap {
+            Some(columns) => columns,
+            None => return self.write_highlighted(writer, line, decorate),
+        };
+        let pieces = wrap(
+            line,
+            columns,
+            display_width(&self.prefix),
+            self.options.wrap_indent,
+            self.options.delimiter,
+        );
+        let last = pieces.len() - 1;
+        for (i, piece) in pieces.into_iter().enumerate() {
+            if i > 0 {
+                writer.write_all(&b" ".repeat(self.options.wrap_indent))?;
+            }
+            if i == last {
+                self.write_highlighted(writer, piece, decorate)?;
+            } else {
+                let mut continued = piece.to_vec();
+                continued.push(LF);
+                self.write_highlighted(writer, &continued, decorate)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_prefix<W: Write>(&self, writer: &mut W, decorate: bool) -> Result<()> {
+        if !decorate {
+            return writer.write_all(&self.plain_prefix);
+        }
+        match &self.options.tee_target {
+            // The mirrored output is kept free from color codes
+            Some(tee_target) if self.prefix != self.plain_prefix => {
+                tee_target.set(TeeTarget::Primary);
+                writer.write_all(&self.prefix)?;
+                tee_target.set(TeeTarget::Secondary);
+                writer.write_all(&self.plain_prefix)?;
+                tee_target.set(TeeTarget::Both);
+                Ok(())
+            }
+            _ => writer.write_all(&self.prefix),
+        }
+    }
+
+    fn write_highlighted<W: Write>(
+        &self,
+        writer: &mut W,
+        line: &[u8],
+        decorate: bool,
+    ) -> Result<()> {
+        if self.options.highlights.is_empty() || !decorate {
+            return self.emit(writer, line);
+        }
+        let highlighted = highlight(line, &self.options.highlights);
+        match &self.options.tee_target {
+            // The mirrored output is kept free from color codes
+            Some(tee_target) => {
+                tee_target.set(TeeTarget::Primary);
+                self.emit(writer, &highlighted)?;
+                tee_target.set(TeeTarget::Secondary);
+                self.emit(writer, line)?;
+                tee_target.set(TeeTarget::Both);
+                Ok(())
+            }
+            None => self.emit(writer, &highlighted),
+        }
+    }
+
+    fn emit<W: Write>(&self, writer: &mut W, line: &[u8]) -> Result<()> {
+        match line.split_last() {
+            Some((&LF, body)) if self.options.crlf && body.last() != Some(&CR) => {
+                writer.write_all(body)?;
+                writer.write_all(b"\r\n")?;
+            }
+            _ => writer.write_all(line)?,
+        }
+        if self.options.line_buffered {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn write_context_separator<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        self.write_repeat_count(writer)?;
+        self.last_line = None;
+        self.emit(writer, CONTEXT_SEPARATOR)
+    }
+
+    fn write_repeat_count<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        if self.repeat_count > 0 {
+            let message = format!("(repeated {})\n", plural(self.repeat_count, "time"));
+            self.emit(writer, message.as_bytes())?;
+            self.repeat_count = 0;
+        }
+        Ok(())
+    }
+
+    fn write_suppressed_count<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        if self.suppressed_count > 0 {
+            let message = format!("({} suppressed)\n", plural(self.suppressed_count, "line"));
+            self.emit(writer, message.as_bytes())?;
+            self.suppressed_count = 0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{Result, Write};
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    use regex::bytes::Regex;
+
+    use super::{
+        highlight, parse_highlight, sanitize, strip_ansi, strip_cr, wrap, Alert, LineOptions,
+        LineProcessor, RateLimiter,
+    };
+    use crate::config::Config;
+    use crate::tail::TeeWriter;
+
+    // Records the written content at each flush
+    #[derive(Default)]
+    struct FlushRecorder {
+        buffer: Vec<u8>,
+        flushed: Vec<String>,
+    }
+
+    impl Write for FlushRecorder {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.buffer.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            let content = String::from_utf8(self.buffer.split_off(0)).unwrap();
+            self.flushed.push(content);
+            Ok(())
+        }
+    }
+
+    fn squeeze_processor() -> LineProcessor {
+        LineProcessor::new(Rc::new(LineOptions {
+            squeeze: true,
+            ..LineOptions::default()
+        }))
+    }
+
+    #[test]
+    fn test_squeeze_in_single_write() {
+        let mut processor = squeeze_processor();
+        let mut writer: Vec<u8> = Vec::new();
+        processor.write(&mut writer, b"a\na\na\nb\nb\nc\n").unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "a\n(repeated 2 times)\nb\n(repeated 1 time)\nc\n"
+        );
+    }
+
+    #[test]
+    fn test_squeeze_across_writes() {
+        let mut processor = squeeze_processor();
+        let mut writer: Vec<u8> = Vec::new();
+        processor.write(&mut writer, b"a\na\n").unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert_eq!(writer, b"a\n");
+        processor.write(&mut writer, b"a\na\nb\n").unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "a\n(repeated 3 times)\nb\n"
+        );
+    }
+
+    #[test]
+    fn test_squeeze_line_split_across_writes() {
+        let mut processor = squeeze_processor();
+        let mut writer: Vec<u8> = Vec::new();
+        processor.write(&mut writer, b"line\nli").unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert_eq!(writer, b"line\n");
+        processor.write(&mut writer, b"ne\nother\n").unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "line\n(repeated 1 time)\nother\n"
+        );
+    }
+
+    #[test]
+    fn test_alert_interval() {
+        let mut alert = Alert::new(true, false);
+        let now = Instant::now();
+        assert!(alert.try_fire(now));
+        assert!(!alert.try_fire(now + Duration::from_millis(500)));
+        assert!(alert.try_fire(now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut rate_limiter = RateLimiter::new(2);
+        let now = Instant::now();
+        assert!(rate_limiter.try_acquire(now));
+        assert!(rate_limiter.try_acquire(now));
+        assert!(!rate_limiter.try_acquire(now));
+        assert!(rate_limiter.try_acquire(now + Duration::from_millis(500)));
+        assert!(!rate_limiter.try_acquire(now + Duration::from_millis(500)));
+
+        // Tokens never exceed one second worth of lines
+        let later = now + Duration::from_secs(10);
+        assert!(rate_limiter.try_acquire(later));
+        assert!(rate_limiter.try_acquire(later));
+        assert!(!rate_limiter.try_acquire(later));
+    }
+
+    #[test]
+    fn test_rate_limited_output() {
+        let mut processor = LineProcessor::new(Rc::new(LineOptions {
+            rate_limiter: Some(Rc::new(RefCell::new(RateLimiter::new(2)))),
+            ..LineOptions::default()
+        }));
+        let mut writer: Vec<u8> = Vec::new();
+        processor.write(&mut writer, b"1\n2\n3\n4\n5\n").unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "1\n2\n(3 lines suppressed)\n"
+        );
+    }
+
+    #[test]
+    fn test_rate_limited_one_line() {
+        let mut processor = LineProcessor::new(Rc::new(LineOptions {
+            rate_limiter: Some(Rc::new(RefCell::new(RateLimiter::new(2)))),
+            ..LineOptions::default()
+        }));
+        let mut writer: Vec<u8> = Vec::new();
+        processor.write(&mut writer, b"1\n2\n3\n").unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "1\n2\n(1 line suppressed)\n"
+        );
+    }
+
+    #[test]
+    fn test_line_buffered() {
+        let mut processor = LineProcessor::new(Rc::new(LineOptions {
+            line_buffered: true,
+            ..LineOptions::default()
+        }));
+        let mut writer = FlushRecorder::default();
+        processor.write(&mut writer, b"line1\nline2\nli").unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert_eq!(writer.flushed, vec!["line1\n", "line2\n"]);
+        processor.write(&mut writer, b"ne3\n").unwrap();
+        assert_eq!(writer.flushed, vec!["line1\n", "line2\n", "line3\n"]);
+    }
+
+    #[test]
+    fn test_squeeze_with_delimiter() {
+        let mut processor = LineProcessor::new(Rc::new(LineOptions {
+            delimiter: 0x0c,
+            squeeze: true,
+            ..LineOptions::default()
+        }));
+        let mut writer: Vec<u8> = Vec::new();
+        processor
+            .write(&mut writer, b"a\nb\x0ca\nb\x0cc\x0c")
+            .unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "a\nb\x0c(repeated 1 time)\nc\x0c"
+        );
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(
+            strip_ansi(b"\x1b[31merror\x1b[0m: failed\n"),
+            b"error: failed\n"
+        );
+        assert_eq!(strip_ansi(b"\x1b[1;32mok\x1b[m\n"), b"ok\n");
+        assert_eq!(strip_ansi(b"\x1b]0;title\x07text\n"), b"text\n");
+        assert_eq!(strip_ansi(b"\x1b]0;title\x1b\\text\n"), b"text\n");
+        assert_eq!(strip_ansi(b"plain\n"), b"plain\n");
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(
+            sanitize(b"a\x00b\x1b[0m\n", b'\n'),
+            "a\u{fffd}b\u{fffd}[0m\n".as_bytes()
+        );
+        assert_eq!(
+            sanitize(b"\xffok\tdone\r\n", b'\n'),
+            "\u{fffd}ok\tdone\r\n".as_bytes()
+        );
+        assert_eq!(
+            sanitize(b"partial\x07", b'\n'),
+            "partial\u{fffd}".as_bytes()
+        );
+        assert_eq!(sanitize(b"a\nb\0", b'\0'), "a\u{fffd}b\0".as_bytes());
+    }
+
+    #[test]
+    fn test_strip_ansi_split_across_writes() {
+        let mut processor = LineProcessor::new(Rc::new(LineOptions {
+            strip_ansi: true,
+            ..LineOptions::default()
+        }));
+        let mut writer: Vec<u8> = Vec::new();
+        processor.write(&mut writer, b"\x1b[31mred\x1b[").unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        processor.write(&mut writer, b"0m plain\n").unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), "red plain\n");
+    }
+
+    fn context_processor(before_context: usize, after_context: usize) -> LineProcessor {
+        LineProcessor::new(Rc::new(LineOptions {
+            grep: Some(Regex::new("match").unwrap()),
+            before_context,
+            after_context,
+            ..LineOptions::default()
+        }))
+    }
+
+    #[test]
+    fn test_grep_context() {
+        let mut processor = context_processor(1, 1);
+        let mut writer: Vec<u8> = Vec::new();
+        processor
+            .write(
+                &mut writer,
+                b"a
+b
+match1
+c
+d
+e
+match2
+f
+g
+",
+            )
+            .unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "b\nmatch1\nc\n--\ne\nmatch2\nf\n"
+        );
+    }
+
+    #[test]
+    fn test_grep_overlapping_context() {
+        let mut processor = context_processor(2, 1);
+        let mut writer: Vec<u8> = Vec::new();
+        processor
+            .write(
+                &mut writer,
+                b"a
+match1
+b
+match2
+c
+d
+match3
+e
+f
+",
+            )
+            .unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        // Each line is shown once even if it is the context of two matches
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "a\nmatch1\nb\nmatch2\nc\nd\nmatch3\ne\n"
+        );
+    }
+
+    #[test]
+    fn test_grep_context_across_writes() {
+        let mut processor = context_processor(1, 1);
+        let mut writer: Vec<u8> = Vec::new();
+        processor
+            .write(
+                &mut writer,
+                b"a
+b
+",
+            )
+            .unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert!(writer.is_empty());
+        processor
+            .write(
+                &mut writer,
+                b"match
+c
+d
+",
+            )
+            .unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), "b\nmatch\nc\n");
+    }
+
+    #[test]
+    fn test_count_matched_lines() {
+        let mut processor = LineProcessor::new(Rc::new(LineOptions {
+            grep: Some(Regex::new("error").unwrap()),
+            count: true,
+            ..LineOptions::default()
+        }));
+        let mut writer: Vec<u8> = Vec::new();
+        processor.write(&mut writer, b"error1\nok\nerr").unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert_eq!(processor.take_line_count(), 1);
+        processor.write(&mut writer, b"or2\nerror3\n").unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert_eq!(processor.take_line_count(), 2);
+        assert_eq!(processor.take_line_count(), 0);
+        assert!(writer.is_empty());
+    }
+
+    #[test]
+    fn test_strip_cr() {
+        assert_eq!(strip_cr(b"line\r\n", b'\n'), b"line\n");
+        assert_eq!(strip_cr(b"line\n", b'\n'), b"line\n");
+        assert_eq!(strip_cr(b"li\rne\n", b'\n'), b"li\rne\n");
+        assert_eq!(strip_cr(b"line\r", b'\n'), b"line\r");
+    }
+
+    #[test]
+    fn test_strip_cr_split_across_writes() {
+        let mut processor = LineProcessor::new(Rc::new(LineOptions {
+            strip_cr: true,
+            ..LineOptions::default()
+        }));
+        let mut writer: Vec<u8> = Vec::new();
+        processor.write(&mut writer, b"dos\r\nmixed\r").unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        processor.write(&mut writer, b"\nunix\n").unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), "dos\nmixed\nunix\n");
+    }
+
+    #[test]
+    fn test_crlf() {
+        let mut processor = LineProcessor::new(Rc::new(LineOptions {
+            crlf: true,
+            squeeze: true,
+            ..LineOptions::default()
+        }));
+        let mut writer: Vec<u8> = Vec::new();
+        processor
+            .write(&mut writer, b"unix\ndos\r\ndos\r\n")
+            .unwrap();
+        processor.end_of_input(&mut writer).unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "unix\r\ndos\r\n(repeated 1 time)\r\n"
+        );
+    }
+
+    #[test]
+    fn test_wrap() {
+        let pieces: Vec<&[u8]> = vec![b"abcd", b"efgh", b"ij\n"];
+        assert_eq!(wrap(b"abcdefghij\n", 4, 0, 0, b'\n'), pieces);
+        // The line ending is not counted
+        let pieces: Vec<&[u8]> = vec![b"abcd\r\n"];
+        assert_eq!(wrap(b"abcd\r\n", 4, 0, 0, b'\n'), pieces);
+        let pieces: Vec<&[u8]> = vec![b"abcd", b"ef", b"gh", b"ij"];
+        assert_eq!(wrap(b"abcdefghij", 4, 0, 2, b'\n'), pieces);
+        // The prefix takes the columns of the first piece
+        let pieces: Vec<&[u8]> = vec![b"ab", b"cdef", b"ghij"];
+        assert_eq!(wrap(b"abcdefghij", 4, 2, 0, b'\n'), pieces);
+        // The escape sequences take no columns and stay with the characters before
+        let pieces: Vec<&[u8]> = vec![b"\x1b[31mabcd\x1b[0m", b"ef"];
+        assert_eq!(wrap(b"\x1b[31mabcd\x1b[0mef", 4, 0, 0, b'\n'), pieces);
+    }
+
+    #[test]
+    fn test_wrap_multibyte() {
+        // The wide characters take two columns
+        let line = "\u{3042}\u{3044}\u{3046}\u{3048}\u{304a}\n";
+        let pieces: Vec<&[u8]> = vec![
+            "\u{3042}\u{3044}".as_bytes(),
+            "\u{3046}\u{3048}".as_bytes(),
+            "\u{304a}\n".as_bytes(),
+        ];
+        assert_eq!(wrap(line.as_bytes(), 4, 0, 0, b'\n'), pieces);
+        let line = "a\u{e9}b\u{1f600}c";
+        let pieces: Vec<&[u8]> = vec!["a\u{e9}b".as_bytes(), "\u{1f600}c".as_bytes()];
+        assert_eq!(wrap(line.as_bytes(), 3, 0, 0, b'\n'), pieces);
+        let pieces: Vec<&[u8]> = vec!["a\u{e9}".as_bytes(), "b\u{1f600}".as_bytes(), b"c"];
+        assert_eq!(wrap(line.as_bytes(), 3, 1, 0, b'\n'), pieces);
+    }
+
+    #[test]
+    fn test_wrapped_output() {
+        let mut processor = LineProcessor::new(Rc::new(LineOptions {
+            wrap: Some(6),
+            wrap_indent: 2,
+            crlf: true,
+            ..LineOptions::default()
+        }));
+        let mut writer: Vec<u8> = Vec::new();
+        processor
+            .write(&mut writer, b"short\nlong line here\n")
+            .unwrap();
+        processor.end_of_read(&mut writer).unwrap();
+        assert_eq!(
+            String::from_utf8(writer).unwrap(),
+            "short\r\nlong l\r\n  ine \r\n  here\r\n"
+        );
+    }
+
+    #[test]
+    fn test_highlight() {
+        let highlights = vec![
+            parse_highlight("ERROR=red").unwrap(),
+            parse_highlight("WARN=yellow").unwrap(),
+        ];
+        assert_eq!(
+            String::from_utf8(highlight(b"WARN after ERROR\n", &highlights)).unwrap(),
+            "\x1b[33mWARN\x1b[0m after \x1b[31mERROR\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn test_highlight_overlap() {
+        let highlights = vec![
+            parse_highlight("ERR=red").unwrap(),
+            parse_highlight("ERROR=yellow").unwrap(),
+            parse_highlight("OR=blue").unwrap(),
+        ];
+        assert_eq!(
+            String::from_utf8(highlight(b"ERROR\n", &highlights)).unwrap(),
+            "\x1b[31mERR\x1b[0m\x1b[34mOR\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_highlight() {
+        assert!(parse_highlight("a=b=green").unwrap().0.is_match(b"a=b"));
+        assert!(parse_highlight("ERROR").is_err());
+        assert!(parse_highlight("ERROR=unknown").is_err());
+        assert!(parse_highlight("(=red").is_err());
+    }
+
+    #[test]
+    fn test_highlight_without_color() {
+        let build = |colorize| {
+            Config::builder()
+                .highlights(vec!["ERROR=red"])
+                .colorize(colorize)
+                .build()
+                .line_options()
+                .unwrap()
+        };
+        let mut processor = LineProcessor::new(Rc::new(build(true)));
+        let mut writer: Vec<u8> = Vec::new();
+        processor.write(&mut writer, b"ERROR\n").unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), "\x1b[31mERROR\x1b[0m\n");
+
+        let options = build(false);
+        assert!(!options.is_line_aware());
+        let mut processor = LineProcessor::new(Rc::new(options));
+        let mut writer: Vec<u8> = Vec::new();
+        processor.write(&mut writer, b"ERROR\n").unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), "ERROR\n");
+    }
+
+    #[test]
+    fn test_highlight_with_tee() {
+        let mut primary: Vec<u8> = Vec::new();
+        let mut secondary: Vec<u8> = Vec::new();
+        {
+            let mut writer = TeeWriter::new(&mut primary, &mut secondary);
+            let mut options = Config::builder()
+                .highlights(vec!["ERROR=red"])
+                .colorize(true)
+                .build()
+                .line_options()
+                .unwrap();
+            options.tee_target = Some(writer.target());
+            let mut processor = LineProcessor::new(Rc::new(options));
+            processor.write(&mut writer, b"ERROR\nfine\n").unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(primary).unwrap(),
+            "\x1b[31mERROR\x1b[0m\nfine\n"
+        );
+        assert_eq!(String::from_utf8(secondary).unwrap(), "ERROR\nfine\n");
+    }
+}
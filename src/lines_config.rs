@@ -0,0 +1,90 @@
+/*
+ * Copyright 2019 StoneDot (Hiroaki Goto)
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+
+// Initial line counts for the files whose path matches the patterns, loaded from --lines-config.
+// Each line is a regex and a count separated by whitespace, such as `access\.log 50`.
+#[derive(Debug, Default)]
+pub struct LineCounts {
+    rules: Vec<(Regex, u64)>,
+}
+
+impl LineCounts {
+    pub fn load(path: &Path) -> Result<LineCounts, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|error| format!("cannot read lines config {}: {}", path.display(), error))?;
+        Self::parse(&content)
+            .map_err(|error| format!("invalid lines config {}: {}", path.display(), error))
+    }
+
+    // Blank lines and the lines starting with # are ignored
+    pub fn parse(content: &str) -> Result<LineCounts, String> {
+        let mut rules = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // The pattern itself may contain whitespace, the count is the last word
+            let (pattern, count) = match line.rsplit_once(char::is_whitespace) {
+                Some((pattern, count)) => (pattern.trim_end(), count),
+                None => return Err(format!("line {}: expected PATTERN N", number + 1)),
+            };
+            let count = count
+                .parse()
+                .map_err(|_| format!("line {}: invalid count: {}", number + 1, count))?;
+            let regex =
+                Regex::new(pattern).map_err(|error| format!("line {}: {}", number + 1, error))?;
+            rules.push((regex, count));
+        }
+        Ok(LineCounts { rules })
+    }
+
+    // The count of the first pattern matching the path, None to fall back to --lines
+    pub fn lines_for(self: &LineCounts, path: &Path) -> Option<u64> {
+        let path = path.to_string_lossy();
+        self.rules
+            .iter()
+            .find(|(regex, _)| regex.is_match(&path))
+            .map(|&(_, count)| count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::LineCounts;
+
+    #[test]
+    fn test_lines_for() {
+        let counts = LineCounts::parse("# comment\n\naccess\\.log 50\n.*\\.log   5\n").unwrap();
+        assert_eq!(counts.lines_for(Path::new("logs/access.log")), Some(50));
+        assert_eq!(counts.lines_for(Path::new("logs/error.log")), Some(5));
+        assert_eq!(counts.lines_for(Path::new("logs/app.txt")), None);
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert!(LineCounts::parse("access.log").is_err());
+        assert!(LineCounts::parse("access.log many").is_err());
+        assert!(LineCounts::parse("access(.log 5").is_err());
+    }
+}
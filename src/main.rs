@@ -14,53 +14,187 @@
  * limitations under the License.
  */
 
-#[macro_use]
-extern crate lazy_static;
-extern crate lru;
-
 #[macro_use]
 extern crate clap;
 
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+
 use opt::Opt;
-use watcher::DirectoryWatcher;
+use regtail::config::Config;
+use regtail::error::RegtailError;
+#[cfg(feature = "sftp")]
+use regtail::sftp::{RemoteWatcher, SftpFs, SftpUrl};
+#[cfg(unix)]
+use regtail::socket::{EventSocket, EventWriter};
+use regtail::tail::{StdinReader, TeeWriter};
+use regtail::watcher::DirectoryWatcher;
 
-mod filter;
+mod file_config;
 mod opt;
-mod tail;
-mod watcher;
 
 const EX_ERR: i32 = 1;
+// Some files could not be followed
+const EX_PARTIAL: i32 = 2;
 const EX_NOINPUT: i32 = 66;
 const EX_SOFTWARE: i32 = 70;
+const EX_CANTCREAT: i32 = 73;
 const EX_IOERR: i32 = 74;
 
-fn follow(opt: &Opt) -> Result<(), i32> {
-    let mut watcher = DirectoryWatcher::new(&opt)?;
-    watcher.follow_dir(&opt).map_err(|error| match error {
-        notify::Error::Generic(string) => {
-            eprintln!("generic error: {}", string);
-            EX_ERR
-        }
-        notify::Error::Io(error) => {
-            eprintln!("io error: {}", error);
+// Report the error and map it to the exit code
+fn exit_code(error: RegtailError) -> i32 {
+    eprintln!("{}", error);
+    match error {
+        RegtailError::Io(_) | RegtailError::Notify(notify::Error::Io(_)) => EX_IOERR,
+        RegtailError::Notify(notify::Error::PathNotFound) => EX_NOINPUT,
+        RegtailError::Notify(notify::Error::WatchNotFound) => EX_SOFTWARE,
+        _ => EX_ERR,
+    }
+}
+
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+// The BOM is written here, so that it precedes any content and is written only once
+fn create_tee_file(path: &Path, bom: bool) -> Result<BufWriter<File>, i32> {
+    let mut file = File::create(path).map(BufWriter::new).map_err(|error| {
+        eprintln!("cannot create {}: {}", path.display(), error);
+        EX_CANTCREAT
+    })?;
+    if bom {
+        file.write_all(UTF8_BOM).map_err(|error| {
+            eprintln!("cannot write {}: {}", path.display(), error);
             EX_IOERR
+        })?;
+    }
+    Ok(file)
+}
+
+fn follow(config: &Config) -> Result<(), i32> {
+    #[cfg(feature = "sftp")]
+    {
+        if let Some(url) = SftpUrl::parse(config.watch_path()) {
+            return follow_sftp(config, &url);
+        }
+    }
+    #[cfg(unix)]
+    {
+        if let Some(path) = config.socket_path() {
+            return follow_with_events(config, path);
+        }
+    }
+    match config.tee_path() {
+        Some(path) => {
+            let file = create_tee_file(path, config.tee_bom())?;
+            let stdout = BufWriter::new(io::stdout());
+            follow_with(
+                DirectoryWatcher::with_tee(config, stdout, file).map_err(exit_code)?,
+                config,
+            )
+        }
+        None => follow_with(DirectoryWatcher::new(config).map_err(exit_code)?, config),
+    }
+}
+
+#[cfg(feature = "sftp")]
+fn follow_sftp(config: &Config, url: &SftpUrl) -> Result<(), i32> {
+    let fs = SftpFs::connect(url, config.accept_unknown_host()).map_err(|error| {
+        eprintln!("cannot connect to {}: {}", url, error);
+        EX_NOINPUT
+    })?;
+    let stdout = BufWriter::new(io::stdout());
+    let mut watcher = RemoteWatcher::new(config, fs, url, stdout).map_err(exit_code)?;
+    watcher.follow(config).map_err(|error| {
+        eprintln!("io error: {}", error);
+        EX_IOERR
+    })
+}
+
+#[cfg(unix)]
+fn follow_with_events(config: &Config, socket_path: &Path) -> Result<(), i32> {
+    let socket = EventSocket::bind(socket_path).map_err(|error| {
+        eprintln!("cannot bind {}: {}", socket_path.display(), error);
+        EX_CANTCREAT
+    })?;
+    let writer = EventWriter::new(BufWriter::new(io::stdout()), socket);
+    let event_socket = writer.socket();
+    match config.tee_path() {
+        Some(path) => {
+            let file = create_tee_file(path, config.tee_bom())?;
+            let mut watcher =
+                DirectoryWatcher::with_tee(config, writer, file).map_err(exit_code)?;
+            watcher.set_event_socket(event_socket);
+            follow_with(watcher, config)
         }
-        notify::Error::PathNotFound => {
-            eprintln!("path not found");
-            EX_NOINPUT
+        None => {
+            let mut watcher = DirectoryWatcher::with_writer(config, writer).map_err(exit_code)?;
+            watcher.set_event_socket(event_socket);
+            follow_with(watcher, config)
         }
-        notify::Error::WatchNotFound => {
-            eprintln!("watch not found");
-            EX_SOFTWARE
+    }
+}
+
+fn follow_with<W: Write>(mut watcher: DirectoryWatcher<W>, config: &Config) -> Result<(), i32> {
+    watcher.follow_dir(&config).map_err(exit_code)?;
+    if watcher.has_failed_files() {
+        return Err(EX_PARTIAL);
+    }
+    Ok(())
+}
+
+fn follow_stdin(config: &Config) -> Result<(), i32> {
+    if config.socket_path().is_some() {
+        eprintln!("--socket cannot be used with the standard input");
+        return Err(EX_ERR);
+    }
+    let mut line_options = config.line_options().map_err(exit_code)?;
+    // A single input is already in order
+    line_options.merger = None;
+    let stdout = BufWriter::new(io::stdout());
+    let result = match config.tee_path() {
+        Some(path) => {
+            let mut writer = TeeWriter::new(stdout, create_tee_file(path, config.tee_bom())?);
+            line_options.tee_target = Some(writer.target());
+            let line_options = Rc::new(line_options);
+            StdinReader::stdin().tail(&mut writer, &line_options, config.initial_lines())
+        }
+        None => {
+            let mut writer = stdout;
+            let line_options = Rc::new(line_options);
+            StdinReader::stdin().tail(&mut writer, &line_options, config.initial_lines())
         }
+    };
+    result.map_err(|error| {
+        eprintln!("io error: {}", error);
+        EX_IOERR
     })
 }
 
+fn check_config(config: &Config) -> Result<(), i32> {
+    // Constructing a watcher validates the path and compiles the regex
+    DirectoryWatcher::new(config).map_err(exit_code)?;
+    println!("configuration is valid");
+    Ok(())
+}
+
+fn print_config(config: &Config) -> Result<(), i32> {
+    let json = serde_json::to_string_pretty(config).map_err(|error| {
+        eprintln!("cannot serialize the configuration: {}", error);
+        EX_SOFTWARE
+    })?;
+    println!("{}", json);
+    Ok(())
+}
+
 fn app() -> i32 {
     //let opt = Opt::from_args();
     let opt = Opt::generate().map_err(|_| EX_ERR);
     match opt {
-        Ok(opt) => follow(&opt).err().unwrap_or(0),
+        Ok(opt) if opt.print_config => print_config(&opt.config).err().unwrap_or(0),
+        Ok(opt) if opt.check_config => check_config(&opt.config).err().unwrap_or(0),
+        Ok(opt) if opt.config.is_stdin() => follow_stdin(&opt.config).err().unwrap_or(0),
+        Ok(opt) => follow(&opt.config).err().unwrap_or(0),
         Err(error_code) => error_code,
     }
 }
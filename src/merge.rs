@@ -0,0 +1,226 @@
+/*
+ * Copyright 2019 StoneDot (Hiroaki Goto)
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::io::{Result, Write};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, NaiveDateTime};
+use regex::bytes::Regex;
+
+// ISO 8601 like timestamps such as 2019-01-02 03:04:05.678 or 2019-01-02T03:04:05Z
+pub const DEFAULT_TIMESTAMP_REGEX: &str =
+    r"\d{4}[-/]\d{2}[-/]\d{2}[T ]\d{2}:\d{2}:\d{2}(?:[.,]\d+)?(?:Z|[+-]\d{2}:?\d{2})?";
+// Lines are held this long for the lines with earlier timestamps from the other files
+pub const MERGE_WINDOW: Duration = Duration::from_millis(500);
+
+const FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y/%m/%d %H:%M:%S%.f",
+    "%Y/%m/%dT%H:%M:%S%.f",
+];
+const FORMATS_WITH_OFFSET: &[&str] = &["%Y-%m-%d %H:%M:%S%.f%z", "%d/%b/%Y:%H:%M:%S %z"];
+
+// Parse the matched timestamp, the ones with an offset are compared in UTC
+pub fn parse_timestamp(text: &str) -> Option<NaiveDateTime> {
+    let text = text.trim().replace(',', ".");
+    if let Ok(time) = DateTime::parse_from_rfc3339(&text) {
+        return Some(time.naive_utc());
+    }
+    for format in FORMATS_WITH_OFFSET {
+        if let Ok(time) = DateTime::parse_from_str(&text, format) {
+            return Some(time.naive_utc());
+        }
+    }
+    FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&text, format).ok())
+}
+
+struct MergedLine {
+    // None if no timestamp has been seen yet, which precedes all the timestamps
+    time: Option<NaiveDateTime>,
+    // Keeps the order of arrival among the lines with the same timestamp
+    sequence: u64,
+    received: Instant,
+    line: Vec<u8>,
+}
+
+impl PartialEq for MergedLine {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MergedLine {}
+
+impl PartialOrd for MergedLine {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MergedLine {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.time, self.sequence).cmp(&(other.time, other.sequence))
+    }
+}
+
+// Reorders the lines of all files by their timestamps.
+// A line is written once it has been held for the window, before which
+// a line with an earlier timestamp from another file is expected to arrive.
+pub struct Merger {
+    timestamp: Regex,
+    window: Duration,
+    lines: BinaryHeap<Reverse<MergedLine>>,
+    sequence: u64,
+}
+
+impl Merger {
+    pub fn new(timestamp: Regex, window: Duration) -> Merger {
+        Merger {
+            timestamp,
+            window,
+            lines: BinaryHeap::new(),
+            sequence: 0,
+        }
+    }
+
+    // The first capture group is the timestamp if any, otherwise the whole match
+    pub fn timestamp_of(&self, line: &[u8]) -> Option<NaiveDateTime> {
+        let captures = self.timestamp.captures(line)?;
+        let matched = captures.get(1).or_else(|| captures.get(0))?;
+        parse_timestamp(std::str::from_utf8(matched.as_bytes()).ok()?)
+    }
+
+    pub fn push(&mut self, time: Option<NaiveDateTime>, line: Vec<u8>, now: Instant) {
+        self.sequence += 1;
+        self.lines.push(Reverse(MergedLine {
+            time,
+            sequence: self.sequence,
+            received: now,
+            line,
+        }));
+    }
+
+    // Write the lines held for the window, in the order of the timestamps
+    pub fn write_ready<W: Write>(&mut self, writer: &mut W, now: Instant) -> Result<()> {
+        while let Some(Reverse(earliest)) = self.lines.peek() {
+            if now.saturating_duration_since(earliest.received) < self.window {
+                break;
+            }
+            self.write_earliest(writer)?;
+        }
+        Ok(())
+    }
+
+    // Write all the held lines, when no more lines are expected
+    pub fn write_all<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        while !self.lines.is_empty() {
+            self.write_earliest(writer)?;
+        }
+        Ok(())
+    }
+
+    fn write_earliest<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        match self.lines.pop() {
+            Some(Reverse(earliest)) => writer.write_all(&earliest.line),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use chrono::NaiveDate;
+    use regex::bytes::Regex;
+
+    use super::{parse_timestamp, Merger, DEFAULT_TIMESTAMP_REGEX};
+
+    fn merger() -> Merger {
+        Merger::new(
+            Regex::new(DEFAULT_TIMESTAMP_REGEX).unwrap(),
+            Duration::from_millis(100),
+        )
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let date = NaiveDate::from_ymd_opt(2019, 1, 2).unwrap();
+        let expected = date.and_hms_milli_opt(3, 4, 5, 678).unwrap();
+        assert_eq!(parse_timestamp("2019-01-02 03:04:05.678"), Some(expected));
+        assert_eq!(parse_timestamp("2019-01-02T03:04:05,678"), Some(expected));
+        assert_eq!(
+            parse_timestamp("2019-01-02T12:04:05.678+09:00"),
+            Some(expected)
+        );
+        assert_eq!(
+            parse_timestamp("02/Jan/2019:03:04:05 +0000"),
+            date.and_hms_opt(3, 4, 5)
+        );
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn test_merge_in_timestamp_order() {
+        let mut merger = merger();
+        let now = Instant::now();
+        for line in &[
+            "2019-01-02 03:04:06 b\n",
+            "2019-01-02 03:04:05 a\n",
+            "2019-01-02 03:04:07 c\n",
+        ] {
+            let time = merger.timestamp_of(line.as_bytes());
+            merger.push(time, line.as_bytes().to_vec(), now);
+        }
+        let mut output = Vec::new();
+        merger.write_ready(&mut output, now).unwrap();
+        assert!(output.is_empty());
+        merger
+            .write_ready(&mut output, now + Duration::from_millis(100))
+            .unwrap();
+        assert_eq!(
+            output,
+            b"2019-01-02 03:04:05 a\n2019-01-02 03:04:06 b\n2019-01-02 03:04:07 c\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_held_behind_earlier_line() {
+        let mut merger = merger();
+        let now = Instant::now();
+        let later = now + Duration::from_millis(50);
+        let time = merger.timestamp_of(b"2019-01-02 03:04:06 old\n");
+        merger.push(time, b"2019-01-02 03:04:06 old\n".to_vec(), now);
+        let time = merger.timestamp_of(b"2019-01-02 03:04:05 new\n");
+        merger.push(time, b"2019-01-02 03:04:05 new\n".to_vec(), later);
+        let mut output = Vec::new();
+        // The old line waits for the earlier line arrived later
+        merger
+            .write_ready(&mut output, now + Duration::from_millis(100))
+            .unwrap();
+        assert!(output.is_empty());
+        merger.write_all(&mut output).unwrap();
+        assert_eq!(
+            output,
+            b"2019-01-02 03:04:05 new\n2019-01-02 03:04:06 old\n"
+        );
+    }
+}
@@ -14,33 +14,168 @@
  * limitations under the License.
  */
 
-use std::path::PathBuf;
-use std::str::FromStr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use clap::{self, Arg};
-use notify::RecursiveMode;
+use clap::{self, Arg, ArgMatches};
+use encoding_rs::Encoding;
+use regtail::config::Config;
+use regtail::theme::Theme;
+use terminal_size::{terminal_size, Width};
 
-lazy_static! {
-    static ref CURRENT_DIR: PathBuf = PathBuf::from_str(".").unwrap();
-}
+use crate::file_config::FileConfig;
 
 pub struct Opt {
-    pub lines: u64,
-    pub recursive: bool,
-    pub show_binary: bool,
-    depth: Option<usize>,
-    pub regex: Option<String>,
-    path: Option<PathBuf>,
-    pub colorize: bool,
+    pub config: Config,
+    pub check_config: bool,
+    pub print_config: bool,
 }
 
 pub enum ParseError {
     ColorParseFailed,
+    InvalidArgument,
+    InvalidConfigFile,
+    InvalidEnv,
+    InvalidPath,
+    Unsupported,
+}
+
+// Expand the path into the matched directories if it contains wildcards, None otherwise
+fn expand_glob(path: &Path) -> Option<Result<Vec<PathBuf>, String>> {
+    let pattern = path.to_str()?;
+    if !pattern.contains(&['*', '?', '['][..]) {
+        return None;
+    }
+    let entries = match glob::glob(pattern) {
+        Ok(entries) => entries,
+        Err(error) => return Some(Err(format!("invalid glob {}: {}", pattern, error))),
+    };
+    let dirs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|path| path.is_dir())
+        .collect();
+    if dirs.is_empty() {
+        return Some(Err(format!("no directory matches the glob: {}", pattern)));
+    }
+    Some(Ok(dirs))
+}
+
+// Parse permission bits in octal such as 644 or 0o644
+fn parse_mode(value: &str) -> Result<u32, String> {
+    let octal = value.strip_prefix("0o").unwrap_or(value);
+    match u32::from_str_radix(octal, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("must be octal permission bits: {}", value)),
+    }
+}
+
+// Parse a single byte, either a character, an escape like \f or a hex like 0x0c
+fn parse_delimiter(value: &str) -> Result<u8, String> {
+    let hex = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("\\x"));
+    if let Some(hex) = hex {
+        return u8::from_str_radix(hex, 16).map_err(|_| format!("invalid hex byte: {}", value));
+    }
+    match value {
+        "\\n" => Ok(b'\n'),
+        "\\r" => Ok(b'\r'),
+        "\\t" => Ok(b'\t'),
+        "\\f" => Ok(0x0c),
+        "\\v" => Ok(0x0b),
+        "\\0" => Ok(0),
+        _ if value.len() == 1 => Ok(value.as_bytes()[0]),
+        _ => Err(format!("must be a single byte: {}", value)),
+    }
+}
+
+// The flags which may be turned on in the config file, so that they can be turned off again.
+// --hidden has its own --no-hidden.
+const NEGATABLE_FLAGS: &[&str] = &[
+    "notify",
+    "container-logs",
+    "all",
+    "recursive",
+    "show-binary",
+    "inspect-full",
+    "reverse-sort",
+    "bom",
+    "batch-initial",
+    "no-initial",
+    "rotation",
+    "follow-symlinks",
+    "strip-cr",
+    "crlf",
+    "strip-ansi",
+    "sanitize",
+    "squeeze",
+    "count",
+    "raw",
+    "merge",
+    "no-eol-fix",
+    "eof-marker",
+    "line-buffered",
+    "exit-when-empty",
+    "once",
+    "quiet",
+    "verbose",
+    "watch-events",
+    "status",
+    "fair-cache",
+    "poll",
+    "poll-on-error",
+    "reload-on-sighup",
+    "bell",
+    "ignore-case",
+    "basename",
+    "full-match",
+    "follow-renamed",
+    "decompress",
+    "absolute-paths",
+    "dereference",
+    "accept-unknown-host",
+    "header-meta",
+    "hyperlinks",
+    "tag",
+    "prefix",
+    "multicolor",
+];
+
+// The flag turning off the named one, such as --no-bom for --bom and --initial for --no-initial
+fn negation(name: &str) -> String {
+    match name.strip_prefix("no-") {
+        Some(name) => name.to_owned(),
+        None => format!("no-{}", name),
+    }
+}
+
+fn usize_value(matches: &ArgMatches, name: &str, file_value: Option<usize>) -> Option<usize> {
+    match value_t!(matches.value_of(name), usize) {
+        Ok(lines) => Some(lines),
+        Err(e) if e.kind == clap::ErrorKind::ArgumentNotFound => file_value,
+        Err(e) => e.exit(),
+    }
 }
 
 impl Opt {
     pub fn generate() -> Result<Opt, ParseError> {
+        let negations: Vec<(&str, String)> = NEGATABLE_FLAGS
+            .iter()
+            .map(|name| (*name, negation(name)))
+            .collect();
         let matches = app_from_crate!()
+            .after_help("The flags set in the config file can be turned off by --no-<FLAG>, or by --initial and --eol-fix.")
+            .args(
+                &negations
+                    .iter()
+                    .map(|(name, negation)| {
+                        Arg::with_name(negation)
+                            .long(negation)
+                            .overrides_with(name)
+                            .hidden(true)
+                    })
+                    .collect::<Vec<_>>(),
+            )
             .arg(
                 Arg::with_name("recursive")
                     .short("r")
@@ -52,6 +187,366 @@ impl Opt {
                     .long("show-binary")
                     .help("Enable binary tailing"),
             )
+            .arg(
+                Arg::with_name("inspect-bytes")
+                    .long("inspect-bytes")
+                    .value_name("N")
+                    .help("Bytes from the beginning of each file to tell whether it is binary")
+                    .validator(|value| match value.parse::<usize>() {
+                        Ok(bytes) if bytes > 0 => Ok(()),
+                        _ => Err(String::from("must be a positive integer")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("inspect-full")
+                    .long("inspect-full")
+                    .conflicts_with("inspect-bytes")
+                    .help("Search the entire file for the null byte to tell whether it is binary"),
+            )
+            .arg(
+                Arg::with_name("hidden")
+                    .long("hidden")
+                    .overrides_with("no-hidden")
+                    .help("Include files and directories whose name starts with a dot"),
+            )
+            .arg(
+                Arg::with_name("no-hidden")
+                    .long("no-hidden")
+                    .overrides_with("hidden")
+                    .help("Exclude files and directories whose name starts with a dot [default]"),
+            )
+            .arg(
+                Arg::with_name("reverse-sort")
+                    .long("reverse-sort")
+                    .help("Show the initial lines of the files in the descending order of the paths"),
+            )
+            .arg(
+                Arg::with_name("batch-initial")
+                    .long("batch-initial")
+                    .help("Flush the initial output of all files at once"),
+            )
+            .arg(
+                Arg::with_name("no-initial")
+                    .long("no-initial")
+                    .help("Print nothing on startup, even the content saved by --state-file"),
+            )
+            .arg(
+                Arg::with_name("rotation")
+                    .long("rotation")
+                    .help("Print the rotated files such as app.log.1 before app.log as one file"),
+            )
+            .arg(
+                Arg::with_name("follow-symlinks")
+                    .long("follow-symlinks")
+                    .help("Walk into the symbolic links to directories"),
+            )
+            .arg(
+                Arg::with_name("container-logs")
+                    .long("container-logs")
+                    .help("Follow the container logs such as /var/log/containers as one stream per container, implies --recursive, --rotation and --follow-symlinks"),
+            )
+            .arg(
+                Arg::with_name("delimiter")
+                    .long("delimiter")
+                    .value_name("BYTE")
+                    .help("Line delimiter, a character, an escape like \\f or a hex like 0x0c")
+                    .validator(|value| parse_delimiter(&value).map(|_| ()))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("strip-cr")
+                    .long("strip-cr")
+                    .help("Remove the carriage return at the end of each line"),
+            )
+            .arg(
+                Arg::with_name("crlf")
+                    .long("crlf")
+                    .help("End each output line with CRLF"),
+            )
+            .arg(
+                Arg::with_name("strip-ansi")
+                    .long("strip-ansi")
+                    .help("Remove ANSI escape codes contained in the content"),
+            )
+            .arg(
+                Arg::with_name("sanitize")
+                    .long("sanitize")
+                    .help("Replace non-printable bytes with U+FFFD instead of skipping binary files"),
+            )
+            .arg(
+                Arg::with_name("squeeze")
+                    .long("squeeze")
+                    .help("Collapse consecutive identical lines"),
+            )
+            .arg(
+                Arg::with_name("wrap")
+                    .long("wrap")
+                    .value_name("COLS")
+                    .help("Hard wrap the lines at the columns, or at the terminal width if auto, only on a terminal")
+                    .validator(|value| match value.parse::<usize>() {
+                        _ if value == "auto" => Ok(()),
+                        Ok(columns) if columns > 0 => Ok(()),
+                        _ => Err(String::from("must be auto or a positive integer")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("wrap-indent")
+                    .long("wrap-indent")
+                    .value_name("N")
+                    .help("Indent the continued lines of a wrapped line by the spaces")
+                    .requires("wrap")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("grep")
+                    .long("grep")
+                    .value_name("PATTERN")
+                    .help("Show only the lines matching the regex")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("before-context")
+                    .short("B")
+                    .long("before-context")
+                    .value_name("NUM")
+                    .help("Lines to show before each line matching --grep")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("after-context")
+                    .short("A")
+                    .long("after-context")
+                    .value_name("NUM")
+                    .help("Lines to show after each line matching --grep")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("count")
+                    .long("count")
+                    .help("Print the number of new lines of each file every second instead, all the lines with --once"),
+            )
+            .arg(
+                Arg::with_name("raw")
+                    .long("raw")
+                    .help("Concatenate the bytes of the files without headers, separators and added line feeds"),
+            )
+            .arg(
+                Arg::with_name("merge")
+                    .long("merge")
+                    .help("Interleave the lines of all files in the order of their timestamps"),
+            )
+            .arg(
+                Arg::with_name("timestamp-regex")
+                    .long("timestamp-regex")
+                    .value_name("RE")
+                    .requires("merge")
+                    .help("Regex matching the timestamp to merge by, the first group if any")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("eof-marker")
+                    .long("eof-marker")
+                    .help("Print <== EOF path ==> when a followed file is removed or renamed away"),
+            )
+            .arg(
+                Arg::with_name("no-eol-fix")
+                    .long("no-eol-fix")
+                    .help("Do not add a line feed to an incomplete line when switching the files"),
+            )
+            .arg(
+                Arg::with_name("line-buffered")
+                    .long("line-buffered")
+                    .help("Flush the output after every line, which is slower for heavy output"),
+            )
+            .arg(
+                Arg::with_name("max-rate")
+                    .long("max-rate")
+                    .value_name("LINES_PER_SEC")
+                    .help("Maximum lines to output per second, excess lines are suppressed")
+                    .validator(|value| match value.parse::<u64>() {
+                        Ok(rate) if rate > 0 => Ok(()),
+                        _ => Err(String::from("must be a positive integer")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("bell")
+                    .long("bell")
+                    .help("Ring the bell when a line matches the --grep pattern on a terminal"),
+            )
+            .arg(
+                Arg::with_name("notify")
+                    .long("notify")
+                    .help("Show a desktop notification when a line matches the --grep pattern"),
+            )
+            .arg(
+                Arg::with_name("flush-interval")
+                    .long("flush-interval")
+                    .value_name("MS")
+                    .help("Maximum milliseconds to buffer the output, 0 flushes after every write")
+                    .validator(|value| {
+                        value
+                            .parse::<u64>()
+                            .map(|_| ())
+                            .map_err(|_| String::from("must be a non-negative integer"))
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("poll")
+                    .long("poll")
+                    .help("Detect changes by polling instead of the OS notification"),
+            )
+            .arg(
+                Arg::with_name("poll-on-error")
+                    .long("poll-on-error")
+                    .help("Fall back to polling if the OS notification cannot be used"),
+            )
+            .arg(
+                Arg::with_name("reload-on-sighup")
+                    .long("reload-on-sighup")
+                    .help("Reopen the files and walk the directories again on SIGHUP, e.g. after a rotation"),
+            )
+            .arg(
+                Arg::with_name("self-heal")
+                    .long("self-heal")
+                    .value_name("SECONDS")
+                    .help("Watch again if no events arrive for the interval while the files are modified")
+                    .validator(|value| match value.parse::<u64>() {
+                        Ok(seconds) if seconds > 0 => Ok(()),
+                        _ => Err(String::from("must be a positive integer")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("idle-timeout")
+                    .long("idle-timeout")
+                    .value_name("SECONDS")
+                    .help("Release a file with no writes for the interval until it is written again")
+                    .validator(|value| match value.parse::<u64>() {
+                        Ok(seconds) if seconds > 0 => Ok(()),
+                        _ => Err(String::from("must be a positive integer")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("interval")
+                    .long("interval")
+                    .value_name("MS")
+                    .help("Wake up at the interval for the periodic work [default: 1000]")
+                    .validator(|value| match value.parse::<u64>() {
+                        Ok(interval) if interval > 0 => Ok(()),
+                        _ => Err(String::from("must be a positive integer")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("debounce")
+                    .long("debounce")
+                    .value_name("MS")
+                    .help("Merge the events of a file within the milliseconds")
+                    .validator(|value| match value.parse::<u64>() {
+                        Ok(delay) if delay > 0 => Ok(()),
+                        _ => Err(String::from("must be a positive integer")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("exit-when-empty")
+                    .long("exit-when-empty")
+                    .help("Exit when all the followed files are removed"),
+            )
+            .arg(
+                Arg::with_name("once")
+                    .long("once")
+                    .alias("batch")
+                    .help("Print the tail of the matched files and exit without following"),
+            )
+            .arg(
+                Arg::with_name("max-output")
+                    .long("max-output")
+                    .value_name("BYTES")
+                    .help("Exit once the total output reaches the bytes")
+                    .validator(|value| {
+                        value
+                            .parse::<u64>()
+                            .map(|_| ())
+                            .map_err(|_| String::from("must be a non-negative integer"))
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("max-scan-bytes")
+                    .long("max-scan-bytes")
+                    .value_name("BYTES")
+                    .help("Scan at most the bytes backward for the initial lines of each file")
+                    .validator(|value| match value.parse::<u64>() {
+                        Ok(bytes) if bytes > 0 => Ok(()),
+                        _ => Err(String::from("must be a positive integer")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("max-read-per-event")
+                    .long("max-read-per-event")
+                    .value_name("BYTES")
+                    .help("Read at most the bytes of a file at once, so that the other files are not kept waiting")
+                    .validator(|value| match value.parse::<u64>() {
+                        Ok(bytes) if bytes > 0 => Ok(()),
+                        _ => Err(String::from("must be a positive integer")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("heartbeat")
+                    .long("heartbeat")
+                    .value_name("SECONDS")
+                    .help("Print a message to stderr when no data arrives for the interval")
+                    .validator(|value| match value.parse::<u64>() {
+                        Ok(seconds) if seconds > 0 => Ok(()),
+                        _ => Err(String::from("must be a positive integer")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("quiet")
+                    .short("q")
+                    .long("quiet")
+                    .help("Suppress informational messages such as the heartbeat"),
+            )
+            .arg(
+                Arg::with_name("verbose")
+                    .short("v")
+                    .long("verbose")
+                    .help("Explain why files are skipped"),
+            )
+            .arg(
+                Arg::with_name("watch-events")
+                    .long("watch-events")
+                    .help("Log every event of the file system watcher on stderr for diagnosis"),
+            )
+            .arg(
+                Arg::with_name("status")
+                    .long("status")
+                    .help("Show the number of watched files on stderr"),
+            )
+            .arg(
+                Arg::with_name("fair-cache")
+                    .long("fair-cache")
+                    .help("Keep an open file of every directory instead of the most recently written ones"),
+            )
+            .arg(
+                Arg::with_name("check-config")
+                    .long("check-config")
+                    .help("Validate the options and exit without following"),
+            )
+            .arg(
+                Arg::with_name("print-config")
+                    .long("print-config")
+                    .help("Print the options resolved from the command line, the environment and the config file as JSON"),
+            )
             .arg(
                 Arg::with_name("regex")
                     .short("e")
@@ -60,13 +555,77 @@ impl Opt {
                     .allow_hyphen_values(true)
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("ext")
+                    .long("ext")
+                    .value_name("EXTENSIONS")
+                    .help("Comma separated extensions of target files such as log,txt")
+                    .use_delimiter(true)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("content-match")
+                    .long("content-match")
+                    .value_name("PATTERN")
+                    .help("Follow only the files whose first bytes match the regex on startup, the skipped and empty ones once written")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("owner")
+                    .long("owner")
+                    .value_name("USER")
+                    .help("Follow only the files owned by the user name or uid (Unix only)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("mode")
+                    .long("mode")
+                    .value_name("MASK")
+                    .help("Follow only the files having all the octal permission bits (Unix only)")
+                    .validator(|value| parse_mode(&value).map(|_| ()))
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("ignore-case")
+                    .short("i")
+                    .long("ignore-case")
+                    .help("Match the regex and the extensions case insensitively"),
+            )
+            .arg(
+                Arg::with_name("basename")
+                    .long("basename")
+                    .help("Match the regex against the file name instead of the whole path"),
+            )
+            .arg(
+                Arg::with_name("full-match")
+                    .long("full-match")
+                    .help("Match the regex against the whole path or file name instead of a part"),
+            )
+            .arg(
+                Arg::with_name("follow-renamed")
+                    .long("follow-renamed")
+                    .help("Keep following a file renamed to a path not matching the regex"),
+            )
+            .arg(
+                Arg::with_name("regex-size-limit")
+                    .long("regex-size-limit")
+                    .value_name("BYTES")
+                    .help("Maximum size of the compiled regex, raise it for a huge regex")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name("path")
                     .short("p")
                     .long("path")
-                    .help("Target directory to process")
+                    .help("Target directory to process, or - for the standard input")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("stdin")
+                    .long("stdin")
+                    .help("Tail the standard input instead of the files, same as -p -")
+                    .conflicts_with_all(&["path", "PATH"]),
+            )
             .arg(
                 Arg::with_name("depth")
                     .short("d")
@@ -74,13 +633,50 @@ impl Opt {
                     .requires("recursive")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("max-files")
+                    .long("max-files")
+                    .value_name("N")
+                    .help("Follow at most N files, the others are skipped")
+                    .validator(|value| match value.parse::<usize>() {
+                        Ok(max_files) if max_files > 0 => Ok(()),
+                        _ => Err(String::from("must be a positive integer")),
+                    })
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name("lines")
                     .short("l")
-                    .help("Lines to show")
+                    .help("Lines to show, or \"all\" to show the entire content")
                     .default_value("10")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("lines-config")
+                    .long("lines-config")
+                    .value_name("FILE")
+                    .help("File of PATTERN N lines to show N lines of the matched files on startup")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("combined-lines")
+                    .long("combined-lines")
+                    .value_name("N")
+                    .help("Show the last N lines of all the files combined, ordered by file then position")
+                    .validator(|value| {
+                        value
+                            .parse::<u64>()
+                            .map(|_| ())
+                            .map_err(|_| String::from("must be a non-negative integer"))
+                    })
+                    .conflicts_with("all")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("all")
+                    .long("all")
+                    .help("Show the entire content of each file on startup, same as -l all"),
+            )
             .arg(
                 Arg::with_name("REGEX")
                     .help("Regex to filter target files")
@@ -105,60 +701,519 @@ impl Opt {
                     .possible_values(&["auto", "never", "always"])
                     .help("Colorize mode"),
             )
+            .arg(
+                Arg::with_name("separator")
+                    .long("separator")
+                    .value_name("STR")
+                    .help("Line printed between files, empty for none [default: blank line]")
+                    .allow_hyphen_values(true)
+                    .empty_values(true)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("tee")
+                    .long("tee")
+                    .value_name("FILE")
+                    .help("Also write the output to the file without colors")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("bom")
+                    .long("bom")
+                    .requires("tee")
+                    .help("Start the tee file with the UTF-8 BOM"),
+            )
+            .arg(
+                Arg::with_name("snapshot")
+                    .long("snapshot")
+                    .value_name("DIR")
+                    .help("Write the initial lines of each file to DIR/<path>.tail on startup, the separators in the path as %2F")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("socket")
+                    .long("socket")
+                    .value_name("PATH")
+                    .help("Also send each line as a length-prefixed JSON message to the clients of the Unix socket")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("encoding")
+                    .long("encoding")
+                    .value_name("NAME")
+                    .help("Read the files in the encoding such as shift_jis, instead of detecting text")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("decompress")
+                    .long("decompress")
+                    .help("Follow the files compressed in xz or bzip2 as their decompressed content"),
+            )
+            .arg(
+                Arg::with_name("state-file")
+                    .long("state-file")
+                    .value_name("FILE")
+                    .help(
+                        "Save the offsets read so far to the file and resume from them on restart",
+                    )
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("accept-unknown-host")
+                    .long("accept-unknown-host")
+                    .help("Connect to an sftp:// host which is not in ~/.ssh/known_hosts"),
+            )
+            .arg(
+                Arg::with_name("absolute-paths")
+                    .long("absolute-paths")
+                    .help("Show the absolute path of each file in the header"),
+            )
+            .arg(
+                Arg::with_name("dereference")
+                    .long("dereference")
+                    .help("Show the target of a symbolic link in the header instead of the link"),
+            )
+            .arg(
+                Arg::with_name("relative-to")
+                    .long("relative-to")
+                    .value_name("DIR")
+                    .help("Show the path in the header relative to DIR instead of the current directory")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("truncate-header")
+                    .long("truncate-header")
+                    .value_name("N")
+                    .help("Show only the last N characters of a longer path in the header")
+                    .validator(|value| match value.parse::<usize>() {
+                        Ok(max_chars) if max_chars > 0 => Ok(()),
+                        _ => Err(String::from("must be a positive integer")),
+                    })
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("strip-prefix")
+                    .long("strip-prefix")
+                    .value_name("P")
+                    .help("Remove the leading directories P from the path in the header")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("header-meta")
+                    .long("header-meta")
+                    .help("Show the size and the last modified time of each file in the header"),
+            )
+            .arg(
+                Arg::with_name("hyperlinks")
+                    .long("hyperlinks")
+                    .help("Make the path in the header a link to the file if colorized"),
+            )
+            .arg(
+                Arg::with_name("tag")
+                    .long("tag")
+                    .help("Prefix each line with the shortest path suffix telling its file from the others"),
+            )
+            .arg(
+                Arg::with_name("prefix")
+                    .long("prefix")
+                    .help("Prefix each line with the path of its file, after the tag with --tag"),
+            )
+            .arg(
+                Arg::with_name("highlight")
+                    .long("highlight")
+                    .value_name("PATTERN=COLOR")
+                    .help("Color the matches of the pattern in the content, such as ERROR=red")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("multicolor")
+                    .long("multicolor")
+                    .help("Color the header of each file differently"),
+            )
+            .arg(
+                Arg::with_name("theme")
+                    .long("theme")
+                    .value_name("NAME")
+                    .possible_values(&Theme::NAMES)
+                    .help("Colors of the headers, light for a light background")
+                    .takes_value(true),
+            )
             .get_matches();
-        let color_mode = matches.value_of("color").unwrap_or("auto");
+        // Precedence: command line > environment > config file > built-in defaults
+        let mut file_config = FileConfig::load().map_err(|error| {
+            eprintln!("{}", error);
+            ParseError::InvalidConfigFile
+        })?;
+        file_config.override_with_env().map_err(|error| {
+            eprintln!("{}", error);
+            ParseError::InvalidEnv
+        })?;
+        let color_mode = matches
+            .value_of("color")
+            .or_else(|| file_config.color.as_ref().map(|color| color.as_str()))
+            .unwrap_or("auto");
         let colorize = match color_mode {
             "auto" => Ok(atty::is(atty::Stream::Stdout)),
             "never" => Ok(false),
             "always" => Ok(true),
             _ => Err(ParseError::ColorParseFailed),
         }?;
-        Ok(Opt {
-            lines: value_t!(matches, "lines", u64).unwrap_or_else(|e| e.exit()),
-            recursive: matches.is_present("recursive"),
-            show_binary: matches.is_present("show-binary"),
-            depth: value_t!(matches.value_of("depth"), usize)
-                .map(Some)
-                .unwrap_or_else(|e| {
-                    if e.kind == clap::ErrorKind::ArgumentNotFound {
-                        None
-                    } else {
-                        e.exit()
-                    }
-                }),
-            regex: matches
-                .value_of("regex")
-                .map(|x| x.to_owned())
-                .or_else(|| matches.value_of("REGEX").map(|x| x.to_owned())),
-            path: matches
-                .value_of_os("path")
-                .map(PathBuf::from)
-                .or_else(|| matches.value_of_os("PATH").map(PathBuf::from)),
-            colorize,
-        })
-    }
-
-    pub fn recursive_mode(self: &Opt) -> RecursiveMode {
-        if self.recursive {
-            RecursiveMode::Recursive
-        } else {
-            RecursiveMode::NonRecursive
+        // Wrapping is for the terminal, so the piped output is left as it is
+        let wrap_value = matches.value_of("wrap").or(file_config.wrap.as_deref());
+        let wrap_columns = match wrap_value {
+            Some("auto") => terminal_size().map(|(Width(width), _)| width as usize),
+            Some(columns) => match columns.parse::<usize>() {
+                Ok(columns) if columns > 0 => Some(columns),
+                _ => {
+                    eprintln!("invalid wrap: {}", columns);
+                    return Err(ParseError::InvalidArgument);
+                }
+            },
+            None => None,
+        };
+        let wrap_indent =
+            usize_value(&matches, "wrap-indent", file_config.wrap_indent).unwrap_or(0);
+        // The continued lines must have room for a character after the indent
+        if let Some(columns) = wrap_columns {
+            if wrap_indent >= columns {
+                eprintln!(
+                    "invalid wrap indent: {} is not less than the columns {}",
+                    wrap_indent, columns
+                );
+                return Err(ParseError::InvalidArgument);
+            }
         }
-    }
-
-    pub fn watch_path(self: &Opt) -> &PathBuf {
-        self.path.as_ref().unwrap_or(&CURRENT_DIR)
-    }
-
-    pub fn watch_path_is_dir(self: &Opt) -> bool {
-        self.watch_path().is_dir()
-    }
-
-    pub fn depth(self: &Opt) -> Option<usize> {
-        if self.recursive {
-            self.depth
+        let wrap = wrap_columns.filter(|_| atty::is(atty::Stream::Stdout));
+        // -l has a default value, so it is only preferred when explicitly supplied
+        let lines = match file_config.lines {
+            Some(lines) if matches.occurrences_of("lines") == 0 => Some(lines),
+            _ if matches.value_of("lines") == Some("all") => None,
+            _ => Some(value_t!(matches, "lines", u64).unwrap_or_else(|e| e.exit())),
+        };
+        // The negation on the command line wins over the config file too
+        let flag = |name: &str, file_value: Option<bool>| {
+            !matches.is_present(negation(name))
+                && (matches.is_present(name) || file_value.unwrap_or(false))
+        };
+        let delimiter = match matches
+            .value_of("delimiter")
+            .or(file_config.delimiter.as_deref())
+        {
+            Some(delimiter) => Some(parse_delimiter(delimiter).map_err(|error| {
+                eprintln!("invalid delimiter: {}", error);
+                ParseError::InvalidArgument
+            })?),
+            None => None,
+        };
+        let mode = match matches.value_of("mode").or(file_config.mode.as_deref()) {
+            Some(mode) => Some(parse_mode(mode).map_err(|error| {
+                eprintln!("invalid mode: {}", error);
+                ParseError::InvalidArgument
+            })?),
+            None => None,
+        };
+        let encoding = match matches
+            .value_of("encoding")
+            .or(file_config.encoding.as_deref())
+        {
+            Some(label) => Some(Encoding::for_label(label.as_bytes()).ok_or_else(|| {
+                eprintln!("unknown encoding: {}", label);
+                ParseError::InvalidArgument
+            })?),
+            None => None,
+        };
+        let theme = match matches.value_of("theme").or(file_config.theme.as_deref()) {
+            Some(name) => Theme::from_name(name).ok_or_else(|| {
+                eprintln!("unknown theme: {}", name);
+                ParseError::InvalidArgument
+            })?,
+            None => Theme::default(),
+        };
+        let desktop_notification = flag("notify", file_config.notify);
+        if desktop_notification && !cfg!(feature = "desktop-notification") {
+            eprintln!("desktop notification requires the desktop-notification feature");
+            return Err(ParseError::Unsupported);
+        }
+        // The container logs are symbolic links to the rotating logs of each restart
+        let container_logs = flag("container-logs", file_config.container_logs);
+        let mut builder = Config::builder()
+            .all(lines.is_none() || flag("all", file_config.all))
+            .recursive(container_logs || flag("recursive", file_config.recursive))
+            .show_binary(flag("show-binary", file_config.show_binary))
+            .inspect_full(flag("inspect-full", file_config.inspect_full))
+            .hidden(flag("hidden", file_config.hidden))
+            .reverse_sort(flag("reverse-sort", file_config.reverse_sort))
+            .mode(mode)
+            .bom(flag("bom", file_config.bom))
+            .batch_initial(flag("batch-initial", file_config.batch_initial))
+            .no_initial(flag("no-initial", file_config.no_initial))
+            .rotation(container_logs || flag("rotation", file_config.rotation))
+            .follow_symlinks(container_logs || flag("follow-symlinks", file_config.follow_symlinks))
+            .container_logs(container_logs)
+            .strip_cr(flag("strip-cr", file_config.strip_cr))
+            .crlf(flag("crlf", file_config.crlf))
+            .strip_ansi(flag("strip-ansi", file_config.strip_ansi))
+            .sanitize(flag("sanitize", file_config.sanitize))
+            .squeeze(flag("squeeze", file_config.squeeze))
+            .wrap(wrap)
+            .wrap_indent(wrap_indent)
+            .count(flag("count", file_config.count))
+            .raw(flag("raw", file_config.raw))
+            .merge(flag("merge", file_config.merge))
+            .no_eol_fix(flag("no-eol-fix", file_config.no_eol_fix))
+            .eof_marker(flag("eof-marker", file_config.eof_marker))
+            .line_buffered(flag("line-buffered", file_config.line_buffered))
+            .exit_when_empty(flag("exit-when-empty", file_config.exit_when_empty))
+            .once(flag("once", file_config.once))
+            .max_output(
+                value_t!(matches, "max-output", u64)
+                    .ok()
+                    .or(file_config.max_output),
+            )
+            .max_scan_bytes(
+                value_t!(matches, "max-scan-bytes", u64)
+                    .ok()
+                    .or(file_config.max_scan_bytes),
+            )
+            .max_read_per_event(
+                value_t!(matches, "max-read-per-event", u64)
+                    .ok()
+                    .or(file_config.max_read_per_event),
+            )
+            .quiet(flag("quiet", file_config.quiet))
+            .verbose(flag("verbose", file_config.verbose))
+            .watch_events(flag("watch-events", file_config.watch_events))
+            .status(flag("status", file_config.status))
+            .fair_cache(flag("fair-cache", file_config.fair_cache))
+            .heartbeat(
+                value_t!(matches, "heartbeat", u64)
+                    .ok()
+                    .or(file_config.heartbeat)
+                    .map(Duration::from_secs),
+            )
+            .poll(flag("poll", file_config.poll))
+            .poll_on_error(flag("poll-on-error", file_config.poll_on_error))
+            .reload_on_sighup(flag("reload-on-sighup", file_config.reload_on_sighup))
+            .self_heal(
+                value_t!(matches, "self-heal", u64)
+                    .ok()
+                    .or(file_config.self_heal)
+                    .map(Duration::from_secs),
+            )
+            .idle_timeout(
+                value_t!(matches, "idle-timeout", u64)
+                    .ok()
+                    .or(file_config.idle_timeout)
+                    .map(Duration::from_secs),
+            )
+            .interval(
+                value_t!(matches, "interval", u64)
+                    .ok()
+                    .or(file_config.interval)
+                    .map(Duration::from_millis),
+            )
+            .debounce(
+                value_t!(matches, "debounce", u64)
+                    .ok()
+                    .or(file_config.debounce)
+                    .map(Duration::from_millis),
+            )
+            .flush_interval(
+                value_t!(matches, "flush-interval", u64)
+                    .ok()
+                    .or(file_config.flush_interval)
+                    .map(Duration::from_millis),
+            )
+            // The bell is for the terminal, so the piped output is left as it is
+            .bell(flag("bell", file_config.bell) && atty::is(atty::Stream::Stdout))
+            .desktop_notification(desktop_notification)
+            .max_rate(
+                value_t!(matches, "max-rate", u64)
+                    .ok()
+                    .or(file_config.max_rate),
+            )
+            .depth(
+                value_t!(matches.value_of("depth"), usize)
+                    .map(Some)
+                    .unwrap_or_else(|e| {
+                        if e.kind == clap::ErrorKind::ArgumentNotFound {
+                            file_config.depth
+                        } else {
+                            e.exit()
+                        }
+                    }),
+            )
+            .max_files(usize_value(&matches, "max-files", file_config.max_files))
+            .regex_size_limit(usize_value(
+                &matches,
+                "regex-size-limit",
+                file_config.regex_size_limit,
+            ))
+            .ignore_case(flag("ignore-case", file_config.ignore_case))
+            .basename(flag("basename", file_config.basename))
+            .full_match(flag("full-match", file_config.full_match))
+            .follow_renamed(flag("follow-renamed", file_config.follow_renamed))
+            .decompress(flag("decompress", file_config.decompress))
+            .absolute_paths(flag("absolute-paths", file_config.absolute_paths))
+            .dereference(flag("dereference", file_config.dereference))
+            .accept_unknown_host(flag("accept-unknown-host", file_config.accept_unknown_host))
+            .truncate_header(usize_value(
+                &matches,
+                "truncate-header",
+                file_config.truncate_header,
+            ))
+            .header_meta(flag("header-meta", file_config.header_meta))
+            .hyperlinks(flag("hyperlinks", file_config.hyperlinks))
+            .tag(flag("tag", file_config.tag))
+            .prefix(flag("prefix", file_config.prefix))
+            .combined_lines(
+                value_t!(matches, "combined-lines", u64)
+                    .ok()
+                    .or(file_config.combined_lines),
+            )
+            .colorize(colorize)
+            .multicolor(flag("multicolor", file_config.multicolor))
+            .theme(theme);
+        if let Some(lines) = lines {
+            builder = builder.lines(lines);
+        }
+        if let Some(delimiter) = delimiter {
+            builder = builder.delimiter(delimiter);
+        }
+        if let Some(extensions) = matches.values_of("ext") {
+            builder = builder.extensions(extensions.collect());
+        } else if let Some(extensions) = file_config.ext.clone() {
+            builder = builder.extensions(extensions);
+        }
+        if let Some(pattern) = matches
+            .value_of("content-match")
+            .or(file_config.content_match.as_deref())
+        {
+            builder = builder.content_match(pattern);
+        }
+        if let Some(owner) = matches.value_of("owner").or(file_config.owner.as_deref()) {
+            builder = builder.owner(owner);
+        }
+        if let Some(highlights) = matches.values_of("highlight") {
+            builder = builder.highlights(highlights.collect());
+        } else if let Some(highlights) = file_config.highlight.clone() {
+            builder = builder.highlights(highlights);
+        }
+        if let Some(separator) = matches
+            .value_of("separator")
+            .or(file_config.separator.as_deref())
+        {
+            builder = builder.separator(separator);
+        }
+        if let Some(tee) = matches
+            .value_of_os("tee")
+            .map(PathBuf::from)
+            .or_else(|| file_config.tee.clone())
+        {
+            builder = builder.tee(tee);
+        }
+        if let Some(dir) = matches
+            .value_of_os("snapshot")
+            .map(PathBuf::from)
+            .or_else(|| file_config.snapshot.clone())
+        {
+            builder = builder.snapshot(dir);
+        }
+        if let Some(dir) = matches
+            .value_of_os("relative-to")
+            .map(PathBuf::from)
+            .or_else(|| file_config.relative_to.clone())
+        {
+            builder = builder.relative_to(dir);
+        }
+        if let Some(prefix) = matches
+            .value_of_os("strip-prefix")
+            .map(PathBuf::from)
+            .or_else(|| file_config.strip_prefix.clone())
+        {
+            builder = builder.strip_prefix(prefix);
+        }
+        if let Some(socket) = matches
+            .value_of_os("socket")
+            .map(PathBuf::from)
+            .or_else(|| file_config.socket.clone())
+        {
+            if !cfg!(unix) {
+                eprintln!("--socket is supported only on Unix");
+                return Err(ParseError::Unsupported);
+            }
+            builder = builder.socket(socket);
+        }
+        if let Some(encoding) = encoding {
+            builder = builder.encoding(encoding);
+        }
+        if let Some(lines_config) = matches
+            .value_of_os("lines-config")
+            .map(PathBuf::from)
+            .or_else(|| file_config.lines_config.clone())
+        {
+            builder = builder.lines_config(lines_config);
+        }
+        if let Some(state_file) = matches
+            .value_of_os("state-file")
+            .map(PathBuf::from)
+            .or_else(|| file_config.state_file.clone())
+        {
+            builder = builder.state_file(state_file);
+        }
+        if let Some(pattern) = matches
+            .value_of("timestamp-regex")
+            .or(file_config.timestamp_regex.as_deref())
+        {
+            builder = builder.timestamp_regex(pattern);
+        }
+        if let Some(pattern) = matches.value_of("grep").or(file_config.grep.as_deref()) {
+            builder = builder.grep(pattern);
+        }
+        if let Some(bytes) = usize_value(&matches, "inspect-bytes", file_config.inspect_bytes) {
+            builder = builder.inspect_bytes(bytes);
+        }
+        if let Some(lines) = usize_value(&matches, "before-context", file_config.before_context) {
+            builder = builder.before_context(lines);
+        }
+        if let Some(lines) = usize_value(&matches, "after-context", file_config.after_context) {
+            builder = builder.after_context(lines);
+        }
+        if let Some(regex) = matches
+            .value_of("regex")
+            .or_else(|| matches.value_of("REGEX"))
+            .or(file_config.regex.as_deref())
+        {
+            builder = builder.regex(regex);
+        }
+        // The standard input is read only if asked, a service may run with anything there
+        let path = if matches.is_present("stdin") {
+            Some(PathBuf::from("-"))
         } else {
-            Some(1)
+            matches
+                .value_of_os("path")
+                .or_else(|| matches.value_of_os("PATH"))
+                .map(PathBuf::from)
+                .or_else(|| file_config.path.clone())
+        };
+        match path {
+            Some(path) if path.as_os_str() == "-" => builder = builder.stdin(true),
+            Some(path) => match expand_glob(&path) {
+                Some(Ok(dirs)) => builder = builder.paths(dirs),
+                Some(Err(error)) => {
+                    eprintln!("{}", error);
+                    return Err(ParseError::InvalidPath);
+                }
+                None => builder = builder.path(path),
+            },
+            None => {}
         }
+        Ok(Opt {
+            config: builder.build(),
+            check_config: matches.is_present("check-config"),
+            print_config: matches.is_present("print-config"),
+        })
     }
 }
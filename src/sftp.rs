@@ -0,0 +1,598 @@
+/*
+ * Copyright 2019 StoneDot (Hiroaki Goto)
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Follow the files under a remote directory over SFTP, such as sftp://user@host/var/log.
+// Remote changes cannot be notified, so the directory is walked again at every poll.
+// Binary files are not detected and the headers are never colorized. The options which
+// depend on the local files or on the local watcher are rejected, see unsupported_options.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::thread::sleep;
+use std::time::Duration;
+
+use regex::Regex;
+use ssh2::{CheckResult, KnownHostFileKind, Session, Sftp};
+
+use crate::cache::HandleCache;
+use crate::config::Config;
+use crate::error::RegtailError;
+use crate::filter::{is_hidden_name, PathFilter};
+use crate::line::LineOptions;
+use crate::tail::{
+    tail_from_reader, Length, ReaderCreator, SharedWriter, TailState, TransparentReader,
+};
+
+const DEFAULT_PORT: u16 = 22;
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// The server limits the open handles per session
+const MAX_REMOTE_HANDLE: usize = 64;
+// Tried in order if the agent cannot authenticate
+const KEY_FILES: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+
+#[derive(Debug, PartialEq)]
+pub struct SftpUrl {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: u16,
+    pub path: PathBuf,
+}
+
+impl SftpUrl {
+    // sftp://[user@]host[:port]/path, None if the path is not an SFTP URL
+    pub fn parse(path: &Path) -> Option<SftpUrl> {
+        let rest = path.to_str()?.strip_prefix("sftp://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        let (user, host) = match authority.rsplit_once('@') {
+            Some((user, host)) => (Some(user.to_owned()), host),
+            None => (None, authority),
+        };
+        let (host, port) = match host.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().ok()?),
+            None => (host, DEFAULT_PORT),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        Some(SftpUrl {
+            user,
+            host: host.to_owned(),
+            port,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+impl Display for SftpUrl {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.user {
+            Some(user) => write!(f, "{}@{}", user, self.host)?,
+            None => write!(f, "{}", self.host)?,
+        }
+        if self.port != DEFAULT_PORT {
+            write!(f, ":{}", self.port)?;
+        }
+        write!(f, "{}", self.path.display())
+    }
+}
+
+// Operations on the remote files, implemented by the SFTP session and by a mock in the tests
+pub trait RemoteFs {
+    type File: Read + Seek;
+
+    // Entries of the directory with whether each one is a directory
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(PathBuf, bool)>>;
+    fn open(&self, path: &Path) -> io::Result<Self::File>;
+    fn len(&self, path: &Path) -> io::Result<u64>;
+}
+
+pub struct SftpFs {
+    // Closing the session invalidates the channel, so it is kept with it
+    _session: Session,
+    sftp: Sftp,
+}
+
+impl SftpFs {
+    // Authenticated by the agent, then by the default keys under ~/.ssh,
+    // after the host key is checked against ~/.ssh/known_hosts
+    pub fn connect(url: &SftpUrl, accept_unknown_host: bool) -> io::Result<SftpFs> {
+        let stream = TcpStream::connect((url.host.as_str(), url.port))?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(stream);
+        session.handshake()?;
+        Self::check_host_key(&session, url, accept_unknown_host)?;
+        let user = match &url.user {
+            Some(user) => user.to_owned(),
+            None => std::env::var("USER").map_err(|_| {
+                io::Error::new(ErrorKind::InvalidInput, "no user in the URL nor $USER")
+            })?,
+        };
+        if session.userauth_agent(&user).is_err() {
+            let ssh_dir = std::env::var_os("HOME").map(|home| Path::new(&home).join(".ssh"));
+            for key_file in ssh_dir
+                .iter()
+                .flat_map(|dir| KEY_FILES.iter().map(move |key| dir.join(key)))
+            {
+                if key_file.exists()
+                    && session
+                        .userauth_pubkey_file(&user, None, &key_file, None)
+                        .is_ok()
+                {
+                    break;
+                }
+            }
+        }
+        if !session.authenticated() {
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!("cannot authenticate {} by the agent or the keys", user),
+            ));
+        }
+        let sftp = session.sftp()?;
+        Ok(SftpFs {
+            _session: session,
+            sftp,
+        })
+    }
+
+    fn check_host_key(session: &Session, url: &SftpUrl, accept_unknown: bool) -> io::Result<()> {
+        let mut known_hosts = session.known_hosts()?;
+        if let Some(home) = std::env::var_os("HOME") {
+            let path = Path::new(&home).join(".ssh").join("known_hosts");
+            // A missing file knows no host
+            if path.exists() {
+                known_hosts.read_file(&path, KnownHostFileKind::OpenSSH)?;
+            }
+        }
+        let (key, _) = session
+            .host_key()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "the host sent no host key"))?;
+        host_key_result(
+            known_hosts.check_port(&url.host, url.port, key),
+            accept_unknown,
+        )
+    }
+}
+
+fn host_key_result(result: CheckResult, accept_unknown: bool) -> io::Result<()> {
+    match result {
+        CheckResult::Match => Ok(()),
+        CheckResult::NotFound if accept_unknown => Ok(()),
+        CheckResult::NotFound => Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            "the host is not in ~/.ssh/known_hosts, connect by --accept-unknown-host",
+        )),
+        CheckResult::Mismatch => Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            "the host key differs from the one in ~/.ssh/known_hosts",
+        )),
+        CheckResult::Failure => Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "cannot check the host key",
+        )),
+    }
+}
+
+// The options which have no effect on a remote directory
+pub fn unsupported_options(config: &Config) -> Vec<&'static str> {
+    let options = [
+        ("--ext", !config.extensions.is_empty()),
+        ("--tee", config.tee.is_some()),
+        ("--socket", config.socket.is_some()),
+        ("--state-file", config.state_file.is_some()),
+        ("--max-files", config.max_files.is_some()),
+        ("--multicolor", config.multicolor),
+    ];
+    options
+        .iter()
+        .filter(|(_, used)| *used)
+        .map(|(option, _)| *option)
+        .collect()
+}
+
+impl RemoteFs for SftpFs {
+    type File = ssh2::File;
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(PathBuf, bool)>> {
+        let entries = self.sftp.readdir(path)?;
+        Ok(entries
+            .into_iter()
+            .map(|(path, stat)| (path, stat.is_dir()))
+            .collect())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<ssh2::File> {
+        Ok(self.sftp.open(path)?)
+    }
+
+    fn len(&self, path: &Path) -> io::Result<u64> {
+        Ok(self.sftp.stat(path)?.size.unwrap_or(0))
+    }
+}
+
+// The length is asked to the server, the handle does not know the growth
+pub struct RemoteFile<F: RemoteFs> {
+    fs: Rc<F>,
+    path: PathBuf,
+    file: F::File,
+}
+
+impl<F: RemoteFs> Read for RemoteFile<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl<F: RemoteFs> Seek for RemoteFile<F> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl<F: RemoteFs> Length for RemoteFile<F> {
+    fn len(&self) -> io::Result<u64> {
+        self.fs.len(&self.path)
+    }
+}
+
+pub struct RemoteCreator<F: RemoteFs> {
+    fs: Rc<F>,
+}
+
+impl<F: RemoteFs> ReaderCreator<PathBuf, RemoteFile<F>> for RemoteCreator<F> {
+    fn create_reader(&self, path: &PathBuf) -> io::Result<RemoteFile<F>> {
+        Ok(RemoteFile {
+            fs: Rc::clone(&self.fs),
+            path: path.to_owned(),
+            file: self.fs.open(path)?,
+        })
+    }
+}
+
+type RemoteReader<F> = TransparentReader<PathBuf, RemoteFile<F>, RemoteCreator<F>>;
+type RemoteRepository<F> = Rc<RefCell<HandleCache<PathBuf, Rc<RefCell<RemoteFile<F>>>>>>;
+
+pub struct RemoteWatcher<F: RemoteFs, W: Write> {
+    fs: Rc<F>,
+    // Shown before the remote paths in the headers, such as user@host:
+    origin: String,
+    root: PathBuf,
+    regex: Regex,
+    basename: bool,
+    hidden: bool,
+    depth: Option<usize>,
+    raw: bool,
+    repository: RemoteRepository<F>,
+    writer: SharedWriter<W>,
+    line_options: Rc<LineOptions>,
+    file_map: BTreeMap<PathBuf, TailState<RemoteReader<F>, SharedWriter<W>>>,
+    selected_file_path: Option<PathBuf>,
+}
+
+impl<F: RemoteFs, W: Write> RemoteWatcher<F, W> {
+    pub fn new(
+        config: &Config,
+        fs: F,
+        url: &SftpUrl,
+        writer: W,
+    ) -> Result<RemoteWatcher<F, W>, RegtailError> {
+        let unsupported = unsupported_options(config);
+        if !unsupported.is_empty() {
+            return Err(RegtailError::UnsupportedRemoteOptions(
+                unsupported.join(", "),
+            ));
+        }
+        let regex = PathFilter::generate_filter_regex(config)
+            .map_err(|error| RegtailError::InvalidRegex(error.to_string()))?;
+        let origin = match &url.user {
+            Some(user) => format!("{}@{}:", user, url.host),
+            None => format!("{}:", url.host),
+        };
+        Ok(RemoteWatcher {
+            fs: Rc::new(fs),
+            origin,
+            root: url.path.clone(),
+            regex,
+            basename: config.basename,
+            hidden: config.hidden,
+            depth: config.depth(),
+            raw: config.raw || config.merge,
+            repository: Rc::new(RefCell::new(HandleCache::new(MAX_REMOTE_HANDLE))),
+            writer: SharedWriter::new(writer),
+            line_options: Rc::new(config.line_options()?),
+            file_map: BTreeMap::new(),
+            selected_file_path: None,
+        })
+    }
+
+    pub fn follow(&mut self, config: &Config) -> io::Result<()> {
+        self.tail_initial_files(config)?;
+        if config.once {
+            return Ok(());
+        }
+        let interval = config.interval.unwrap_or(POLL_INTERVAL);
+        loop {
+            sleep(interval);
+            self.poll()?;
+        }
+    }
+
+    pub fn tail_initial_files(&mut self, config: &Config) -> io::Result<()> {
+        for path in self.walk()? {
+            let mut reader = self.open(&path);
+            match config.initial_lines() {
+                Some(0) => {
+                    reader.skip_to_end()?;
+                }
+                Some(lines) => {
+                    self.select(&path)?;
+                    tail_from_reader(&mut reader, lines)?;
+                }
+                None => {
+                    self.select(&path)?;
+                    reader.dump_to_tail()?;
+                }
+            }
+            self.file_map.insert(path, reader);
+        }
+        self.line_options.write_merged(&mut self.writer, true)?;
+        self.writer.flush()
+    }
+
+    // Print the content appended since the last poll, new files from the beginning
+    pub fn poll(&mut self) -> io::Result<()> {
+        let paths = self.walk()?;
+        self.file_map.retain(|path, _| paths.contains(path));
+        for path in paths {
+            let mut reader = match self.file_map.remove(&path) {
+                Some(reader) => reader,
+                None => self.open(&path),
+            };
+            reader.handle_shrink()?;
+            if reader.len()? > reader.current_seek() {
+                self.select(&path)?;
+                reader.dump_to_tail()?;
+            }
+            self.file_map.insert(path, reader);
+        }
+        // The lines merged at the last poll have been held long enough
+        self.line_options.write_merged(&mut self.writer, false)?;
+        self.writer.flush()
+    }
+
+    fn open(&self, path: &Path) -> TailState<RemoteReader<F>, SharedWriter<W>> {
+        let creator = RemoteCreator {
+            fs: Rc::clone(&self.fs),
+        };
+        let reader =
+            TransparentReader::with_creator(path.to_owned(), Rc::clone(&self.repository), creator);
+        TailState::with_line_options(reader, self.writer.clone(), &self.line_options)
+    }
+
+    // Print the header if the output switches to another file
+    fn select(&mut self, path: &Path) -> io::Result<()> {
+        if self.raw || self.selected_file_path.as_deref() == Some(path) {
+            return Ok(());
+        }
+        if let Some(selected_file_path) = &self.selected_file_path {
+            let interrupted = matches!(
+                self.file_map.get(selected_file_path),
+                Some(selected) if !selected.printed_eol()
+            );
+            if interrupted {
+                writeln!(self.writer)?;
+            }
+            writeln!(self.writer)?;
+        }
+        writeln!(self.writer, "==> {}{} <==", self.origin, path.display())?;
+        self.selected_file_path = Some(path.to_owned());
+        Ok(())
+    }
+
+    // Matched files under the root, walked in the order of the paths
+    fn walk(&self) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let mut dirs = vec![(self.root.clone(), 1)];
+        while let Some((dir, depth)) = dirs.pop() {
+            for (path, is_dir) in self.fs.read_dir(&dir)? {
+                let hidden = matches!(path.file_name(), Some(name) if is_hidden_name(name));
+                if hidden && !self.hidden {
+                    continue;
+                }
+                if is_dir {
+                    let within_depth = match self.depth {
+                        Some(max_depth) => depth < max_depth,
+                        None => true,
+                    };
+                    if within_depth {
+                        dirs.push((path, depth + 1));
+                    }
+                } else if self.is_match(&path) {
+                    files.push(path);
+                }
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    fn is_match(&self, path: &Path) -> bool {
+        let target = if self.basename {
+            path.file_name().and_then(|name| name.to_str())
+        } else {
+            path.to_str()
+        };
+        matches!(target, Some(target) if self.regex.is_match(target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom};
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+
+    use ssh2::CheckResult;
+
+    use super::{host_key_result, RemoteFs, RemoteWatcher, SftpUrl};
+    use crate::config::Config;
+    use crate::error::RegtailError;
+
+    // Files in memory, which the tests append to while the watcher reads them
+    #[derive(Default)]
+    struct MockFs {
+        files: RefCell<BTreeMap<PathBuf, Rc<RefCell<Vec<u8>>>>>,
+    }
+
+    impl MockFs {
+        fn put(&self, path: &str, content: &str) {
+            let mut files = self.files.borrow_mut();
+            let file = files.entry(PathBuf::from(path)).or_default();
+            file.borrow_mut().extend_from_slice(content.as_bytes());
+        }
+    }
+
+    struct MockFile {
+        content: Rc<RefCell<Vec<u8>>>,
+        position: u64,
+    }
+
+    impl Read for MockFile {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut cursor = Cursor::new(self.content.borrow().clone());
+            cursor.set_position(self.position);
+            let size = cursor.read(buf)?;
+            self.position += size as u64;
+            Ok(size)
+        }
+    }
+
+    impl Seek for MockFile {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            let mut cursor = Cursor::new(self.content.borrow().clone());
+            cursor.set_position(self.position);
+            self.position = cursor.seek(pos)?;
+            Ok(self.position)
+        }
+    }
+
+    impl RemoteFs for Rc<MockFs> {
+        type File = MockFile;
+
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<(PathBuf, bool)>> {
+            let mut entries = BTreeMap::new();
+            for file in self.files.borrow().keys() {
+                if let Ok(relative) = file.strip_prefix(path) {
+                    let mut components = relative.iter();
+                    let name = components.next().unwrap();
+                    entries.insert(path.join(name), components.next().is_some());
+                }
+            }
+            Ok(entries.into_iter().collect())
+        }
+
+        fn open(&self, path: &Path) -> io::Result<MockFile> {
+            match self.files.borrow().get(path) {
+                Some(content) => Ok(MockFile {
+                    content: Rc::clone(content),
+                    position: 0,
+                }),
+                None => Err(io::Error::from(ErrorKind::NotFound)),
+            }
+        }
+
+        fn len(&self, path: &Path) -> io::Result<u64> {
+            match self.files.borrow().get(path) {
+                Some(content) => Ok(content.borrow().len() as u64),
+                None => Err(io::Error::from(ErrorKind::NotFound)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            SftpUrl::parse(Path::new("sftp://admin@example.com:2222/var/log")),
+            Some(SftpUrl {
+                user: Some(String::from("admin")),
+                host: String::from("example.com"),
+                port: 2222,
+                path: PathBuf::from("/var/log"),
+            })
+        );
+        let url = SftpUrl::parse(Path::new("sftp://example.com")).unwrap();
+        assert_eq!(
+            (url.user, url.port, url.path),
+            (None, 22, PathBuf::from("/"))
+        );
+        assert_eq!(SftpUrl::parse(Path::new("/var/log")), None);
+        assert_eq!(SftpUrl::parse(Path::new("sftp://example.com:ssh/")), None);
+    }
+
+    #[test]
+    fn test_follow_remote_files() {
+        let fs = Rc::new(MockFs::default());
+        fs.put("/logs/app.log", "a\nb\nc\n");
+        fs.put("/logs/app.txt", "ignored\n");
+        fs.put("/logs/sub/deep.log", "too deep\n");
+        let config = Config::builder().regex(r"\.log$").lines(2).build();
+        let url = SftpUrl::parse(Path::new("sftp://host/logs")).unwrap();
+        let mut watcher = RemoteWatcher::new(&config, Rc::clone(&fs), &url, Vec::new()).unwrap();
+        watcher.tail_initial_files(&config).unwrap();
+        fs.put("/logs/app.log", "d\n");
+        fs.put("/logs/new.log", "new\n");
+        watcher.poll().unwrap();
+        let output = String::from_utf8(watcher.writer.get_ref().clone()).unwrap();
+        assert_eq!(
+            output,
+            "==> host:/logs/app.log <==\nb\nc\nd\n\n==> host:/logs/new.log <==\nnew\n"
+        );
+    }
+
+    #[test]
+    fn test_host_key_result() {
+        assert!(host_key_result(CheckResult::Match, false).is_ok());
+        assert!(host_key_result(CheckResult::NotFound, false).is_err());
+        assert!(host_key_result(CheckResult::NotFound, true).is_ok());
+        assert!(host_key_result(CheckResult::Mismatch, true).is_err());
+        assert!(host_key_result(CheckResult::Failure, true).is_err());
+    }
+
+    #[test]
+    fn test_unsupported_options() {
+        let config = Config::builder()
+            .extensions(vec!["log"])
+            .state_file("state.json")
+            .build();
+        let url = SftpUrl::parse(Path::new("sftp://host/logs")).unwrap();
+        match RemoteWatcher::new(&config, Rc::new(MockFs::default()), &url, Vec::new()) {
+            Err(RegtailError::UnsupportedRemoteOptions(options)) => {
+                assert_eq!(options, "--ext, --state-file")
+            }
+            _ => panic!("the options must be rejected"),
+        }
+    }
+}
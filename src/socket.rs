@@ -0,0 +1,220 @@
+/*
+ * Copyright 2019 StoneDot (Hiroaki Goto)
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::line::strip_ansi;
+
+// A client not reading the events for this long is disconnected instead of stalling the tail
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+const BEL: u8 = 0x07;
+
+pub type SharedEventSocket = Rc<RefCell<EventSocket>>;
+
+// Sends each line of the followed files to the connected clients as a JSON object,
+// prefixed by its length in 4 bytes big endian
+pub struct EventSocket {
+    listener: UnixListener,
+    path: PathBuf,
+    clients: Vec<UnixStream>,
+    // Path of the file whose content is written, None while the headers are written
+    source: Option<String>,
+    // Bytes written after the last line feed
+    partial_line: Vec<u8>,
+}
+
+impl EventSocket {
+    // A socket file left by a previous run is replaced
+    pub fn bind(path: &Path) -> io::Result<EventSocket> {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                fs::remove_file(path)?;
+            }
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(EventSocket {
+            listener,
+            path: path.to_owned(),
+            clients: Vec::new(),
+            source: None,
+            partial_line: Vec::new(),
+        })
+    }
+
+    pub fn accept_clients(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    let configured = stream
+                        .set_nonblocking(false)
+                        .and_then(|_| stream.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT)));
+                    if configured.is_ok() {
+                        self.clients.push(stream);
+                    }
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) => {
+                    eprintln!(
+                        "cannot accept a client of {}: {}",
+                        self.path.display(),
+                        error
+                    );
+                    break;
+                }
+            }
+        }
+    }
+
+    // An incomplete line of the previous source is printed again when it is selected next time
+    pub fn set_source(&mut self, source: Option<&Path>) {
+        self.source = source.map(|path| path.to_string_lossy().into_owned());
+        self.partial_line.clear();
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    fn receive(&mut self, buf: &[u8]) {
+        if self.source.is_none() {
+            return;
+        }
+        let mut rest = buf;
+        while let Some(position) = rest.iter().position(|&byte| byte == b'\n') {
+            self.partial_line.extend_from_slice(&rest[..position]);
+            let line = std::mem::take(&mut self.partial_line);
+            self.send_line(&line);
+            rest = &rest[position + 1..];
+        }
+        self.partial_line.extend_from_slice(rest);
+    }
+
+    fn send_line(&mut self, line: &[u8]) {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        // The highlights and the bell of the terminal are not part of the line
+        let mut line = strip_ansi(line);
+        line.retain(|&byte| byte != BEL);
+        let message = serde_json::json!({
+            "path": self.source,
+            "line": String::from_utf8_lossy(&line),
+        })
+        .to_string();
+        let mut frame = Vec::with_capacity(4 + message.len());
+        frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        frame.extend_from_slice(message.as_bytes());
+        self.accept_clients();
+        // Disconnected clients are dropped
+        self.clients
+            .retain(|client| (&*client).write_all(&frame).is_ok());
+    }
+}
+
+impl Drop for EventSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Writes through to the inner writer and mirrors the content to the event socket
+pub struct EventWriter<W: Write> {
+    inner: W,
+    socket: SharedEventSocket,
+}
+
+impl<W: Write> EventWriter<W> {
+    pub fn new(inner: W, socket: EventSocket) -> EventWriter<W> {
+        EventWriter {
+            inner,
+            socket: Rc::new(RefCell::new(socket)),
+        }
+    }
+
+    // Shared with the owner of the writer, which switches the source
+    pub fn socket(&self) -> SharedEventSocket {
+        Rc::clone(&self.socket)
+    }
+}
+
+impl<W: Write> Write for EventWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.inner.write(buf)?;
+        self.socket.borrow_mut().receive(&buf[..size]);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::path::Path;
+
+    use super::{EventSocket, EventWriter};
+
+    fn read_message(client: &mut UnixStream) -> serde_json::Value {
+        let mut length = [0u8; 4];
+        client.read_exact(&mut length).unwrap();
+        let mut message = vec![0u8; u32::from_be_bytes(length) as usize];
+        client.read_exact(&mut message).unwrap();
+        serde_json::from_slice(&message).unwrap()
+    }
+
+    #[test]
+    fn test_send_lines() {
+        let dir = std::env::temp_dir().join(format!("regtail_socket_{}", std::process::id()));
+        let _ = std::fs::create_dir_all(&dir);
+        let socket_path = dir.join("events.sock");
+        let mut writer = EventWriter::new(Vec::new(), EventSocket::bind(&socket_path).unwrap());
+        let socket = writer.socket();
+        let mut client = UnixStream::connect(&socket_path).unwrap();
+
+        writer.write_all(b"==> header <==\n").unwrap();
+        socket
+            .borrow_mut()
+            .set_source(Some(Path::new("/var/log/app.log")));
+        writer.write_all(b"line1\nli").unwrap();
+        writer.write_all(b"ne2\r\n").unwrap();
+
+        let message = read_message(&mut client);
+        assert_eq!(message["path"], "/var/log/app.log");
+        assert_eq!(message["line"], "line1");
+        assert_eq!(read_message(&mut client)["line"], "line2");
+        assert_eq!(socket.borrow().client_count(), 1);
+        assert_eq!(writer.inner, b"==> header <==\nline1\nline2\r\n");
+
+        drop(client);
+        writer.write_all(b"line3\n").unwrap();
+        writer.write_all(b"line4\n").unwrap();
+        assert_eq!(socket.borrow().client_count(), 0);
+        drop(socket);
+        drop(writer);
+        assert!(!socket_path.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,132 @@
+/*
+ * Copyright 2019 StoneDot (Hiroaki Goto)
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FilePosition {
+    pub offset: u64,
+    // Detects the file replaced by the rotation
+    pub inode: Option<u64>,
+}
+
+// Offsets read so far for each canonical path, persisted to resume after a restart
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TailPositions {
+    files: HashMap<PathBuf, FilePosition>,
+}
+
+impl TailPositions {
+    // The empty positions are returned if the state file does not exist yet
+    pub fn load(path: &Path) -> Result<TailPositions, String> {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|error| format!("invalid state file {}: {}", path.display(), error)),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(TailPositions::default()),
+            Err(error) => Err(format!(
+                "cannot read state file {}: {}",
+                path.display(),
+                error
+            )),
+        }
+    }
+
+    // Written to a temporary file and renamed, so a crash never leaves a broken state file
+    pub fn save(self: &TailPositions, path: &Path) -> io::Result<()> {
+        let content = serde_json::to_string(self)?;
+        let mut temporary_path = path.as_os_str().to_owned();
+        temporary_path.push(".tmp");
+        fs::write(&temporary_path, content)?;
+        fs::rename(&temporary_path, path)
+    }
+
+    pub fn get(self: &TailPositions, path: &Path) -> Option<FilePosition> {
+        self.files.get(path).copied()
+    }
+
+    pub fn insert(self: &mut TailPositions, path: PathBuf, position: FilePosition) {
+        self.files.insert(path, position);
+    }
+
+    pub fn remove(self: &mut TailPositions, path: &Path) -> Option<FilePosition> {
+        self.files.remove(path)
+    }
+
+    // Returns the offset to resume from, None if the file was replaced or shrank
+    pub fn resume_offset(
+        self: &TailPositions,
+        path: &Path,
+        inode: Option<u64>,
+        len: u64,
+    ) -> Option<u64> {
+        match self.get(path) {
+            Some(position) if position.inode == inode && position.offset <= len => {
+                Some(position.offset)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{FilePosition, TailPositions};
+
+    #[test]
+    fn test_resume_offset() {
+        let mut positions = TailPositions::default();
+        positions.insert(
+            PathBuf::from("/var/log/app.log"),
+            FilePosition {
+                offset: 10,
+                inode: Some(1),
+            },
+        );
+        let path = Path::new("/var/log/app.log");
+        assert_eq!(positions.resume_offset(path, Some(1), 20), Some(10));
+        assert_eq!(positions.resume_offset(path, Some(1), 10), Some(10));
+        // Shrank
+        assert_eq!(positions.resume_offset(path, Some(1), 5), None);
+        // Rotated
+        assert_eq!(positions.resume_offset(path, Some(2), 20), None);
+        assert_eq!(
+            positions.resume_offset(Path::new("/var/log/other.log"), Some(1), 20),
+            None
+        );
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut positions = TailPositions::default();
+        positions.insert(
+            PathBuf::from("/var/log/app.log"),
+            FilePosition {
+                offset: 10,
+                inode: None,
+            },
+        );
+        let content = serde_json::to_string(&positions).unwrap();
+        let loaded: TailPositions = serde_json::from_str(&content).unwrap();
+        assert_eq!(loaded, positions);
+    }
+}
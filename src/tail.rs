@@ -14,24 +14,210 @@
  * limitations under the License.
  */
 
-use std::cell::RefCell;
-use std::cmp::max;
-use std::fs::File;
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::VecDeque;
+use std::fs::{self, File};
 use std::hash::Hash;
-use std::io::{self, sink, Read, Result, Seek, SeekFrom, Sink, Stdout, Write};
+use std::io::{
+    self, sink, Cursor, ErrorKind, Read, Result, Seek, SeekFrom, Sink, Stdin, Stdout, Write,
+};
+use std::mem::take;
 use std::path::{Path, PathBuf};
 use std::rc::{Rc, Weak};
+use std::time::Instant;
 
-use lru::LruCache;
+use encoding_rs::Decoder;
+
+use crate::cache::HandleCache;
+use crate::line::{LineOptions, LineProcessor, DEFAULT_DELIMITER};
 
 // Max recommended buffer size is 128kB
 // We choose reasonable size 8kB
 const BUFFER_SIZE: usize = 8 * 1024;
 const BUFFER_LEN: u64 = BUFFER_SIZE as u64;
+// Longer incomplete lines are not replayed after an interruption
+const PARTIAL_LINE_LIMIT: usize = 4 * 1024;
+// Only the last part of a decompressed file is kept in memory
+const DECOMPRESSED_LIMIT: usize = 64 * 1024 * 1024;
+
+// Identifies the file which the path points to, used to detect the replacement of the file
+#[cfg(unix)]
+pub fn file_id(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|metadata| metadata.ino())
+}
+
+#[cfg(not(unix))]
+pub fn file_id(_path: &Path) -> Option<u64> {
+    None
+}
+
+// A named pipe cannot be seeked, so it is streamed as its content arrives
+#[cfg(unix)]
+pub fn is_fifo(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    fs::metadata(path)
+        .map(|metadata| metadata.file_type().is_fifo())
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+pub fn is_fifo(_path: &Path) -> bool {
+    false
+}
+
+// A burst of new files may exceed the limit of the open files even with the LRU cache
+#[cfg(unix)]
+fn is_too_many_open_files(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(libc::EMFILE)
+}
+
+#[cfg(not(unix))]
+fn is_too_many_open_files(_error: &io::Error) -> bool {
+    false
+}
+
+// The file was removed while it is read, the following REMOVE event cleans it up
+#[cfg(unix)]
+fn is_removed(error: &io::Error) -> bool {
+    error.kind() == ErrorKind::NotFound || error.raw_os_error() == Some(libc::ESTALE)
+}
+
+#[cfg(not(unix))]
+fn is_removed(error: &io::Error) -> bool {
+    error.kind() == ErrorKind::NotFound
+}
 
-pub type FileRepository = Rc<RefCell<LruCache<PathBuf, Rc<RefCell<File>>>>>;
+pub type FileRepository = Rc<RefCell<HandleCache<PathBuf, Rc<RefCell<File>>>>>;
 pub type FileReader = TransparentReader<PathBuf, File, FileCreator>;
-pub type CachedTailState = TailState<FileReader, io::BufWriter<Stdout>>;
+pub type CachedTailState<W = io::BufWriter<Stdout>> = TailState<SourceReader, SharedWriter<W>>;
+
+// Writer shared by every tail state and the header output to keep them in order
+pub struct SharedWriter<W: Write> {
+    writer: Rc<RefCell<W>>,
+    defer_flush: Rc<Cell<bool>>,
+    flush_per_write: Rc<Cell<bool>>,
+    // When any byte was written last, the creation time if nothing was written
+    last_write: Rc<Cell<Instant>>,
+    // Total bytes written so far
+    written: Rc<Cell<u64>>,
+}
+
+impl<W: Write> SharedWriter<W> {
+    pub fn new(writer: W) -> SharedWriter<W> {
+        SharedWriter {
+            writer: Rc::new(RefCell::new(writer)),
+            defer_flush: Rc::new(Cell::new(false)),
+            flush_per_write: Rc::new(Cell::new(false)),
+            last_write: Rc::new(Cell::new(Instant::now())),
+            written: Rc::new(Cell::new(0)),
+        }
+    }
+
+    // While flush is deferred, flush requests are ignored until it is enabled again
+    pub fn defer_flush(&self, defer: bool) {
+        self.defer_flush.set(defer);
+    }
+
+    // Flush after every write unless flush is deferred
+    pub fn flush_per_write(&self, enabled: bool) {
+        self.flush_per_write.set(enabled);
+    }
+
+    pub fn last_write(&self) -> Instant {
+        self.last_write.get()
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written.get()
+    }
+
+    pub fn get_ref(&self) -> Ref<'_, W> {
+        (*self.writer).borrow()
+    }
+}
+
+impl<W: Write> Clone for SharedWriter<W> {
+    fn clone(&self) -> Self {
+        SharedWriter {
+            writer: Rc::clone(&self.writer),
+            defer_flush: Rc::clone(&self.defer_flush),
+            flush_per_write: Rc::clone(&self.flush_per_write),
+            last_write: Rc::clone(&self.last_write),
+            written: Rc::clone(&self.written),
+        }
+    }
+}
+
+impl<W: Write> Write for SharedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let size = (*self.writer).borrow_mut().write(buf)?;
+        if size > 0 {
+            self.last_write.set(Instant::now());
+            self.written.set(self.written.get() + size as u64);
+        }
+        if self.flush_per_write.get() {
+            self.flush()?;
+        }
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.defer_flush.get() {
+            return Ok(());
+        }
+        (*self.writer).borrow_mut().flush()
+    }
+}
+
+// Destination of the output written to TeeWriter
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TeeTarget {
+    Both,
+    Primary,
+    Secondary,
+}
+
+// Mirrors the output to two writers, e.g. the terminal and a capture file.
+// The target can be switched to render the same content differently for each.
+pub struct TeeWriter<W1: Write, W2: Write> {
+    primary: W1,
+    secondary: W2,
+    target: Rc<Cell<TeeTarget>>,
+}
+
+impl<W1: Write, W2: Write> TeeWriter<W1, W2> {
+    pub fn new(primary: W1, secondary: W2) -> TeeWriter<W1, W2> {
+        TeeWriter {
+            primary,
+            secondary,
+            target: Rc::new(Cell::new(TeeTarget::Both)),
+        }
+    }
+
+    // Switch shared with the owner of the writer
+    pub fn target(&self) -> Rc<Cell<TeeTarget>> {
+        Rc::clone(&self.target)
+    }
+}
+
+impl<W1: Write, W2: Write> Write for TeeWriter<W1, W2> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let target = self.target.get();
+        if target != TeeTarget::Secondary {
+            self.primary.write_all(buf)?;
+        }
+        if target != TeeTarget::Primary {
+            self.secondary.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.primary.flush()?;
+        self.secondary.flush()
+    }
+}
 
 pub trait ReaderCreator<K, T> {
     fn create_reader(&self, path: &K) -> Result<T>;
@@ -44,13 +230,135 @@ impl ReaderCreator<PathBuf, File> for FileCreator {
         File::open(path)
     }
 }
+
+// Compression formats read by --decompress
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Xz,
+    Bzip2,
+}
+
+impl Compression {
+    // The format is selected by the extension of the file
+    pub fn from_path(path: &Path) -> Option<Compression> {
+        match path.extension()?.to_str()? {
+            "xz" => Some(Compression::Xz),
+            "bz2" => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+
+    // Returns true if the content starts with the magic bytes of the format
+    pub fn has_magic(self, buf: &[u8]) -> bool {
+        match self {
+            Compression::Xz => buf.starts_with(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]),
+            Compression::Bzip2 => buf.starts_with(b"BZh"),
+        }
+    }
+}
+
+pub struct DecompressCreator(pub Compression);
+
+impl ReaderCreator<PathBuf, DecompressedReader> for DecompressCreator {
+    fn create_reader(&self, path: &PathBuf) -> Result<DecompressedReader> {
+        let file = File::open(path)?;
+        let content = match self.0 {
+            Compression::Xz => read_last_bytes(xz2::read::XzDecoder::new(file), DECOMPRESSED_LIMIT),
+            Compression::Bzip2 => {
+                read_last_bytes(bzip2::read::BzDecoder::new(file), DECOMPRESSED_LIMIT)
+            }
+        }
+        .map_err(|error| match error.kind() {
+            ErrorKind::UnexpectedEof | ErrorKind::InvalidData => {
+                io::Error::new(ErrorKind::UnexpectedEof, IncompleteCompression)
+            }
+            _ => error,
+        })?;
+        Ok(DecompressedReader {
+            content: Cursor::new(content),
+        })
+    }
+}
+
+// A compressed file which cannot be decoded to the end, e.g. it is still being written
+#[derive(Debug)]
+pub struct IncompleteCompression;
+
+impl std::fmt::Display for IncompleteCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the compressed content is incomplete")
+    }
+}
+
+impl std::error::Error for IncompleteCompression {}
+
+// Returns true if the error comes from a compressed file which is not complete yet
+pub fn is_incomplete_compression(error: &io::Error) -> bool {
+    match error.get_ref() {
+        Some(inner) => inner.is::<IncompleteCompression>(),
+        None => false,
+    }
+}
+
+// The content is streamed and only the last limit bytes are kept,
+// where at most twice the limit is held while reading
+fn read_last_bytes<R: Read>(mut reader: R, limit: usize) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    let mut buf = [0u8; BUFFER_SIZE];
+    loop {
+        let size = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(size) => size,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        content.extend_from_slice(&buf[..size]);
+        if content.len() > limit.saturating_mul(2) {
+            content.drain(..content.len() - limit);
+        }
+    }
+    if content.len() > limit {
+        content.drain(..content.len() - limit);
+    }
+    Ok(content)
+}
+
+// The last part of the content is decompressed at once, so that the tail can seek backward in it.
+// Compressed files are not expected to grow, so the content is never reloaded.
+pub struct DecompressedReader {
+    content: Cursor<Vec<u8>>,
+}
+
+impl Read for DecompressedReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.content.read(buf)
+    }
+}
+
+impl Seek for DecompressedReader {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.content.seek(pos)
+    }
+}
+
+impl SeekPos for DecompressedReader {
+    fn seek_pos(&self) -> u64 {
+        self.content.position()
+    }
+}
+
+impl Length for DecompressedReader {
+    fn len(&self) -> Result<u64> {
+        Ok(self.content.get_ref().len() as u64)
+    }
+}
 pub struct TransparentReader<K, T, C>
 where
     K: Hash + Eq + Clone,
     T: Read + Seek + Length,
     C: ReaderCreator<K, T>,
 {
-    reader_repository: Rc<RefCell<LruCache<K, Rc<RefCell<T>>>>>,
+    reader_repository: Rc<RefCell<HandleCache<K, Rc<RefCell<T>>>>>,
     path: K,
     reader_seek_pos: u64,
     reader_cache: RefCell<Weak<RefCell<T>>>,
@@ -73,7 +381,18 @@ where
         match reader_repo.get(&self.path) {
             Some(reader) => Ok(Rc::clone(reader)),
             None => {
-                let file = self.reader_creator.create_reader(&self.path)?;
+                let mut file = match self.reader_creator.create_reader(&self.path) {
+                    // Close a file in the repository and retry once
+                    Err(error) if is_too_many_open_files(&error) && !reader_repo.is_empty() => {
+                        reader_repo.evict();
+                        self.reader_creator.create_reader(&self.path)?
+                    }
+                    result => result?,
+                };
+                // A reopened file starts at the beginning, continue from the last position
+                if self.reader_seek_pos > 0 {
+                    file.seek(SeekFrom::Start(self.reader_seek_pos))?;
+                }
                 reader_repo.put(self.path.clone(), Rc::new(RefCell::new(file)));
                 let data = reader_repo.get(&self.path).unwrap();
                 *reader_cache = Rc::downgrade(data);
@@ -90,7 +409,11 @@ where
     C: ReaderCreator<K, T>,
 {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let rc_reader = self.reader()?;
+        let rc_reader = match self.reader() {
+            // The closed file cannot be opened again, read it as empty until the removal
+            Err(error) if is_removed(&error) => return Ok(0),
+            result => result?,
+        };
         let mut reader = (*rc_reader).borrow_mut();
         let size = (*reader).read(buf);
         if let Ok(size) = size {
@@ -150,21 +473,37 @@ where
     }
 }
 
-impl TransparentReader<PathBuf, File, FileCreator> {
-    fn new(
-        path: PathBuf,
-        repository: Rc<RefCell<LruCache<PathBuf, Rc<RefCell<File>>>>>,
-    ) -> TransparentReader<PathBuf, File, FileCreator> {
+impl<K, T, C> TransparentReader<K, T, C>
+where
+    K: Hash + Eq + Clone,
+    T: Read + Seek + Length,
+    C: ReaderCreator<K, T>,
+{
+    // The reader is created lazily by the creator, and shared through the repository
+    pub fn with_creator(
+        path: K,
+        repository: Rc<RefCell<HandleCache<K, Rc<RefCell<T>>>>>,
+        reader_creator: C,
+    ) -> TransparentReader<K, T, C> {
         TransparentReader {
             reader_repository: repository,
             path,
             reader_seek_pos: 0,
             reader_cache: RefCell::new(Weak::new()),
-            reader_creator: FileCreator,
+            reader_creator,
         }
     }
 }
 
+impl TransparentReader<PathBuf, File, FileCreator> {
+    fn new(
+        path: PathBuf,
+        repository: FileRepository,
+    ) -> TransparentReader<PathBuf, File, FileCreator> {
+        Self::with_creator(path, repository, FileCreator)
+    }
+}
+
 // Allow lack of is_empty function because of len returns Result type
 #[allow(clippy::len_without_is_empty)]
 pub trait Length {
@@ -188,23 +527,450 @@ where
 {
     reader: T,
     writer: U,
+    line_processor: Option<LineProcessor>,
+    // Whether the output ended on the delimiter
     printed_eol: bool,
+    delimiter: u8,
+    // Bytes printed after the last delimiter, None if it exceeds the limit
+    partial_line: Option<Vec<u8>>,
+    line_interrupted: bool,
+    // File length when the last read finished, used to detect truncation
+    last_len: u64,
+    inode: Option<u64>,
+    // Transcodes to UTF-8, keeping a character split across reads
+    decoder: Option<Decoder>,
+    // Bytes scanned backward for the initial lines at most
+    max_scan_bytes: Option<u64>,
+    // The last read stopped at the cap before the end of the file
+    read_pending: bool,
 }
 
-impl CachedTailState {
-    pub fn from_path(path: PathBuf, repo: FileRepository) -> Result<CachedTailState> {
-        let reader = FileReader::new(path, repo);
-        Self::from_file_reader(reader)
+impl<W: Write> CachedTailState<W> {
+    pub fn from_path(
+        path: PathBuf,
+        repo: FileRepository,
+        writer: SharedWriter<W>,
+        line_options: &Rc<LineOptions>,
+    ) -> Result<CachedTailState<W>> {
+        let inode = file_id(&path);
+        let reader = Self::open_source(path, repo, line_options)?;
+        let mut state = Self::from_file_reader(reader, writer, line_options)?;
+        state.inode = inode;
+        Ok(state)
     }
 
-    pub fn from_file_reader(reader: FileReader) -> Result<CachedTailState> {
-        let writer = io::BufWriter::new(io::stdout());
-        Ok(CachedTailState {
-            reader,
-            writer,
-            printed_eol: false,
+    // The rotated files are read as one content from the oldest, and the last one is followed
+    pub fn from_rotated_paths(
+        paths: Vec<PathBuf>,
+        repo: FileRepository,
+        writer: SharedWriter<W>,
+        line_options: &Rc<LineOptions>,
+    ) -> Result<CachedTailState<W>> {
+        let inode = paths.last().and_then(|path| file_id(path));
+        let segments = paths
+            .into_iter()
+            .map(|path| Self::open_source(path, Rc::clone(&repo), line_options))
+            .collect::<Result<Vec<SourceReader>>>()?;
+        let reader = SourceReader::Chained(ChainedReader::new(segments)?);
+        let mut state = Self::from_file_reader(reader, writer, line_options)?;
+        state.inode = inode;
+        Ok(state)
+    }
+
+    fn open_source(
+        path: PathBuf,
+        repo: FileRepository,
+        line_options: &Rc<LineOptions>,
+    ) -> Result<SourceReader> {
+        if is_fifo(&path) {
+            Ok(SourceReader::Fifo(FifoReader::open(&path)?))
+        } else if let Some(compression) =
+            Compression::from_path(&path).filter(|_| line_options.decompress)
+        {
+            Ok(SourceReader::Decompressed(
+                DecompressCreator(compression).create_reader(&path)?,
+            ))
+        } else {
+            Ok(SourceReader::File(FileReader::new(path, repo)))
+        }
+    }
+
+    // Returns true if the path points to another file than the followed one,
+    // e.g. an editor saved the file by renaming a temporary file over it
+    pub fn is_replaced(&self, path: &Path) -> bool {
+        match (self.inode, file_id(path)) {
+            (Some(inode), Some(current_inode)) => inode != current_inode,
+            _ => false,
+        }
+    }
+
+    pub fn inode(&self) -> Option<u64> {
+        self.inode
+    }
+
+    // Returns true if the content is not seekable and only streamed
+    pub fn is_stream(&self) -> bool {
+        matches!(self.reader, SourceReader::Fifo(_))
+    }
+
+    // Returns true if the stream has content to be read.
+    // Writes to a named pipe are not notified, so this has to be polled.
+    pub fn has_stream_content(&self) -> bool {
+        match &self.reader {
+            SourceReader::Fifo(reader) => reader.available() > 0,
+            SourceReader::File(_) | SourceReader::Decompressed(_) | SourceReader::Chained(_) => {
+                false
+            }
+        }
+    }
+
+    pub fn from_file_reader(
+        reader: SourceReader,
+        writer: SharedWriter<W>,
+        line_options: &Rc<LineOptions>,
+    ) -> Result<CachedTailState<W>> {
+        let mut state = CachedTailState::new(reader, writer);
+        state.set_line_options(line_options);
+        Ok(state)
+    }
+}
+
+// Reads the segments as one content. Only the last segment may grow,
+// the lengths of the others are fixed when opened.
+pub struct ChainedReader {
+    segments: Vec<SourceReader>,
+    // Offset of each segment in the whole content
+    starts: Vec<u64>,
+    position: u64,
+}
+
+impl ChainedReader {
+    pub fn new(segments: Vec<SourceReader>) -> Result<ChainedReader> {
+        let mut starts = Vec::with_capacity(segments.len());
+        let mut start = 0;
+        for segment in &segments {
+            starts.push(start);
+            start += segment.len()?;
+        }
+        Ok(ChainedReader {
+            segments,
+            starts,
+            position: 0,
+        })
+    }
+
+    // Index of the segment containing the position, the content beyond the end is in the last one
+    fn segment_index(&self) -> usize {
+        self.starts
+            .iter()
+            .rposition(|&start| start <= self.position)
+            .unwrap_or(0)
+    }
+}
+
+// The buffer is filled across the segments, because the tail expects a block is read at once
+impl Read for ChainedReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut filled = 0;
+        let mut index = self.segment_index();
+        while filled < buf.len() && index < self.segments.len() {
+            let start = self.starts[index];
+            let segment = &mut self.segments[index];
+            segment.seek(SeekFrom::Start(self.position - start))?;
+            let size = segment.read(&mut buf[filled..])?;
+            filled += size;
+            self.position += size as u64;
+            if size > 0 {
+                continue;
+            }
+            index += 1;
+            match self.starts.get(index) {
+                Some(&next_start) => self.position = next_start,
+                None => break,
+            }
+        }
+        Ok(filled)
+    }
+}
+
+impl Seek for ChainedReader {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => checked_offset(self.len()?, offset),
+            SeekFrom::Current(offset) => checked_offset(self.position, offset),
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            )),
+        }
+    }
+}
+
+fn checked_offset(base: u64, offset: i64) -> Option<u64> {
+    if offset < 0 {
+        base.checked_sub(offset.unsigned_abs())
+    } else {
+        base.checked_add(offset as u64)
+    }
+}
+
+impl SeekPos for ChainedReader {
+    fn seek_pos(&self) -> u64 {
+        self.position
+    }
+}
+
+impl Length for ChainedReader {
+    fn len(&self) -> Result<u64> {
+        match (self.starts.last(), self.segments.last()) {
+            (Some(start), Some(segment)) => Ok(start + segment.len()?),
+            _ => Ok(0),
+        }
+    }
+}
+
+// Reads a named pipe without blocking. Seek is ignored and the length is the size read so far.
+pub struct FifoReader {
+    file: File,
+    read_size: u64,
+}
+
+impl FifoReader {
+    pub fn open(path: &Path) -> io::Result<FifoReader> {
+        let mut options = fs::OpenOptions::new();
+        options.read(true);
+        // Opening a named pipe blocks until a writer appears without O_NONBLOCK
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.custom_flags(libc::O_NONBLOCK);
+        }
+        Ok(FifoReader {
+            file: options.open(path)?,
+            read_size: 0,
         })
     }
+
+    // Size of the content which can be read without blocking
+    #[cfg(unix)]
+    pub fn available(&self) -> usize {
+        use std::os::unix::io::AsRawFd;
+        let mut size: libc::c_int = 0;
+        let result = unsafe { libc::ioctl(self.file.as_raw_fd(), libc::FIONREAD, &mut size) };
+        if result < 0 {
+            0
+        } else {
+            size as usize
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn available(&self) -> usize {
+        0
+    }
+}
+
+impl Read for FifoReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self.file.read(buf) {
+            Ok(size) => {
+                self.read_size += size as u64;
+                Ok(size)
+            }
+            // No data is available from the writers for now
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(0),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+impl Seek for FifoReader {
+    fn seek(&mut self, _pos: SeekFrom) -> Result<u64> {
+        Ok(self.read_size)
+    }
+}
+
+impl SeekPos for FifoReader {
+    fn seek_pos(&self) -> u64 {
+        self.read_size
+    }
+}
+
+impl Length for FifoReader {
+    fn len(&self) -> Result<u64> {
+        Ok(self.read_size)
+    }
+}
+
+// Reader of a followed path, either a regular file, a named pipe or a compressed file,
+// or the rotated files of a path chained in order
+pub enum SourceReader {
+    File(FileReader),
+    Fifo(FifoReader),
+    Decompressed(DecompressedReader),
+    Chained(ChainedReader),
+}
+
+impl Read for SourceReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            SourceReader::File(reader) => reader.read(buf),
+            SourceReader::Fifo(reader) => reader.read(buf),
+            SourceReader::Decompressed(reader) => reader.read(buf),
+            SourceReader::Chained(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl Seek for SourceReader {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        match self {
+            SourceReader::File(reader) => reader.seek(pos),
+            SourceReader::Fifo(reader) => reader.seek(pos),
+            SourceReader::Decompressed(reader) => reader.seek(pos),
+            SourceReader::Chained(reader) => reader.seek(pos),
+        }
+    }
+}
+
+impl SeekPos for SourceReader {
+    fn seek_pos(&self) -> u64 {
+        match self {
+            SourceReader::File(reader) => reader.seek_pos(),
+            SourceReader::Fifo(reader) => reader.seek_pos(),
+            SourceReader::Decompressed(reader) => reader.seek_pos(),
+            SourceReader::Chained(reader) => reader.seek_pos(),
+        }
+    }
+}
+
+impl Length for SourceReader {
+    fn len(&self) -> Result<u64> {
+        match self {
+            SourceReader::File(reader) => reader.len(),
+            SourceReader::Fifo(reader) => reader.len(),
+            SourceReader::Decompressed(reader) => reader.len(),
+            SourceReader::Chained(reader) => reader.len(),
+        }
+    }
+}
+
+// Reads a pipe such as the standard input, which cannot seek backward.
+// The last lines are held in memory until the end of the input instead.
+// The last lines selected by --grep, with the lines before them which may be printed as their
+// context. The grep applies first, so the count is of the matches.
+#[derive(Default)]
+struct SelectedLines {
+    lines: VecDeque<(Vec<u8>, bool)>,
+    selected: usize,
+}
+
+impl SelectedLines {
+    fn push(&mut self, line: Vec<u8>, tail_count: usize, line_options: &LineOptions) {
+        let selected = line_options.is_selected(&line);
+        if selected {
+            self.selected += 1;
+        }
+        self.lines.push_back((line, selected));
+        while self.selected > tail_count {
+            if let Some((_, true)) = self.lines.pop_front() {
+                self.selected -= 1;
+            }
+        }
+        // Only the context of the first kept match can precede it
+        while self.leading_unselected() > line_options.before_context {
+            self.lines.pop_front();
+        }
+    }
+
+    fn leading_unselected(&self) -> usize {
+        self.lines
+            .iter()
+            .take_while(|(_, selected)| !selected)
+            .count()
+    }
+}
+
+pub struct StdinReader<R: Read = Stdin> {
+    reader: R,
+}
+
+impl StdinReader {
+    pub fn stdin() -> StdinReader {
+        StdinReader::new(io::stdin())
+    }
+}
+
+impl<R: Read> StdinReader<R> {
+    pub fn new(reader: R) -> StdinReader<R> {
+        StdinReader { reader }
+    }
+
+    // Print the last tail_count lines at the end of the input,
+    // or everything as it arrives if tail_count is None
+    pub fn tail<W: Write>(
+        &mut self,
+        writer: &mut W,
+        line_options: &Rc<LineOptions>,
+        tail_count: Option<u64>,
+    ) -> Result<()> {
+        let mut processor = if line_options.is_line_aware() {
+            Some(LineProcessor::new(Rc::clone(line_options)))
+        } else {
+            None
+        };
+        let mut output = |writer: &mut W, buf: &[u8]| match &mut processor {
+            Some(processor) => processor.write(writer, buf),
+            None => writer.write_all(buf),
+        };
+        let delimiter = line_options.delimiter;
+        let mut buffer = [0; BUFFER_SIZE];
+        let mut lines = SelectedLines::default();
+        let mut partial_line = Vec::new();
+        loop {
+            let read_size = match self.reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read_size) => read_size,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let target = &buffer[..read_size];
+            let tail_count = match tail_count {
+                Some(tail_count) => tail_count as usize,
+                None => {
+                    output(writer, target)?;
+                    writer.flush()?;
+                    continue;
+                }
+            };
+            for chunk in target.split_inclusive(|&byte| byte == delimiter) {
+                partial_line.extend_from_slice(chunk);
+                if chunk.last() == Some(&delimiter) {
+                    lines.push(take(&mut partial_line), tail_count, line_options);
+                }
+            }
+        }
+        if let Some(tail_count) = tail_count {
+            // An incomplete last line is counted as a line like tail does
+            if !partial_line.is_empty() {
+                lines.push(partial_line, tail_count as usize, line_options);
+            }
+            for (line, _) in lines.lines {
+                output(writer, &line)?;
+            }
+        }
+        if let Some(processor) = &mut processor {
+            processor.end_of_input(writer)?;
+        }
+        writer.flush()
+    }
 }
 
 pub struct DirectFileReader {
@@ -213,7 +979,6 @@ pub struct DirectFileReader {
 }
 
 impl DirectFileReader {
-    #[allow(dead_code)]
     pub fn new(path: &Path) -> io::Result<DirectFileReader> {
         let file = File::open(path)?;
         Ok(DirectFileReader {
@@ -253,11 +1018,7 @@ impl Length for DirectFileReader {
 
 #[allow(dead_code)]
 pub fn from_file_to_sink(path: &Path) -> io::Result<TailState<DirectFileReader, Sink>> {
-    Ok(TailState {
-        reader: DirectFileReader::new(path)?,
-        writer: sink(),
-        printed_eol: false,
-    })
+    Ok(TailState::new(DirectFileReader::new(path)?, sink()))
 }
 
 // Allow lack of is_empty function because of len returns Result type
@@ -267,8 +1028,50 @@ where
     T: Read + Seek + SeekPos + Length,
     U: Write,
 {
+    pub fn with_line_options(
+        reader: T,
+        writer: U,
+        line_options: &Rc<LineOptions>,
+    ) -> TailState<T, U> {
+        let mut state = TailState::new(reader, writer);
+        state.set_line_options(line_options);
+        state
+    }
+
+    fn set_line_options(&mut self, line_options: &Rc<LineOptions>) {
+        self.delimiter = line_options.delimiter;
+        self.max_scan_bytes = line_options.max_scan_bytes;
+        self.decoder = line_options
+            .encoding
+            .map(|encoding| encoding.new_decoder_without_bom_handling());
+        if line_options.is_line_aware() {
+            self.line_processor = Some(LineProcessor::new(Rc::clone(line_options)));
+        }
+    }
+
+    pub fn new(reader: T, writer: U) -> TailState<T, U> {
+        TailState {
+            reader,
+            writer,
+            line_processor: None,
+            printed_eol: false,
+            delimiter: DEFAULT_DELIMITER,
+            partial_line: Some(Vec::new()),
+            line_interrupted: false,
+            last_len: 0,
+            inode: None,
+            decoder: None,
+            max_scan_bytes: None,
+            read_pending: false,
+        }
+    }
+
+    // Reaching the removed file is treated as the end of the file
     pub fn read(&mut self, mut buf: &mut [u8]) -> Result<usize> {
-        self.reader.read(&mut buf)
+        match self.reader.read(&mut buf) {
+            Err(error) if is_removed(&error) => Ok(0),
+            result => result,
+        }
     }
 
     pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
@@ -279,6 +1082,80 @@ where
         self.writer.flush()
     }
 
+    fn output(&mut self, buf: &[u8]) -> Result<()> {
+        let mut decoded = String::new();
+        let buf = match &mut self.decoder {
+            Some(decoder) => {
+                let capacity = decoder
+                    .max_utf8_buffer_length(buf.len())
+                    .unwrap_or(buf.len());
+                decoded.reserve(capacity);
+                let _ = decoder.decode_to_string(buf, &mut decoded, false);
+                decoded.as_bytes()
+            }
+            None => buf,
+        };
+        match &mut self.line_processor {
+            Some(processor) => processor.write(&mut self.writer, buf),
+            None => {
+                self.write(buf)?;
+                self.track_partial_line(buf);
+                Ok(())
+            }
+        }
+    }
+
+    fn track_partial_line(&mut self, buf: &[u8]) {
+        let delimiter = self.delimiter;
+        let rest = match buf.iter().rposition(|&byte| byte == delimiter) {
+            Some(pos) => {
+                self.partial_line = Some(Vec::new());
+                &buf[pos + 1..]
+            }
+            None => buf,
+        };
+        if let Some(partial_line) = &mut self.partial_line {
+            if partial_line.len() + rest.len() > PARTIAL_LINE_LIMIT {
+                self.partial_line = None;
+            } else {
+                partial_line.extend_from_slice(rest);
+            }
+        }
+    }
+
+    // Called when other output breaks the line in the middle
+    pub fn interrupt_line(&mut self) {
+        if !self.printed_eol {
+            self.line_interrupted = true;
+        }
+    }
+
+    // Print the interrupted incomplete line again so that it is joined with the rest
+    pub fn resume_line(&mut self) -> Result<()> {
+        if self.line_interrupted {
+            self.line_interrupted = false;
+            if let Some(partial_line) = &self.partial_line {
+                self.writer.write_all(partial_line)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn end_of_output(&mut self) -> Result<()> {
+        match &mut self.line_processor {
+            Some(processor) => processor.end_of_read(&mut self.writer),
+            None => Ok(()),
+        }
+    }
+
+    // Called when no more content is read, the incomplete last line is processed as is
+    pub fn end_of_input(&mut self) -> Result<()> {
+        match &mut self.line_processor {
+            Some(processor) => processor.end_of_input(&mut self.writer),
+            None => Ok(()),
+        }
+    }
+
     pub fn seek(&mut self, seek: SeekFrom) -> Result<u64> {
         self.reader.seek(seek)
     }
@@ -291,10 +1168,51 @@ where
         self.reader.len()
     }
 
+    // Written before each line, only available if the lines are processed
+    pub fn set_line_prefix(&mut self, prefix: Vec<u8>) {
+        if let Some(processor) = &mut self.line_processor {
+            processor.set_prefix(prefix);
+        }
+    }
+
+    // Lines counted since the last call, only available in count mode
+    pub fn take_line_count(&mut self) -> u64 {
+        match &mut self.line_processor {
+            Some(processor) => processor.take_line_count(),
+            None => 0,
+        }
+    }
+
     pub fn printed_eol(&self) -> bool {
         self.printed_eol
     }
 
+    // Returns true if the line processor holds an incomplete line not printed yet
+    pub fn has_partial_line(&self) -> bool {
+        match &self.line_processor {
+            Some(processor) => processor.has_partial_line(),
+            None => false,
+        }
+    }
+
+    // Returns true if the line processor holds the output until the end of the input
+    pub fn has_held_output(&self) -> bool {
+        match &self.line_processor {
+            Some(processor) => processor.has_held_output(),
+            None => false,
+        }
+    }
+
+    // Returns true if the content is left to be read by dump_within
+    pub fn has_pending_read(&self) -> bool {
+        self.read_pending
+    }
+
+    // The pending content is about to be read, which may be capped again
+    pub fn resume_pending_read(&mut self) {
+        self.read_pending = false;
+    }
+
     fn tail_start_position(&mut self, tail_count: u64) -> Result<u64> {
         let mut buffer = [0u8; BUFFER_SIZE];
 
@@ -318,12 +1236,15 @@ where
             return Ok(0);
         }
 
-        // Seek position should be a multiple of BUFFER_SIZE because of read efficiency
-        let mut size = end_index % BUFFER_LEN;
-        if size == 0 {
-            size = BUFFER_LEN;
-        }
-        let mut start_index = max(0, end_index - size);
+        // The scan gives up here, then the output starts from the middle of a long line
+        let scan_limit = self
+            .max_scan_bytes
+            .map_or(0, |max_scan_bytes| len.saturating_sub(max_scan_bytes));
+
+        // Seek position should be a multiple of BUFFER_SIZE because of read efficiency.
+        // The first block must contain the last byte, otherwise the delimiter before it is
+        // taken as the line ending of the file.
+        let mut start_index = end_index - end_index % BUFFER_LEN;
         assert_eq!(0, start_index % BUFFER_LEN);
 
         // Read to buffer
@@ -332,10 +1253,11 @@ where
 
         let mut target = &buffer[..read_size];
 
-        // Skip last line ending
-        if let Some(&x) = target.last() {
-            if x == b'\n' {
-                target = &target[..read_size - 1];
+        // Skip last line ending, including the CR of a CRLF
+        if target.last() == Some(&self.delimiter) {
+            target = &target[..read_size - 1];
+            if self.delimiter == b'\n' && target.last() == Some(&b'\r') {
+                target = &target[..read_size - 2];
             }
         }
 
@@ -343,7 +1265,10 @@ where
         loop {
             // Count end of lines
             for (i, &byte) in target.iter().enumerate().rev() {
-                if byte == b'\n' {
+                if start_index + (i as u64) < scan_limit {
+                    return self.skip_split_crlf(scan_limit);
+                }
+                if byte == self.delimiter {
                     eol_count += 1;
                     if eol_count >= tail_count {
                         return Ok(start_index + i as u64 + 1);
@@ -352,8 +1277,8 @@ where
             }
 
             // End check
-            if start_index == 0 {
-                return Ok(0);
+            if start_index <= scan_limit {
+                return self.skip_split_crlf(scan_limit);
             }
 
             // Read file data into buffer
@@ -364,20 +1289,52 @@ where
         }
     }
 
-    pub fn handle_shrink(&mut self, offset: u64) -> Result<bool> {
-        let len = self.len()?;
-        if len < offset {
-            self.seek(SeekFrom::Start(0))?;
+    // The offset given up at is moved past the LF of a CRLF it splits, which would be printed alone
+    // as an empty line otherwise
+    fn skip_split_crlf(&mut self, offset: u64) -> Result<u64> {
+        if self.delimiter != b'\n' || offset == 0 {
+            return Ok(offset);
+        }
+        let mut pair = [0u8; 2];
+        self.seek(SeekFrom::Start(offset - 1))?;
+        let read_size = self.read(&mut pair)?;
+        if &pair[..read_size] == b"\r\n" {
+            Ok(offset + 1)
+        } else {
+            Ok(offset)
+        }
+    }
+
+    // Returns true if the file got shorter than the last read.
+    // The remaining content was already printed, so reading continues from the new EOF,
+    // which is offset 0 if the file was truncated entirely.
+    pub fn handle_shrink(&mut self) -> Result<bool> {
+        let len = match self.len() {
+            Err(error) if is_removed(&error) => return Ok(false),
+            result => result?,
+        };
+        if len < self.last_len {
+            self.seek(SeekFrom::Start(len))?;
+            self.last_len = len;
+            self.partial_line = Some(Vec::new());
+            self.line_interrupted = false;
             Ok(true)
         } else {
             Ok(false)
         }
     }
 
+    // Start from the end without reading the content, so only appended content is printed
+    pub fn skip_to_end(&mut self) -> Result<u64> {
+        let len = self.len()?;
+        self.last_len = len;
+        self.seek(SeekFrom::Start(len))
+    }
+
     fn seek_with_shrink_handling(&mut self, offset: u64) -> Result<u64> {
         // Shrink handling
-        if self.handle_shrink(offset)? {
-            return Ok(0);
+        if self.len()? < offset {
+            return self.seek(SeekFrom::Start(0));
         }
 
         // Seek to target position
@@ -385,8 +1342,16 @@ where
     }
 
     pub fn dump_to_tail(&mut self) -> Result<u64> {
+        self.dump_within(None)
+    }
+
+    // Read at most the bytes if supplied, so that a file growing faster than read
+    // does not keep the others waiting. The rest is read by the next call.
+    pub fn dump_within(&mut self, max_read: Option<u64>) -> Result<u64> {
         let mut buffer = [0; BUFFER_SIZE];
         let mut offset = self.current_seek();
+        let start = offset;
+        self.read_pending = false;
         let initial_size = (BUFFER_LEN - (offset % BUFFER_LEN)) as usize;
         let mut target = &mut buffer[..initial_size];
 
@@ -399,23 +1364,30 @@ where
         let mut last_byte = target.last().map(u8::to_owned);
 
         if read_size == 0 {
+            self.last_len = offset;
             Ok(offset)
         } else {
             loop {
                 // Write to stdio
-                self.write(&target)?;
+                self.output(&target)?;
 
+                let capped = matches!(max_read, Some(max_read) if offset - start >= max_read);
                 // Read additional data
-                let read_size = self.read(&mut buffer)?;
+                let read_size = if capped { 0 } else { self.read(&mut buffer)? };
                 target = &mut buffer[..read_size];
                 offset += read_size as u64;
                 if read_size == 0 {
+                    self.read_pending = capped;
                     // Flush buffer
+                    self.end_of_output()?;
                     self.flush()?;
 
-                    // Save whether last byte is \n
-                    self.printed_eol = last_byte.map_or(false, |byte| byte == b'\n');
+                    // Save whether last byte is the delimiter
+                    // The line processor holds incomplete lines, so its output always ends with it
+                    self.printed_eol = self.line_processor.is_some()
+                        || last_byte.map_or(false, |byte| byte == self.delimiter);
 
+                    self.last_len = offset;
                     return Ok(offset);
                 }
 
@@ -431,24 +1403,126 @@ where
     U: Write,
 {
     let offset = reader.tail_start_position(tail_count)?;
+    dump_from_offset(reader, offset)
+}
+
+// Print the content from the offset, e.g. returned by tail_within
+pub fn dump_from_offset<T, U>(reader: &mut TailState<T, U>, offset: u64) -> Result<u64>
+where
+    T: Read + Seek + SeekPos + Length,
+    U: Write,
+{
     reader.seek_with_shrink_handling(offset)?;
     reader.dump_to_tail()
 }
 
-pub fn tail2(path: PathBuf, repo: FileRepository, tail_count: u64) -> Result<CachedTailState> {
-    let mut tail_state = CachedTailState::from_path(path, repo)?;
-    let _offset = tail_from_reader(&mut tail_state, tail_count);
+// Returns the offset of the last lines up to the budget, and the number of those lines.
+// Used to share the lines among files, nothing is printed.
+pub fn tail_within<T, U>(reader: &mut TailState<T, U>, budget: u64) -> Result<(u64, u64)>
+where
+    T: Read + Seek + SeekPos + Length,
+    U: Write,
+{
+    let offset = reader.tail_start_position(budget)?;
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut buffer = [0; BUFFER_SIZE];
+    let mut lines = 0;
+    let mut last_byte = None;
+    loop {
+        let read_size = reader.read(&mut buffer)?;
+        if read_size == 0 {
+            break;
+        }
+        let delimiter = reader.delimiter;
+        lines += buffer[..read_size]
+            .iter()
+            .filter(|&&byte| byte == delimiter)
+            .count() as u64;
+        last_byte = Some(buffer[read_size - 1]);
+    }
+    // The incomplete last line
+    if matches!(last_byte, Some(byte) if byte != reader.delimiter) {
+        lines += 1;
+    }
+    Ok((offset, lines))
+}
+
+// Write the last lines of the file to the target file as they are printed, the entire content
+// if tail_count is None
+pub fn write_snapshot(
+    path: &Path,
+    target: &Path,
+    tail_count: Option<u64>,
+    line_options: &Rc<LineOptions>,
+) -> Result<()> {
+    let writer = SharedWriter::new(io::BufWriter::new(File::create(target)?));
+    // The handles of the followed files are left as they are
+    let repo: FileRepository = Rc::new(RefCell::new(HandleCache::new(1)));
+    let mut state = CachedTailState::from_path(path.to_owned(), repo, writer, line_options)?;
+    tail_initial(&mut state, tail_count)?;
+    state.end_of_input()?;
+    state.flush()
+}
+
+// Print the entire content from the beginning
+pub fn dump_from_start<T, U>(reader: &mut TailState<T, U>) -> Result<u64>
+where
+    T: Read + Seek + SeekPos + Length,
+    U: Write,
+{
+    reader.seek(SeekFrom::Start(0))?;
+    reader.dump_to_tail()
+}
+
+// The entire content is printed if tail_count is None
+pub fn tail2<W: Write>(
+    path: PathBuf,
+    repo: FileRepository,
+    writer: SharedWriter<W>,
+    line_options: &Rc<LineOptions>,
+    tail_count: Option<u64>,
+) -> Result<CachedTailState<W>> {
+    let mut tail_state = CachedTailState::from_path(path, repo, writer, line_options)?;
+    let _offset = tail_initial(&mut tail_state, tail_count);
     Ok(tail_state)
 }
 
+// Print the initial content of the opened file, the entire content if tail_count is None
+pub fn tail_initial<W: Write>(
+    tail_state: &mut CachedTailState<W>,
+    tail_count: Option<u64>,
+) -> Result<u64> {
+    match tail_count {
+        // Only the content arrived so far is printed
+        _ if tail_state.is_stream() => tail_state.dump_to_tail(),
+        Some(tail_count) => tail_from_reader(tail_state, tail_count),
+        None => dump_from_start(tail_state),
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::io;
     use std::io::Cursor;
+    use std::io::Read;
     use std::io::Result;
+    use std::io::Write;
+    use std::path::Path;
+    use std::rc::{Rc, Weak};
 
+    use super::read_last_bytes;
     use super::tail_from_reader;
+    use super::Compression;
     use super::Length;
+    use super::SharedWriter;
+    use super::StdinReader;
     use super::TailState;
+    use super::BUFFER_SIZE;
+    use super::{ChainedReader, DecompressedReader, SourceReader};
+    use super::{ReaderCreator, TransparentReader};
+    use crate::cache::HandleCache;
+    use crate::line::LineOptions;
     use crate::tail::SeekPos;
 
     const CONTENT: &str = r#"line1
@@ -472,7 +1546,7 @@ line5"#;
 
     impl SeekPos for Cursor<&[u8]> {
         fn seek_pos(&self) -> u64 {
-            self.get_ref().len() as u64
+            self.position()
         }
     }
 
@@ -481,11 +1555,30 @@ line5"#;
             reader: Cursor<&'a [u8]>,
             writer: &'a mut Vec<u8>,
         ) -> Result<TailState<Cursor<&'a [u8]>, &'a mut Vec<u8>>> {
-            Ok(TailState {
-                reader,
-                writer,
-                printed_eol: false,
-            })
+            Ok(TailState::new(reader, writer))
+        }
+    }
+
+    #[derive(Default)]
+    struct FlushCounter {
+        flush_count: usize,
+    }
+
+    impl Write for FlushCounter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.flush_count += 1;
+            Ok(())
+        }
+    }
+
+    fn tail_files_into(writer: &SharedWriter<FlushCounter>) {
+        for content in &[CONTENT, CONTENT_WITHOUT_LINE_ENDING, CONTENT] {
+            let mut target = TailState::new(Cursor::new(content.as_bytes()), writer.clone());
+            tail_from_reader(&mut target, 1).unwrap();
         }
     }
 
@@ -507,6 +1600,22 @@ line5"#;
         })
     }
 
+    #[test]
+    fn test_dump_within() {
+        let content = "a\n".repeat(BUFFER_SIZE);
+        let content = content.as_str();
+        tail_state_test!(content, |target, writer| {
+            // Stops after a buffer, and continues from there
+            assert_eq!(target.dump_within(Some(1)).unwrap(), BUFFER_SIZE as u64);
+            assert!(target.has_pending_read());
+            assert_eq!(target.dump_within(Some(1)).unwrap(), content.len() as u64);
+            assert!(target.has_pending_read());
+            assert_eq!(target.dump_within(Some(1)).unwrap(), content.len() as u64);
+            assert!(!target.has_pending_read());
+            assert_eq!(writer, content.as_bytes());
+        })
+    }
+
     #[test]
     fn test_dump_to_tail_without_line_ending() {
         tail_state_test!(CONTENT_WITHOUT_LINE_ENDING, |target, writer| {
@@ -532,4 +1641,310 @@ line5"#;
             assert_eq!(writer, "line5".as_bytes());
         })
     }
+
+    fn tail_last_line(content: &str) -> String {
+        let mut writer: Vec<u8> = Vec::new();
+        let mut target =
+            TailState::from_slice(Cursor::new(content.as_bytes()), &mut writer).unwrap();
+        tail_from_reader(&mut target, 1).unwrap();
+        String::from_utf8(writer).unwrap()
+    }
+
+    #[test]
+    fn test_tail_single_line_boundary() {
+        for &size in &[1, BUFFER_SIZE, BUFFER_SIZE + 1] {
+            let without_ending = "a".repeat(size);
+            assert_eq!(tail_last_line(&without_ending), without_ending);
+            let with_ending = format!("{}\n", "a".repeat(size - 1));
+            assert_eq!(tail_last_line(&with_ending), with_ending);
+        }
+    }
+
+    #[test]
+    fn test_tail_delimiter_at_block_end() {
+        let first_line = format!("{}\n", "a".repeat(BUFFER_SIZE - 1));
+        assert_eq!(tail_last_line(&format!("{}b", first_line)), "b");
+        assert_eq!(tail_last_line(&format!("{}\n", first_line)), "\n");
+        assert_eq!(tail_last_line(&format!("{}b\n", first_line)), "b\n");
+    }
+
+    #[test]
+    fn test_read_last_bytes() {
+        let content = "0123456789".repeat(BUFFER_SIZE);
+        let last = read_last_bytes(content.as_bytes(), 15).unwrap();
+        assert_eq!(last, b"567890123456789");
+        let last = read_last_bytes(&b"short"[..], 15).unwrap();
+        assert_eq!(last, b"short");
+    }
+
+    #[test]
+    fn test_chained_reader() {
+        let segment = |content: &str| {
+            SourceReader::Decompressed(DecompressedReader {
+                content: Cursor::new(content.as_bytes().to_vec()),
+            })
+        };
+        let reader = ChainedReader::new(vec![
+            segment("line1\nline2\n"),
+            segment(""),
+            segment("line3\nline4\n"),
+        ])
+        .unwrap();
+        assert_eq!(reader.len().unwrap(), 24);
+
+        let mut writer: Vec<u8> = Vec::new();
+        let mut target = TailState::new(reader, &mut writer);
+        tail_from_reader(&mut target, 3).unwrap();
+        assert_eq!(String::from_utf8(writer).unwrap(), "line2\nline3\nline4\n");
+    }
+
+    #[test]
+    fn test_flush_per_file() {
+        let writer = SharedWriter::new(FlushCounter::default());
+        tail_files_into(&writer);
+        assert_eq!(writer.writer.borrow().flush_count, 3);
+    }
+
+    #[test]
+    fn test_deferred_flush() {
+        let writer = SharedWriter::new(FlushCounter::default());
+        writer.defer_flush(true);
+        tail_files_into(&writer);
+        assert_eq!(writer.writer.borrow().flush_count, 0);
+
+        writer.defer_flush(false);
+        writer.clone().flush().unwrap();
+        assert_eq!(writer.writer.borrow().flush_count, 1);
+    }
+
+    #[test]
+    fn test_flush_per_write() {
+        let writer = SharedWriter::new(FlushCounter::default());
+        writer.flush_per_write(true);
+        tail_files_into(&writer);
+        // Every file is written at once, then flushed at the end of the read
+        assert_eq!(writer.writer.borrow().flush_count, 6);
+    }
+
+    #[test]
+    fn test_tail_crlf() {
+        let content = "line1\r\nline2\r\nline3\r\n";
+        tail_state_test!(content, |target, writer| {
+            assert_eq!(target.tail_start_position(1).unwrap(), 14);
+            assert_eq!(target.tail_start_position(2).unwrap(), 7);
+            assert_eq!(target.tail_start_position(3).unwrap(), 0);
+            let result = tail_from_reader(&mut target, 2);
+            assert!(result.is_ok());
+            assert_eq!(writer, "line2\r\nline3\r\n".as_bytes());
+        });
+        let content = "line1\r\nline2\r\nline3";
+        tail_state_test!(content, |target, writer| {
+            assert_eq!(target.tail_start_position(1).unwrap(), 14);
+            let result = tail_from_reader(&mut target, 1);
+            assert!(result.is_ok());
+            assert_eq!(writer, "line3".as_bytes());
+        });
+        // An empty last line is still a line
+        let content = "line1\r\n\r\n";
+        tail_state_test!(content, |target, writer| {
+            assert_eq!(target.tail_start_position(1).unwrap(), 7);
+            let result = tail_from_reader(&mut target, 1);
+            assert!(result.is_ok());
+            assert_eq!(writer, "\r\n".as_bytes());
+        })
+    }
+
+    #[test]
+    fn test_tail_crlf_max_scan_bytes() {
+        // The scan gives up between the CR and the LF
+        let content = format!("first\r\n{}\r\nlast", "a".repeat(BUFFER_SIZE * 3));
+        let content = content.as_str();
+        tail_state_test!(content, |target, writer| {
+            target.max_scan_bytes = Some(5);
+            let result = tail_from_reader(&mut target, 2);
+            assert!(result.is_ok());
+            assert_eq!(writer, "last".as_bytes());
+        })
+    }
+
+    #[test]
+    fn test_tail_with_delimiter() {
+        let content = "rec1\nmore\x0crec2\x0crec3\nmore\x0c";
+        tail_state_test!(content, |target, writer| {
+            target.delimiter = 0x0c;
+            let result = tail_from_reader(&mut target, 2);
+            assert!(result.is_ok());
+            assert!(target.printed_eol());
+            assert_eq!(writer, "rec2\x0crec3\nmore\x0c".as_bytes());
+        })
+    }
+
+    #[test]
+    fn test_tail_with_delimiter_without_ending() {
+        let content = "rec1\x0crec2\nmore";
+        tail_state_test!(content, |target, writer| {
+            target.delimiter = 0x0c;
+            let result = tail_from_reader(&mut target, 1);
+            assert!(result.is_ok());
+            assert!(!target.printed_eol());
+            assert_eq!(writer, "rec2\nmore".as_bytes());
+        })
+    }
+
+    #[test]
+    fn test_tail_max_scan_bytes() {
+        let content = format!("first\n{}\n", "a".repeat(BUFFER_SIZE * 3));
+        let content = content.as_str();
+        tail_state_test!(content, |target, writer| {
+            target.max_scan_bytes = Some(10);
+            let result = tail_from_reader(&mut target, 1);
+            assert!(result.is_ok());
+            assert_eq!(writer, format!("{}\n", "a".repeat(9)).as_bytes());
+        });
+        // The lines found within the limit are shown as usual
+        tail_state_test!(CONTENT, |target, writer| {
+            target.max_scan_bytes = Some(10);
+            let result = tail_from_reader(&mut target, 1);
+            assert!(result.is_ok());
+            assert_eq!(writer, "line5\n".as_bytes());
+        })
+    }
+
+    #[test]
+    fn test_stdin_tail() {
+        let line_options = Rc::new(LineOptions::default());
+        let mut output: Vec<u8> = Vec::new();
+        let mut reader = StdinReader::new(CONTENT.as_bytes());
+        reader.tail(&mut output, &line_options, Some(2)).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "line4\nline5\n");
+
+        let mut output: Vec<u8> = Vec::new();
+        let mut reader = StdinReader::new(CONTENT_WITHOUT_LINE_ENDING.as_bytes());
+        reader.tail(&mut output, &line_options, Some(2)).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "line4\nline5");
+
+        let mut output: Vec<u8> = Vec::new();
+        let mut reader = StdinReader::new(CONTENT.as_bytes());
+        reader.tail(&mut output, &line_options, None).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), CONTENT);
+    }
+
+    #[test]
+    fn test_stdin_tail_with_line_processor() {
+        let line_options = Rc::new(LineOptions {
+            squeeze: true,
+            ..LineOptions::default()
+        });
+        let mut output: Vec<u8> = Vec::new();
+        let mut reader = StdinReader::new(&b"a\nb\nb\nc"[..]);
+        reader.tail(&mut output, &line_options, Some(3)).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "b\n(repeated 1 time)\nc"
+        );
+    }
+
+    #[test]
+    fn test_stdin_tail_grep_first() {
+        let line_options = Rc::new(LineOptions {
+            grep: Some(regex::bytes::Regex::new("match").unwrap()),
+            before_context: 1,
+            ..LineOptions::default()
+        });
+        let mut output: Vec<u8> = Vec::new();
+        let content = b"match1\nbefore2\nmatch2\nother\nother\nbefore3\nmatch3\nother\n";
+        let mut reader = StdinReader::new(&content[..]);
+        reader.tail(&mut output, &line_options, Some(2)).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "before2\nmatch2\n--\nbefore3\nmatch3\n"
+        );
+    }
+
+    // Fails to open with EMFILE until the count reaches zero
+    #[cfg(unix)]
+    struct ExhaustedCreator {
+        failures: Cell<usize>,
+    }
+
+    #[cfg(unix)]
+    impl ReaderCreator<u32, Cursor<&'static [u8]>> for ExhaustedCreator {
+        fn create_reader(&self, _path: &u32) -> Result<Cursor<&'static [u8]>> {
+            if self.failures.get() > 0 {
+                self.failures.set(self.failures.get() - 1);
+                return Err(io::Error::from_raw_os_error(libc::EMFILE));
+            }
+            Ok(Cursor::new(CONTENT.as_bytes()))
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reopen_after_emfile() {
+        let repository = Rc::new(RefCell::new(HandleCache::new(2)));
+        repository
+            .borrow_mut()
+            .put(0, Rc::new(RefCell::new(Cursor::new(&b"old"[..]))));
+        let reader_for = |failures| TransparentReader {
+            reader_repository: Rc::clone(&repository),
+            path: 1,
+            reader_seek_pos: 0,
+            reader_cache: RefCell::new(Weak::new()),
+            reader_creator: ExhaustedCreator {
+                failures: Cell::new(failures),
+            },
+        };
+
+        let mut content = String::new();
+        reader_for(1).read_to_string(&mut content).unwrap();
+        assert_eq!(content, CONTENT);
+        // The least recently used file is closed to open the new one
+        assert!(!repository.borrow().contains(&0));
+        assert!(repository.borrow().contains(&1));
+
+        // Retried only once
+        repository.borrow_mut().clear();
+        repository
+            .borrow_mut()
+            .put(0, Rc::new(RefCell::new(Cursor::new(&b"old"[..]))));
+        let error = reader_for(2).read_to_string(&mut content).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::EMFILE));
+    }
+
+    struct MissingCreator;
+
+    impl ReaderCreator<u32, Cursor<&'static [u8]>> for MissingCreator {
+        fn create_reader(&self, _path: &u32) -> Result<Cursor<&'static [u8]>> {
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        }
+    }
+
+    #[test]
+    fn test_read_removed_file() {
+        let mut reader = TransparentReader {
+            reader_repository: Rc::new(RefCell::new(HandleCache::new(1))),
+            path: 0,
+            reader_seek_pos: 0,
+            reader_cache: RefCell::new(Weak::new()),
+            reader_creator: MissingCreator,
+        };
+        let mut buf = [0; 16];
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_compression_magic() {
+        assert_eq!(
+            Compression::from_path(Path::new("app.log.xz")),
+            Some(Compression::Xz)
+        );
+        assert_eq!(
+            Compression::from_path(Path::new("app.log.bz2")),
+            Some(Compression::Bzip2)
+        );
+        assert_eq!(Compression::from_path(Path::new("app.log")), None);
+        assert!(Compression::Xz.has_magic(b"\xFD7zXZ\x00\x00"));
+        assert!(!Compression::Xz.has_magic(b"BZh91AY"));
+        assert!(Compression::Bzip2.has_magic(b"BZh91AY"));
+    }
 }
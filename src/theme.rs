@@ -0,0 +1,141 @@
+/*
+ * Copyright 2019 StoneDot (Hiroaki Goto)
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::path::Path;
+
+use ansi_term::Colour::{self, Black, Blue, Cyan, Fixed, Green, Purple, Red, White, Yellow};
+use ansi_term::Style;
+
+// Green is excluded because it highlights the part matched with the regex
+const DEFAULT_PALETTE: [Colour; 6] = [Blue, Yellow, Purple, Cyan, Red, Fixed(208)];
+// Dark colors readable on a light background
+const LIGHT_PALETTE: [Colour; 6] = [
+    Fixed(18),
+    Fixed(88),
+    Fixed(90),
+    Fixed(23),
+    Fixed(130),
+    Fixed(54),
+];
+// Colors distinguishable with the common color vision deficiencies
+const HIGH_CONTRAST_PALETTE: [Colour; 4] = [Fixed(208), Fixed(39), Fixed(226), Fixed(15)];
+
+// Styles of the headers, selected by name with --theme
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Theme {
+    pub header: Style,
+    // Part of the path matched with the regex
+    pub path_match: Style,
+    // Header colors picked by the path with --multicolor, the header style is used if empty
+    pub palette: &'static [Colour],
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            header: Blue.bold(),
+            path_match: Green.bold(),
+            palette: &DEFAULT_PALETTE,
+        }
+    }
+}
+
+impl Theme {
+    pub const NAMES: [&'static str; 4] = ["default", "light", "mono", "high-contrast"];
+
+    // Name of the predefined theme, None if customized
+    pub fn name(&self) -> Option<&'static str> {
+        Theme::NAMES
+            .iter()
+            .copied()
+            .find(|&name| Theme::from_name(name).as_ref() == Some(self))
+    }
+
+    pub fn from_name(name: &str) -> Option<Theme> {
+        match name {
+            "default" => Some(Theme::default()),
+            "light" => Some(Theme {
+                header: Fixed(18).bold(),
+                path_match: Fixed(22).bold(),
+                palette: &LIGHT_PALETTE,
+            }),
+            "mono" => Some(Theme {
+                header: Style::new().bold(),
+                path_match: Style::new().bold().underline(),
+                palette: &[],
+            }),
+            "high-contrast" => Some(Theme {
+                header: White.on(Blue).bold(),
+                path_match: Black.on(Yellow).bold(),
+                palette: &HIGH_CONTRAST_PALETTE,
+            }),
+            _ => None,
+        }
+    }
+
+    // Pick a stable color for the file so that the streams can be distinguished.
+    // FNV-1a is used to keep the color same across runs and builds.
+    pub fn palette_color(&self, path: &Path) -> Option<Colour> {
+        if self.palette.is_empty() {
+            return None;
+        }
+        let hash = path
+            .to_string_lossy()
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            });
+        Some(self.palette[(hash % self.palette.len() as u64) as usize])
+    }
+
+    pub fn header_style(&self, path: &Path, multicolor: bool) -> Style {
+        match self.palette_color(path) {
+            Some(color) if multicolor => color.bold(),
+            _ => self.header,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use ansi_term::Colour::Blue;
+
+    use super::Theme;
+
+    #[test]
+    fn test_from_name() {
+        for name in Theme::NAMES.iter() {
+            assert_eq!(Theme::from_name(name).unwrap().name(), Some(*name));
+        }
+        assert_eq!(Theme::from_name("unknown"), None);
+        assert_eq!(Theme::from_name("default").unwrap().header, Blue.bold());
+    }
+
+    #[test]
+    fn test_header_style() {
+        let path = Path::new("app.log");
+        let theme = Theme::default();
+        assert_eq!(theme.header_style(path, false), Blue.bold());
+        assert_eq!(
+            theme.header_style(path, true),
+            theme.palette_color(path).unwrap().bold()
+        );
+        let mono = Theme::from_name("mono").unwrap();
+        assert_eq!(mono.header_style(path, true), mono.header);
+    }
+}
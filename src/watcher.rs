@@ -14,56 +14,593 @@
  * limitations under the License.
  */
 
-use std::cell::RefCell;
-use std::collections::{HashMap, VecDeque};
-use std::fs::File;
-use std::io::{self, BufWriter, ErrorKind, Stdout};
-use std::path::{Path, PathBuf};
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, ErrorKind, SeekFrom, Stdout, Write};
+use std::path::{is_separator, Path, PathBuf};
 use std::rc::Rc;
-use std::sync::mpsc::channel;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use ansi_term::Colour::Blue;
-use lru::LruCache;
-use notify::{op::Op, raw_watcher, Error as NotifyError, RawEvent, Watcher};
+use ansi_term::Style;
+use chrono::{DateTime, Local};
+use notify::{
+    op::Op, raw_watcher, watcher as debounced_watcher, DebouncedEvent, Error as NotifyError,
+    PollWatcher, RawEvent, RecursiveMode, Watcher,
+};
 use pathdiff::diff_paths;
+#[cfg(unix)]
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1},
+    iterator::Signals,
+    low_level::emulate_default_handler,
+};
 
-use crate::tail::{CachedTailState, SeekPos};
+use crate::tail::{is_incomplete_compression, CachedTailState};
 
-use super::filter::PathFilter;
-use super::tail::{tail2, FileReader, FileRepository, Length, TailState};
-use super::Opt;
+use super::cache::{parent_dir, HandleCache};
+use super::error::RegtailError;
+use super::filter::{container_stream_of, group_container_logs, group_rotated, PathFilter};
+use super::line::{plural, LineOptions};
+use super::lines_config::LineCounts;
+use super::merge::MERGE_WINDOW;
+#[cfg(unix)]
+use super::socket::SharedEventSocket;
+use super::state::{FilePosition, TailPositions};
+use super::tail::{
+    dump_from_offset, file_id, tail_initial, tail_within, write_snapshot, FileRepository,
+    SharedWriter, TeeTarget, TeeWriter,
+};
+use super::theme::Theme;
+use crate::config::Config;
 
 const MAX_FILE_HANDLE: usize = 512;
+// Interval to check events and flush the output
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+// Interval to check whether named pipes have content
+const STREAM_POLL_INTERVAL: Duration = Duration::from_millis(200);
+// Interval to scan the files when the polling watcher is used
+const POLL_INTERVAL_MS: u32 = 500;
+// Time to wait for new files before exiting by --exit-when-empty
+const EMPTY_GRACE_PERIOD: Duration = Duration::from_secs(1);
+// Window to aggregate the new lines in count mode
+const COUNT_INTERVAL: Duration = Duration::from_secs(1);
+// Interval to save the offsets to the state file
+const STATE_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+// Events queued while the output is paused at most
+const MAX_PAUSED_EVENTS: usize = 65536;
+// Replaces the beginning of the truncated path in the headers
+const ELLIPSIS: &str = "\u{2026}";
 
-pub struct DirectoryWatcher<T, U>
+// Hold the watcher while following, events are stopped when it is dropped
+struct ActiveWatcher {
+    _watcher: Box<dyn Any>,
+    // The polling watcher reports a new file only by CREATE
+    polling: bool,
+}
+
+impl ActiveWatcher {
+    fn new<W: Watcher + 'static>(watcher: W, polling: bool) -> ActiveWatcher {
+        ActiveWatcher {
+            _watcher: Box::new(watcher),
+            polling,
+        }
+    }
+}
+
+// Returns the hint for the error caused by the limit of the OS
+#[cfg(target_os = "linux")]
+fn watch_error_hint(error: &NotifyError) -> Option<&'static str> {
+    match error {
+        NotifyError::Io(error) => match error.raw_os_error() {
+            Some(libc::ENOSPC) => Some(
+                "the inotify watch limit is reached; \
+                 raise fs.inotify.max_user_watches (e.g. sysctl fs.inotify.max_user_watches=524288) \
+                 or use --poll",
+            ),
+            Some(libc::EMFILE) => Some(
+                "the inotify instance limit is reached; \
+                 raise fs.inotify.max_user_instances or use --poll",
+            ),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch_error_hint(_error: &NotifyError) -> Option<&'static str> {
+    None
+}
+
+fn watch_natively(tx: Sender<RawEvent>, config: &Config) -> Result<ActiveWatcher, NotifyError> {
+    let mut watcher = raw_watcher(tx)?;
+    for watch_dir in config.watch_dirs() {
+        watcher.watch(watch_dir.as_os_str(), config.recursive_mode())?;
+    }
+    Ok(ActiveWatcher::new(watcher, false))
+}
+
+fn watch_by_polling(tx: Sender<RawEvent>, config: &Config) -> Result<ActiveWatcher, NotifyError> {
+    let mut watcher = PollWatcher::with_delay_ms(tx, POLL_INTERVAL_MS)?;
+    for watch_dir in config.watch_dirs() {
+        watcher.watch(watch_dir.as_os_str(), config.recursive_mode())?;
+    }
+    Ok(ActiveWatcher::new(watcher, true))
+}
+
+// Convert a debounced event into the raw events which have the same effect
+fn to_raw_events(event: DebouncedEvent, cookie: u32) -> Vec<RawEvent> {
+    let raw_event = |path: PathBuf, op: Op, cookie: Option<u32>| RawEvent {
+        path: Some(path),
+        op: Ok(op),
+        cookie,
+    };
+    match event {
+        // A new file is reported only by Create, whose content has to be printed as well
+        DebouncedEvent::Create(path) if !path.is_dir() => vec![raw_event(path, Op::WRITE, None)],
+        DebouncedEvent::Create(path) => vec![raw_event(path, Op::CREATE, None)],
+        DebouncedEvent::Write(path) => vec![raw_event(path, Op::WRITE, None)],
+        DebouncedEvent::Remove(path) => vec![raw_event(path, Op::REMOVE, None)],
+        DebouncedEvent::Rename(from, to) => vec![
+            raw_event(from, Op::RENAME, Some(cookie)),
+            raw_event(to, Op::RENAME, Some(cookie)),
+        ],
+        DebouncedEvent::Error(error, _) => vec![RawEvent {
+            path: None,
+            op: Err(error),
+            cookie: None,
+        }],
+        DebouncedEvent::NoticeWrite(_)
+        | DebouncedEvent::NoticeRemove(_)
+        | DebouncedEvent::Chmod(_)
+        | DebouncedEvent::Rescan => Vec::new(),
+    }
+}
+
+// The debounced events are forwarded as raw events, so the event loop handles both in the same way
+fn watch_debounced(
+    tx: Sender<RawEvent>,
+    config: &Config,
+    delay: Duration,
+) -> Result<ActiveWatcher, NotifyError> {
+    let (debounced_tx, debounced_rx) = channel();
+    let mut watcher = debounced_watcher(debounced_tx, delay)?;
+    for watch_dir in config.watch_dirs() {
+        watcher.watch(watch_dir.as_os_str(), config.recursive_mode())?;
+    }
+    thread::spawn(move || {
+        // Ends when the watcher is dropped
+        for (cookie, event) in debounced_rx.into_iter().enumerate() {
+            for raw_event in to_raw_events(event, cookie as u32) {
+                if tx.send(raw_event).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(ActiveWatcher::new(watcher, false))
+}
+
+// Log management tools send SIGHUP after the rotation, then the files are reopened
+// if --reload-on-sighup is supplied. SIGUSR1 toggles the pause of the output, and the files
+// are read again on resume. SIGINT and SIGTERM are caught only if the terminated flag is given,
+// which the event loop checks to end after the offsets are saved. The signals are forwarded as
+// a rescan event to wake up the event loop immediately.
+#[cfg(unix)]
+fn forward_signals(
+    tx: Sender<RawEvent>,
+    paused: Arc<AtomicBool>,
+    reload_on_sighup: bool,
+    terminated: Option<Arc<AtomicI32>>,
+) -> io::Result<()> {
+    let mut signals = Signals::new([SIGUSR1])?;
+    if reload_on_sighup {
+        signals.add_signal(SIGHUP)?;
+    }
+    if terminated.is_some() {
+        signals.add_signal(SIGINT)?;
+        signals.add_signal(SIGTERM)?;
+    }
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGINT || signal == SIGTERM {
+                if let Some(terminated) = &terminated {
+                    terminated.store(signal, Ordering::SeqCst);
+                }
+            }
+            if signal == SIGUSR1 {
+                if paused.fetch_xor(true, Ordering::SeqCst) {
+                    eprintln!("output resumed");
+                } else {
+                    eprintln!("output paused, send SIGUSR1 again to resume");
+                }
+            }
+            let event = RawEvent {
+                path: None,
+                op: Ok(Op::RESCAN),
+                cookie: None,
+            };
+            if tx.send(event).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+// The active filters of the file names and contents, shown when nothing matches
+fn describe_filters(config: &Config) -> String {
+    let mut filters = Vec::new();
+    if let Some(regex) = &config.regex {
+        filters.push(regex.clone());
+    }
+    if !config.extensions.is_empty() {
+        filters.push(format!("--ext {}", config.extensions.join(",")));
+    }
+    if let Some(pattern) = &config.content_match {
+        filters.push(format!("--content-match {}", pattern));
+    }
+    if let Some(owner) = &config.owner {
+        filters.push(format!("--owner {}", owner));
+    }
+    if let Some(mode) = config.mode {
+        filters.push(format!("--mode {:o}", mode));
+    }
+    if filters.is_empty() {
+        ".*".to_owned()
+    } else {
+        filters.join(" ")
+    }
+}
+
+// The separators are percent-encoded, so that the names are as unique as the paths are
+fn snapshot_name(path: &Path) -> String {
+    let mut name = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '%' => name.push_str("%25"),
+            ':' => name.push_str("%3A"),
+            c if is_separator(c) => name.push_str(&format!("%{:02X}", c as u32)),
+            c => name.push(c),
+        }
+    }
+    name
+}
+
+// Byte offset to show only the last characters of the path, never splitting a character
+fn truncated_start(path: &str, max_chars: usize) -> usize {
+    match path.char_indices().rev().nth(max_chars.saturating_sub(1)) {
+        Some((index, _)) if max_chars > 0 && index > 0 => index,
+        _ => 0,
+    }
+}
+
+// Byte offset of the path following the prefix, compared by the components
+fn stripped_start(path: &str, prefix: &Path) -> usize {
+    // The trailing separators are not a part of the components
+    let path = path.trim_end_matches(std::path::is_separator);
+    match Path::new(path).strip_prefix(prefix) {
+        Ok(rest) if !rest.as_os_str().is_empty() => path.len() - rest.as_os_str().len(),
+        _ => 0,
+    }
+}
+
+// Human readable size such as 1.2 MiB
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    let mut scaled = size as f64 / 1024.0;
+    let mut unit = 0;
+    while scaled >= 1024.0 && unit < UNITS.len() - 1 {
+        scaled /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", scaled, UNITS[unit])
+}
+
+// Size and last modified time shown in the header, such as (1.2 MiB, 2024-06-01 12:00)
+fn header_meta(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    let size = format_size(metadata.len());
+    match metadata.modified() {
+        Ok(modified) => {
+            let modified: DateTime<Local> = modified.into();
+            Some(format!("({}, {})", size, modified.format("%Y-%m-%d %H:%M")))
+        }
+        Err(_) => Some(format!("({})", size)),
+    }
+}
+
+// URL of the canonical path for OSC 8 links, where the bytes not allowed in a path are escaped
+fn file_url(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut url = String::from(if path.starts_with('/') {
+        "file://"
+    } else {
+        "file:///"
+    });
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                url.push(byte as char)
+            }
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    url
+}
+
+// Shortest trailing components telling each path from the others, such as a/current and b/current
+fn unique_suffixes<'a, I: Iterator<Item = &'a PathBuf>>(paths: I) -> HashMap<PathBuf, String> {
+    let paths: Vec<(&PathBuf, Vec<String>)> = paths
+        .map(|path| {
+            let components = path
+                .components()
+                .rev()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            (path, components)
+        })
+        .collect();
+    let max_depth = paths.iter().map(|(_, components)| components.len()).max();
+    let mut suffixes = HashMap::new();
+    for depth in 1..=max_depth.unwrap_or(0) {
+        let mut counts: HashMap<&[String], usize> = HashMap::new();
+        for (_, components) in &paths {
+            *counts
+                .entry(&components[..depth.min(components.len())])
+                .or_insert(0) += 1;
+        }
+        for (path, components) in &paths {
+            if suffixes.contains_key(*path) {
+                continue;
+            }
+            let suffix = &components[..depth.min(components.len())];
+            if counts[suffix] == 1 || depth >= components.len() {
+                let tag: Vec<&str> = suffix.iter().rev().map(String::as_str).collect();
+                suffixes.insert(path.to_path_buf(), tag.join("/"));
+            }
+        }
+    }
+    suffixes
+}
+
+// Every operation is logged as received, before the ones not followed are ignored
+fn log_event(event: &RawEvent) {
+    let path = event
+        .path
+        .as_ref()
+        .map_or_else(|| "-".to_owned(), |path| path.display().to_string());
+    let cookie = event
+        .cookie
+        .map_or_else(String::new, |cookie| format!(" (cookie {})", cookie));
+    match &event.op {
+        Ok(op) => eprintln!("event: {:?} {}{}", op, path, cookie),
+        Err(error) => eprintln!("event: error {} {}", error, path),
+    }
+}
+
+fn start_watcher(tx: Sender<RawEvent>, config: &Config) -> Result<ActiveWatcher, NotifyError> {
+    if config.poll {
+        return watch_by_polling(tx, config);
+    }
+    if let Some(delay) = config.debounce {
+        return watch_debounced(tx, config, delay);
+    }
+    match watch_natively(tx.clone(), config) {
+        Ok(watcher) => Ok(watcher),
+        Err(error) => {
+            if let Some(hint) = watch_error_hint(&error) {
+                eprintln!("{}", hint);
+            }
+            if !config.poll_on_error {
+                return Err(error);
+            }
+            eprintln!(
+                "failed to watch ({}), falling back to polling",
+                RegtailError::from(error)
+            );
+            watch_by_polling(tx, config)
+        }
+    }
+}
+
+// Convert a canonical Windows path into the form users type, such as C:\dir or \\server\share.
+// Separators are unified to backslashes and redundant or trailing ones are removed.
+fn normalize_windows_path(path: &str) -> String {
+    let path = path.replace('/', "\\");
+    let (prefix, rest) = if let Some(rest) = path.strip_prefix("\\\\?\\UNC\\") {
+        ("\\\\", rest)
+    } else if let Some(rest) = path.strip_prefix("\\\\?\\") {
+        ("", rest)
+    } else if let Some(rest) = path.strip_prefix("\\\\") {
+        ("\\\\", rest)
+    } else if let Some(rest) = path.strip_prefix('\\') {
+        ("\\", rest)
+    } else {
+        ("", &path[..])
+    };
+    let components: Vec<&str> = rest.split('\\').filter(|c| !c.is_empty()).collect();
+    let mut normalized = String::from(prefix);
+    normalized.push_str(&components.join("\\"));
+    // The root of a drive keeps its separator, otherwise it means the current directory of the drive
+    let is_drive_root =
+        components.len() == 1 && components[0].ends_with(':') && rest.contains('\\');
+    if prefix.is_empty() && is_drive_root {
+        normalized.push('\\');
+    }
+    normalized
+}
+
+pub struct DirectoryWatcher<W = BufWriter<Stdout>>
 where
-    T: std::io::Read + std::io::Seek + SeekPos + Length,
-    U: std::io::Write,
+    W: Write,
 {
     filter: PathFilter,
     current_dir: Option<PathBuf>,
     selected_file_path: Option<PathBuf>,
-    file_map: HashMap<PathBuf, CachedTailState>,
-    renaming_map: HashMap<u32, Option<TailState<T, U>>>,
+    file_map: HashMap<PathBuf, CachedTailState<W>>,
+    // The renamed file with its old path, paired with the new path by the cookie
+    renaming_map: HashMap<u32, Option<(PathBuf, CachedTailState<W>)>>,
     repository: FileRepository,
+    writer: SharedWriter<W>,
+    line_options: Rc<LineOptions>,
     colorize: bool,
+    multicolor: bool,
+    theme: Theme,
+    // Print the number of new lines instead of the headers and the lines
+    count: bool,
+    // Keep following a file renamed to a path not matching the regex
+    follow_renamed: bool,
+    // Write the file bytes exactly, without the headers, the separators and the added line feeds
+    raw: bool,
+    // Add a line feed to an incomplete line before the header of another file
+    eol_fix: bool,
+    // Print a marker when a followed file is removed or renamed away
+    eof_marker: bool,
+    // Switch of the mirrored output, set only if the output is mirrored
+    tee_target: Option<Rc<Cell<TeeTarget>>>,
+    // Show the canonical path in the headers instead of the relative one
+    absolute_paths: bool,
+    // Show the target of a symbolic link instead of the link
+    dereference: bool,
+    // Link found on startup for each canonical path, shown in the headers unless dereferenced
+    link_names: HashMap<PathBuf, PathBuf>,
+    // The current directory is replaced by the canonical directory of --relative-to
+    relative_to: bool,
+    // Show only the last characters of a longer path in the headers
+    truncate_header: Option<usize>,
+    // Leading directories hidden from the paths in the headers
+    strip_prefix: Option<PathBuf>,
+    // Bytes read from a file per event at most
+    max_read_per_event: Option<u64>,
+    // The events through the symbolic links are for the files they point to
+    follow_symlinks: bool,
+    // The container logs are headed by their directory, the logical stream of the container
+    container_logs: bool,
+    // Show the size and the last modified time in the headers
+    header_meta: bool,
+    // Wrap the path in the colorized headers with an OSC 8 link to the file
+    hyperlinks: bool,
+    // Prefix the lines and the headers with the tag of the file
+    tag: bool,
+    // Shortest unique path suffix of every followed file, recomputed as files are added or removed
+    tags: HashMap<PathBuf, String>,
+    // Prefix the lines with the path of the file, after the tag
+    prefix: bool,
+    // Line between files, a blank line if None and nothing if empty
+    separator: Option<String>,
+    // Files failed to be opened on startup
+    failed_files: usize,
+    // Canonical watched directories and the depth limit, set only in recursive mode
+    recursive_roots: Vec<PathBuf>,
+    depth: Option<usize>,
+    // Files followed at most, new files are ignored after reaching it
+    max_files: Option<usize>,
+    // Lines shown on startup for the files matching the patterns of --lines-config
+    line_counts: LineCounts,
+    // Offsets read so far are saved to the file, and the last saved ones are kept
+    state_file: Option<PathBuf>,
+    positions: TailPositions,
+    // Files with no writes for the duration are released
+    idle_timeout: Option<Duration>,
+    // Last write of each followed file, tracked only with the idle timeout
+    last_activity: HashMap<PathBuf, Instant>,
+    // Offsets of the released files, resumed on their next write
+    released: TailPositions,
+    // Tell which idle files are released
+    verbose: bool,
+    // Receives the lines with the path of their file, set only if the output is mirrored to it
+    #[cfg(unix)]
+    event_socket: Option<SharedEventSocket>,
+}
+
+impl DirectoryWatcher {
+    pub fn new(config: &Config) -> Result<DirectoryWatcher, RegtailError> {
+        Self::with_writer(config, BufWriter::new(io::stdout()))
+    }
 }
 
-impl DirectoryWatcher<FileReader, BufWriter<Stdout>> {
-    pub fn new(opt: &Opt) -> Result<DirectoryWatcher<FileReader, BufWriter<Stdout>>, i32> {
-        // Check whether supplied path is a directory
-        if !opt.watch_path_is_dir() {
-            eprintln!("supplied path is not a directory");
-            return Err(1);
+impl<W1: Write, W2: Write> DirectoryWatcher<TeeWriter<W1, W2>> {
+    // Mirror the output to the secondary writer, where the headers are never colorized
+    pub fn with_tee(
+        config: &Config,
+        primary: W1,
+        secondary: W2,
+    ) -> Result<DirectoryWatcher<TeeWriter<W1, W2>>, RegtailError> {
+        let writer = TeeWriter::new(primary, secondary);
+        let tee_target = writer.target();
+        Self::build(config, writer, Some(tee_target))
+    }
+}
+
+impl<W> DirectoryWatcher<W>
+where
+    W: Write,
+{
+    pub fn with_writer(config: &Config, writer: W) -> Result<DirectoryWatcher<W>, RegtailError> {
+        Self::build(config, writer, None)
+    }
+
+    fn build(
+        config: &Config,
+        writer: W,
+        tee_target: Option<Rc<Cell<TeeTarget>>>,
+    ) -> Result<DirectoryWatcher<W>, RegtailError> {
+        // Check whether supplied path exists, either a directory or a single file
+        if !config.watch_path().exists() {
+            return Err(RegtailError::PathNotFound(config.watch_path().clone()));
         }
 
         // Generate filter
-        let filter = PathFilter::new(&opt)?;
+        let filter = PathFilter::new(&config)?;
+
+        let mut line_options = config.line_options()?;
+        line_options.tee_target = tee_target.clone();
+
+        let recursive_roots = match config.recursive_mode() {
+            RecursiveMode::Recursive => config
+                .watch_dirs()
+                .iter()
+                .filter_map(|watch_dir| Self::canonicalize_path(watch_dir).ok())
+                .collect(),
+            RecursiveMode::NonRecursive => Vec::new(),
+        };
 
         // Retrieve current directory
-        let current_dir = std::env::current_dir().ok();
+        let current_dir = match &config.relative_to {
+            Some(dir) if !dir.exists() => return Err(RegtailError::PathNotFound(dir.clone())),
+            Some(dir) if !dir.is_dir() => return Err(RegtailError::NotDirectory(dir.clone())),
+            Some(dir) => Some(Self::canonicalize_path(dir)?),
+            None => std::env::current_dir().ok(),
+        };
+
+        let positions = match &config.state_file {
+            Some(state_file) => {
+                TailPositions::load(state_file).map_err(RegtailError::InvalidStateFile)?
+            }
+            None => TailPositions::default(),
+        };
 
-        let repository: FileRepository = Rc::new(RefCell::new(LruCache::new(MAX_FILE_HANDLE)));
+        let line_counts = match &config.lines_config {
+            Some(path) => LineCounts::load(path).map_err(RegtailError::InvalidLinesConfig)?,
+            None => LineCounts::default(),
+        };
+
+        let cache = if config.fair_cache {
+            HandleCache::fair(MAX_FILE_HANDLE, parent_dir)
+        } else {
+            HandleCache::new(MAX_FILE_HANDLE)
+        };
+        let repository: FileRepository = Rc::new(RefCell::new(cache));
 
         Ok(DirectoryWatcher {
             filter,
@@ -72,37 +609,176 @@ impl DirectoryWatcher<FileReader, BufWriter<Stdout>> {
             file_map: HashMap::new(),
             renaming_map: HashMap::new(),
             repository,
-            colorize: opt.colorize,
+            writer: SharedWriter::new(writer),
+            line_options: Rc::new(line_options),
+            colorize: config.colorize,
+            multicolor: config.multicolor,
+            theme: config.theme,
+            count: config.count,
+            follow_renamed: config.follow_renamed,
+            // The merged lines of the files are interleaved, so no header tells their file
+            raw: config.raw || config.merge,
+            eol_fix: !config.no_eol_fix,
+            eof_marker: config.eof_marker,
+            tee_target,
+            absolute_paths: config.absolute_paths,
+            dereference: config.dereference,
+            link_names: HashMap::new(),
+            relative_to: config.relative_to.is_some(),
+            truncate_header: config.truncate_header,
+            strip_prefix: config.strip_prefix.clone(),
+            max_read_per_event: config.max_read_per_event,
+            follow_symlinks: config.follow_symlinks,
+            container_logs: config.container_logs,
+            header_meta: config.header_meta,
+            hyperlinks: config.hyperlinks,
+            tag: config.tag,
+            tags: HashMap::new(),
+            prefix: config.prefix,
+            separator: config.separator.clone(),
+            failed_files: 0,
+            recursive_roots,
+            depth: config.depth(),
+            max_files: config.max_files,
+            line_counts,
+            state_file: config.state_file.clone(),
+            positions,
+            idle_timeout: config.idle_timeout,
+            last_activity: HashMap::new(),
+            released: TailPositions::default(),
+            verbose: config.verbose,
+            #[cfg(unix)]
+            event_socket: None,
         })
     }
-}
 
-impl DirectoryWatcher<FileReader, BufWriter<Stdout>> {
-    fn print_normalized_path(&self, path: &Path) {
-        let relative_path = path.to_string_lossy();
+    // The socket has to be shared with an EventWriter in the output
+    #[cfg(unix)]
+    pub fn set_event_socket(&mut self, event_socket: SharedEventSocket) {
+        self.event_socket = Some(event_socket);
+    }
+
+    // The content written is sent as the lines of the path, None while printing the headers
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    fn set_event_source(&self, path: Option<&Path>) {
+        #[cfg(unix)]
+        if let Some(event_socket) = &self.event_socket {
+            event_socket.borrow_mut().set_source(path);
+        }
+    }
+
+    // Clients connecting while the files are idle are accepted at every tick
+    fn accept_event_clients(&self) {
+        #[cfg(unix)]
+        if let Some(event_socket) = &self.event_socket {
+            event_socket.borrow_mut().accept_clients();
+        }
+    }
+
+    // The style of the header, in the color of the file with --multicolor
+    fn header_style(&self, shown_path: &Path) -> Style {
+        let shown_path = match container_stream_of(shown_path) {
+            Some(stream) if self.container_logs => stream,
+            _ => shown_path,
+        };
+        let relative_path = shown_path.to_string_lossy();
         let display_path = relative_path.trim_start_matches("./");
+        self.theme
+            .header_style(Path::new(display_path), self.multicolor)
+    }
 
-        if self.colorize {
-            print!("{}", Blue.bold().paint("==> "));
-            self.filter.print_path_with_color(display_path);
-            println!("{}", Blue.bold().paint(" <=="));
+    fn print_normalized_path(&mut self, path: &Path) -> io::Result<()> {
+        // A container log is shown as its stream, which is the same across the restarts
+        let shown_path = match container_stream_of(path) {
+            Some(stream) if self.container_logs => stream,
+            _ => path,
+        };
+        let relative_path = shown_path.to_string_lossy();
+        let display_path = relative_path.trim_start_matches("./");
+        // A relative path is shown relative to the directory of --relative-to if supplied
+        let file_path = match &self.current_dir {
+            Some(base) if self.relative_to && path.is_relative() => base.join(path),
+            _ => path.to_owned(),
+        };
+        let meta = if self.header_meta {
+            header_meta(&file_path).map_or_else(String::new, |meta| format!(" {}", meta))
         } else {
-            println!("==> {} <==", display_path);
+            String::new()
+        };
+        // Resolved only if tagged, which is costly for many files
+        let tag = if self.tag {
+            Self::canonicalize_path(&file_path)
+                .ok()
+                .and_then(|canonical_path| self.tags.get(&canonical_path))
+        } else {
+            None
+        };
+        let tag = tag.map_or_else(String::new, |tag| format!("[{}] ", tag));
+        // The stripped prefix is hidden as the truncated start is, leaving the path as is otherwise
+        let stripped_from = match &self.strip_prefix {
+            Some(prefix) => stripped_start(display_path, prefix),
+            None => 0,
+        };
+        let visible_from = match self.truncate_header {
+            Some(max_chars) => {
+                stripped_from + truncated_start(&display_path[stripped_from..], max_chars)
+            }
+            None => stripped_from,
+        };
+        let ellipsis = if visible_from > stripped_from {
+            ELLIPSIS
+        } else {
+            ""
+        };
+        let plain_header = format!(
+            "==> {}{}{}{} <==",
+            tag,
+            ellipsis,
+            &display_path[visible_from..],
+            meta
+        );
+
+        if !self.colorize {
+            return writeln!(self.writer, "{}", plain_header);
+        }
+
+        let style = self.header_style(path);
+        if let Some(tee_target) = &self.tee_target {
+            tee_target.set(TeeTarget::Primary);
+        }
+        write!(self.writer, "{}", style.paint("==> "))?;
+        write!(self.writer, "{}", style.paint(tag))?;
+        write!(self.writer, "{}", style.paint(ellipsis))?;
+        let link = if self.hyperlinks {
+            Self::canonicalize_path(&file_path)
+                .ok()
+                .map(|path| file_url(&path))
+        } else {
+            None
+        };
+        if let Some(link) = &link {
+            write!(self.writer, "\x1b]8;;{}\x1b\\", link)?;
         }
+        // The matches are searched in the whole path, so they are the same as without truncation
+        self.filter
+            .write_path_with_color(&mut self.writer, display_path, visible_from, style)?;
+        if link.is_some() {
+            write!(self.writer, "\x1b]8;;\x1b\\")?;
+        }
+        writeln!(self.writer, "{}{}", meta, style.paint(" <=="))?;
+        // The mirrored output is kept free from color codes
+        if let Some(tee_target) = &self.tee_target {
+            tee_target.set(TeeTarget::Secondary);
+            writeln!(self.writer, "{}", plain_header)?;
+            tee_target.set(TeeTarget::Both);
+        }
+        Ok(())
     }
 
     fn normalize_path_for_windows(canonical_path: PathBuf) -> PathBuf {
         if cfg!(target_os = "windows") {
             let lossy_str = canonical_path.to_string_lossy();
-            let path = lossy_str.replace("/", "\\");
-            if path.starts_with("\\\\?\\") {
-                let mut path = path.trim_start_matches("\\\\?\\");
-                if path.starts_with("UNC\\") {
-                    path = path.trim_start_matches("UNC\\");
-                }
-                return PathBuf::from(path);
-            }
-            return PathBuf::from(path);
+            return PathBuf::from(normalize_windows_path(&lossy_str));
         }
         canonical_path
     }
@@ -121,7 +797,10 @@ impl DirectoryWatcher<FileReader, BufWriter<Stdout>> {
         false
     }
 
-    fn handle_pending_delete(&mut self, pending_delete_files: &mut VecDeque<PathBuf>) {
+    fn handle_pending_delete(
+        &mut self,
+        pending_delete_files: &mut VecDeque<PathBuf>,
+    ) -> io::Result<()> {
         // On Windows, try to detect pending delete files
         if cfg!(target_os = "windows") {
             for path in self.file_map.keys() {
@@ -136,117 +815,476 @@ impl DirectoryWatcher<FileReader, BufWriter<Stdout>> {
                     repo.pop(path);
                 }
                 if let Some(reader) = self.file_map.remove(path) {
-                    self.unsubscribe_select_file(path, &reader);
+                    self.unsubscribe_select_file(path, &reader)?;
                 }
             }
             pending_delete_files.clear();
         }
+        Ok(())
     }
 
-    fn print_file_path(&self, path: &Path) {
-        let mut preceding = "\n";
+    // If the selected file stopped in the middle of a line, put \n for consistent result.
+    // The incomplete line is printed again when the file is selected next time.
+    fn interrupt_selected_file(&mut self) -> io::Result<()> {
+        if !self.eol_fix {
+            return Ok(());
+        }
         if let Some(selected_file_path) = &self.selected_file_path {
-            if let Some(selected_file) = self.file_map.get(selected_file_path) {
+            if let Some(selected_file) = self.file_map.get_mut(selected_file_path) {
                 if !selected_file.printed_eol() {
-                    println!();
+                    selected_file.interrupt_line();
+                    writeln!(self.writer)?;
                 }
             }
-        } else {
-            preceding = "";
+        }
+        Ok(())
+    }
+
+    fn print_file_path(&mut self, path: &Path) -> io::Result<()> {
+        if self.count || self.raw {
+            return Ok(());
+        }
+        let shown_path = self.shown_path(path);
+        self.set_event_source(None);
+        if self.selected_file_path.is_some() {
+            self.interrupt_selected_file()?;
+            self.print_separator()?;
+        }
+        self.print_normalized_path(&shown_path)
+    }
+
+    // The path as shown in the header, absolute or relative as configured
+    fn shown_path(&self, path: &Path) -> PathBuf {
+        // The events are for the canonical path, while the link is shown as on startup
+        let path = match self.link_names.get(path) {
+            Some(link_name) if !self.dereference && !self.absolute_paths => link_name,
+            _ => path,
+        };
+        if self.absolute_paths {
+            return Self::canonicalize_path(path).unwrap_or_else(|_| path.to_owned());
+        }
+        if self.relative_to {
+            return self.relative_to_base(path);
         }
         if let Some(current_dir) = &self.current_dir {
             if let Some(relative_path) = diff_paths(&path, &current_dir) {
-                print!("{}", preceding);
-                self.print_normalized_path(&relative_path);
-                return;
+                return relative_path;
+            }
+        }
+        path.to_owned()
+    }
+
+    // Printed when the file is no longer followed, so that a script can tell its end
+    fn print_eof_marker(&mut self, path: &Path) -> io::Result<()> {
+        if !self.eof_marker || self.count {
+            return Ok(());
+        }
+        self.set_event_source(None);
+        // The output of the selected file continues under its header again
+        if self.selected_file_path.is_some() {
+            self.interrupt_selected_file()?;
+            self.print_separator()?;
+            self.selected_file_path = None;
+        }
+        let display_path = match &self.current_dir {
+            Some(current_dir) if !self.absolute_paths => {
+                diff_paths(path, current_dir).unwrap_or_else(|| path.to_owned())
             }
+            _ => path.to_owned(),
+        };
+        let display_path = display_path.to_string_lossy();
+        let marker = format!("<== EOF {} ==>", display_path.trim_start_matches("./"));
+        if !self.colorize {
+            return writeln!(self.writer, "{}", marker);
+        }
+        // The same color as the header of the file
+        let style = self.header_style(&self.shown_path(path));
+        if let Some(tee_target) = &self.tee_target {
+            tee_target.set(TeeTarget::Primary);
         }
-        print!("{}", preceding);
-        self.print_normalized_path(path);
+        writeln!(self.writer, "{}", style.paint(marker.as_str()))?;
+        // The mirrored output is kept free from color codes
+        if let Some(tee_target) = &self.tee_target {
+            tee_target.set(TeeTarget::Secondary);
+            writeln!(self.writer, "{}", marker)?;
+            tee_target.set(TeeTarget::Both);
+        }
+        Ok(())
     }
 
-    fn unsubscribe_select_file(&mut self, path: &Path, reader: &CachedTailState) {
+    // Both paths must be canonical to be compared with the directory of --relative-to
+    fn relative_to_base(&self, path: &Path) -> PathBuf {
+        let canonical_path = Self::canonicalize_path(path).unwrap_or_else(|_| path.to_owned());
+        self.current_dir
+            .as_ref()
+            .and_then(|base| diff_paths(&canonical_path, base))
+            .unwrap_or(canonical_path)
+    }
+
+    // Printed between the output of different files
+    fn print_separator(&mut self) -> io::Result<()> {
+        match &self.separator {
+            Some(separator) if separator.is_empty() => Ok(()),
+            Some(separator) => writeln!(self.writer, "{}", separator),
+            None => writeln!(self.writer),
+        }
+    }
+
+    fn print_line_counts(&mut self, noun: &str) -> io::Result<()> {
+        self.set_event_source(None);
+        let mut counts: Vec<(PathBuf, u64)> = self
+            .file_map
+            .iter_mut()
+            .map(|(path, reader)| (path.to_owned(), reader.take_line_count()))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        counts.sort();
+        for (path, count) in counts {
+            let relative_path = match &self.current_dir {
+                Some(current_dir) if !self.absolute_paths => {
+                    diff_paths(&path, current_dir).unwrap_or(path)
+                }
+                _ => path,
+            };
+            let relative_path = relative_path.to_string_lossy();
+            let display_path = relative_path.trim_start_matches("./");
+            writeln!(self.writer, "{}: {}", display_path, plural(count, noun))?;
+        }
+        Ok(())
+    }
+
+    fn print_status(&self) {
+        eprint!("\rwatching {}", plural(self.file_map.len() as u64, "file"));
+        // Frequent reopens tell that the handles are not enough for the active files
+        let reopened = self.repository.borrow().reopened();
+        if reopened > 0 {
+            eprint!(", reopened {}", plural(reopened, "time"));
+        }
+    }
+
+    fn clear_status(&self) {
+        eprint!("\r\x1b[K");
+    }
+
+    // Tell that regtail is alive while the followed files are idle
+    fn print_heartbeat(&self, heartbeat: Duration) {
+        let message = format!("(no new data for {}s)", heartbeat.as_secs());
+        if self.colorize {
+            eprintln!("{}", Style::new().dimmed().paint(message));
+        } else {
+            eprintln!("{}", message);
+        }
+    }
+
+    fn unsubscribe_select_file(
+        &mut self,
+        path: &Path,
+        reader: &CachedTailState<W>,
+    ) -> io::Result<()> {
         if let Some(selected_file_path) = &self.selected_file_path {
             if selected_file_path == path {
-                if !reader.printed_eol() {
-                    println!();
+                self.set_event_source(None);
+                if !self.raw {
+                    if self.eol_fix && !reader.printed_eol() {
+                        writeln!(self.writer)?;
+                    }
+                    writeln!(self.writer)?;
                 }
-                println!();
                 self.selected_file_path = None
             }
         }
+        Ok(())
     }
 
-    fn change_selected_file(&mut self, path: &Path) {
+    fn change_selected_file(&mut self, path: &Path) -> io::Result<()> {
         // Handle current path change
         if let Some(last_path) = &self.selected_file_path {
-            if last_path != path {
-                self.print_file_path(&path);
-                self.selected_file_path = Some(path.to_owned());
+            if last_path == path {
+                return Ok(());
             }
-        } else {
-            // Should print file path because of first output of the program
-            self.print_file_path(&path);
-            self.selected_file_path = Some(path.to_owned());
         }
+        // Should print file path if changed or because of first output of the program
+        self.print_file_path(&path)?;
+        self.selected_file_path = Some(path.to_owned());
+        self.set_event_source(Some(path));
+        if let Some(reader) = self.file_map.get_mut(path) {
+            reader.resume_line()?;
+        }
+        Ok(())
+    }
+
+    fn is_directory(path: &Path) -> bool {
+        fs::metadata(path)
+            .map(|metadata| metadata.file_type().is_dir())
+            .unwrap_or(false)
+    }
+
+    fn notify_replaced_by_directory(path: &Path) {
+        eprintln!(
+            "{} has been replaced by a directory; no longer following",
+            path.display()
+        );
     }
 
     fn handle_write(&mut self, path: PathBuf) -> std::io::Result<()> {
-        // Just ignore if the path is not match regex
-        if !self.filter.match_path(&path) {
+        // The files are followed by the canonical paths, not by the links they are reached through
+        let path = if self.follow_symlinks {
+            Self::canonicalize_path(&path).unwrap_or(path)
+        } else {
+            path
+        };
+
+        // Just ignore if the path is not match regex, unless the file was followed before renamed
+        let followed_renamed = self.follow_renamed && self.file_map.contains_key(&path);
+        if !followed_renamed && (!self.filter.match_path(&path) || self.beyond_depth(&path)) {
             return Ok(());
         }
 
-        self.change_selected_file(&path);
+        // The rest of the capped read is read once per loop, however many events are queued
+        if matches!(self.file_map.get(&path), Some(reader) if reader.has_pending_read()) {
+            return Ok(());
+        }
+
+        // A directory cannot be tailed. If it replaced a followed file, stop following it.
+        if Self::is_directory(&path) {
+            return self.handle_remove(&path);
+        }
+
+        // The file may be replaced, then follow the new file from the beginning
+        self.handle_replaced(&path)?;
+
+        // The tag is shown in the header, so it must be known before the file is selected
+        if self.tag && !self.tags.contains_key(&path) && Path::exists(&path) {
+            self.add_tags(vec![path.clone()]);
+        }
+
+        // Shrink handling, before the incomplete line is printed again by the file change
+        if let Some(reader) = self.file_map.get_mut(&path) {
+            reader.handle_shrink()?;
+        }
+
+        self.change_selected_file(&path)?;
 
         match self.file_map.get_mut(&path) {
             Some(reader) => {
-                // Shrink handling
-                let offset = reader.current_seek();
-                reader.handle_shrink(offset)?;
-                reader.dump_to_tail()?;
+                reader.dump_within(self.max_read_per_event)?;
             }
             None => {
                 // Check file existence
                 if !Path::exists(&path) {
                     return Ok(());
                 }
+                if self.reaches_max_files() {
+                    return Ok(());
+                }
 
                 // Supplied path is not opened currently
-                let mut reader =
-                    CachedTailState::from_path(path.clone(), Rc::clone(&self.repository))?;
-                reader.dump_to_tail()?;
-                self.file_map.insert(path, reader);
+                let mut reader = match CachedTailState::from_path(
+                    path.clone(),
+                    Rc::clone(&self.repository),
+                    self.writer.clone(),
+                    &self.line_options,
+                ) {
+                    Ok(reader) => reader,
+                    // A compressed file being written is opened again on the next write
+                    Err(error) if is_incomplete_compression(&error) => {
+                        Self::notify_incomplete_compression(&path);
+                        return Ok(());
+                    }
+                    Err(error) => return Err(error),
+                };
+                self.prefix_reader(&path, &mut reader);
+                // A released file goes on from where it was left
+                if let Some(position) = self.released.remove(&path) {
+                    if position.inode == reader.inode() && position.offset <= reader.len()? {
+                        reader.seek(SeekFrom::Start(position.offset))?;
+                    }
+                }
+                reader.dump_within(self.max_read_per_event)?;
+                self.file_map.insert(path.clone(), reader);
+            }
+        }
+        if self.idle_timeout.is_some() {
+            self.last_activity.insert(path, Instant::now());
+        }
+        Ok(())
+    }
+
+    // Stop following the files with no writes for the idle timeout and close their handles
+    fn release_idle_files(&mut self) -> io::Result<()> {
+        let idle_timeout = match self.idle_timeout {
+            Some(idle_timeout) => idle_timeout,
+            None => return Ok(()),
+        };
+        let file_map = &self.file_map;
+        self.last_activity
+            .retain(|path, _| file_map.contains_key(path));
+        let now = Instant::now();
+        let mut idle_paths = Vec::new();
+        for (path, reader) in &self.file_map {
+            // A file without a write since it was followed is idle from now
+            let last_activity = *self.last_activity.entry(path.clone()).or_insert(now);
+            // The held incomplete line would be lost with the reader
+            if reader.is_stream() || reader.has_pending_read() || reader.has_partial_line() {
+                continue;
+            }
+            if now.duration_since(last_activity) >= idle_timeout {
+                idle_paths.push(path.clone());
+            }
+        }
+        for path in idle_paths {
+            let reader = match self.file_map.remove(&path) {
+                Some(reader) => reader,
+                None => continue,
+            };
+            (*self.repository).borrow_mut().pop(&path);
+            self.unsubscribe_select_file(&path, &reader)?;
+            self.last_activity.remove(&path);
+            let position = FilePosition {
+                offset: reader.current_seek(),
+                inode: reader.inode(),
+            };
+            self.released.insert(path.clone(), position);
+            if self.verbose {
+                eprintln!("released idle file: {}", path.display());
             }
         }
         Ok(())
     }
 
+    // Deeper than the depth below the watched directory, which the recursive watch still reports,
+    // e.g. for a directory created after startup
+    fn beyond_depth(&self, path: &Path) -> bool {
+        let depth = match self.depth {
+            Some(depth) => depth,
+            None => return false,
+        };
+        let relative = self
+            .recursive_roots
+            .iter()
+            .find_map(|root| path.strip_prefix(root).ok());
+        match relative {
+            Some(relative) => relative.components().count() > depth,
+            None => false,
+        }
+    }
+
+    // Files may be created in a new subdirectory before it is watched, so seed them by walking
+    fn handle_new_directory(&mut self, path: &Path) -> io::Result<()> {
+        let relative = self
+            .recursive_roots
+            .iter()
+            .find_map(|root| path.strip_prefix(root).ok());
+        let dir_depth = match relative {
+            Some(relative) => relative.components().count(),
+            None => return Ok(()),
+        };
+        let depth = match self.depth {
+            Some(depth) if depth <= dir_depth => return Ok(()),
+            Some(depth) => Some(depth - dir_depth),
+            None => None,
+        };
+        let paths: Vec<PathBuf> = self.filter.files_under(path, depth).collect();
+        for path in paths {
+            let canonical_path = match Self::canonicalize_path(&path) {
+                Ok(canonical_path) => canonical_path,
+                // Removed during the walk, such as a short-lived temporary file
+                Err(_) => continue,
+            };
+            if !self.file_map.contains_key(&canonical_path) {
+                self.handle_write(canonical_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Make the followed files directly in the directory agree with the files there,
+    // used when the events are too ambiguous to be handled one by one.
+    // A file moved within the directory is followed from the same offset.
+    fn reconcile_directory(&mut self, dir: &Path) -> io::Result<()> {
+        let followed: Vec<PathBuf> = self
+            .file_map
+            .keys()
+            .filter(|path| path.parent() == Some(dir))
+            .cloned()
+            .collect();
+        let mut vanished = Vec::new();
+        for path in followed {
+            let replaced = match self.file_map.get(&path) {
+                Some(reader) => !Path::exists(&path) || reader.is_replaced(&path),
+                None => continue,
+            };
+            if !replaced {
+                self.handle_write(path)?;
+            } else if let Some(reader) = self.file_map.remove(&path) {
+                self.unsubscribe_select_file(&path, &reader)?;
+                vanished.push((path, reader));
+            }
+        }
+
+        let paths: Vec<PathBuf> = if Self::is_directory(dir) {
+            self.filter.files_under(dir, Some(1)).collect()
+        } else {
+            Vec::new()
+        };
+        for path in paths {
+            let canonical_path = match Self::canonicalize_path(&path) {
+                Ok(canonical_path) => canonical_path,
+                // Removed during the walk
+                Err(_) => continue,
+            };
+            if self.file_map.contains_key(&canonical_path) {
+                continue;
+            }
+            let inode = file_id(&canonical_path);
+            let moved = vanished
+                .iter()
+                .position(|(_, reader)| inode.is_some() && reader.inode() == inode);
+            if let Some(index) = moved {
+                let (_, reader) = vanished.swap_remove(index);
+                self.file_map.insert(canonical_path.to_owned(), reader);
+            }
+            self.handle_write(canonical_path)?;
+        }
+
+        let mut repo = (*self.repository).borrow_mut();
+        for (path, _) in vanished {
+            repo.pop(&path);
+        }
+        Ok(())
+    }
+
     #[allow(clippy::single_match)]
-    fn handle_rename(&mut self, path: PathBuf, cookie: Option<u32>) {
+    fn handle_rename(&mut self, path: PathBuf, cookie: Option<u32>) -> io::Result<()> {
         if let Some(cookie) = cookie {
             match self.renaming_map.remove(&cookie) {
                 Some(file) => match file {
-                    Some(file) => {
-                        // Just ignore if the new path is not match regex
-                        if !self.filter.match_path(&path) {
-                            return;
+                    Some((old_path, file)) => {
+                        // Just ignore if the new path is not match regex, unless it is still followed
+                        if !self.follow_renamed
+                            && (!self.filter.match_path(&path) || self.beyond_depth(&path))
+                        {
+                            return self.print_eof_marker(&old_path);
                         }
 
-                        // New path supplied
+                        // New path supplied, which may replace the followed file
+                        self.handle_remove(&path)?;
                         self.file_map.insert(path, file);
                     }
                     None => {
-                        // This is maybe duplication request
-                        // Just ignore
+                        // An unfollowed file is renamed. If it replaces the followed file,
+                        // e.g. saving by an editor, follow the new file from the beginning.
+                        if self.filter.match_path(&path) && self.file_map.contains_key(&path) {
+                            self.handle_write(path)?;
+                        }
                     }
                 },
                 None => {
                     // Old path supplied
                     match self.file_map.remove(&path) {
                         Some(file) => {
-                            self.unsubscribe_select_file(&path, &file);
-                            self.renaming_map.insert(cookie, Some(file));
+                            self.unsubscribe_select_file(&path, &file)?;
+                            self.renaming_map.insert(cookie, Some((path, file)));
                         }
                         None => {
                             self.renaming_map.insert(cookie, None);
@@ -255,107 +1293,778 @@ impl DirectoryWatcher<FileReader, BufWriter<Stdout>> {
                 }
             }
         }
+        Ok(())
+    }
+
+    fn handle_streams(&mut self) -> io::Result<()> {
+        let paths: Vec<PathBuf> = self
+            .file_map
+            .iter()
+            .filter(|(_, reader)| reader.has_stream_content())
+            .map(|(path, _)| path.to_owned())
+            .collect();
+        for path in paths {
+            self.handle_write(path)?;
+        }
+        Ok(())
+    }
+
+    // Continue the reads stopped by --max-read-per-event
+    fn handle_pending_reads(&mut self) -> io::Result<()> {
+        let paths: Vec<PathBuf> = self
+            .file_map
+            .iter()
+            .filter(|(_, reader)| reader.has_pending_read())
+            .map(|(path, _)| path.to_owned())
+            .collect();
+        for path in paths {
+            if let Some(reader) = self.file_map.get_mut(&path) {
+                reader.resume_pending_read();
+            }
+            self.handle_write(path)?;
+        }
+        Ok(())
+    }
+
+    fn handle_replaced(&mut self, path: &PathBuf) -> io::Result<()> {
+        let replaced = matches!(self.file_map.get(path), Some(reader) if reader.is_replaced(path));
+        if replaced {
+            // Drop the stale handle in the repository as well
+            self.handle_remove(path)?;
+        }
+        Ok(())
     }
 
     // Allow &PathBuf because of the lack of implicit type conversion
     #[allow(clippy::ptr_arg)]
-    fn handle_remove(&mut self, path: &PathBuf) {
+    fn handle_remove(&mut self, path: &PathBuf) -> io::Result<()> {
+        self.released.remove(path);
+        if self.tags.remove(path).is_some() {
+            self.retag();
+        }
+        // The file ends here, so the count of its repeated last line is not held any longer
+        self.end_of_file(path)?;
         if let Some(reader) = self.file_map.remove(path) {
             {
                 let mut repo = (*self.repository).borrow_mut();
                 repo.pop(path);
             }
-            self.unsubscribe_select_file(path, &reader);
+            self.unsubscribe_select_file(path, &reader)?;
+            self.print_eof_marker(path)?;
+
+            // The file may be replaced by a directory with the same name
+            if Self::is_directory(path) {
+                Self::notify_replaced_by_directory(path);
+            }
         }
+        Ok(())
     }
 
-    pub fn follow_dir(&mut self, opt: &Opt) -> Result<(), NotifyError> {
-        // Empty tailing consideration
-        if opt.lines == 0 {
-            for path in self.filter.filtered_files(&opt) {
-                let canonical_path = Self::canonicalize_path(&path)?;
-                let reader = tail2(
-                    PathBuf::from(&canonical_path),
-                    Rc::clone(&self.repository),
-                    0,
-                )?;
-                self.file_map.insert(canonical_path.to_owned(), reader);
+    // Open a matched file on startup, failing before its header is printed
+    fn open_initial_file(&self, path: &Path) -> io::Result<(PathBuf, CachedTailState<W>)> {
+        let canonical_path = Self::canonicalize_path(path)?;
+        let reader = CachedTailState::from_path(
+            canonical_path.to_owned(),
+            Rc::clone(&self.repository),
+            self.writer.clone(),
+            &self.line_options,
+        )?;
+        // The file is opened lazily, so open it here to fail before its header is printed
+        reader.len()?;
+        Ok((canonical_path, reader))
+    }
+
+    // The rotated files are opened as one file, followed by the path of the last one
+    fn open_rotated_files(&self, paths: &[PathBuf]) -> io::Result<(PathBuf, CachedTailState<W>)> {
+        let canonical_paths = paths
+            .iter()
+            .map(|path| Self::canonicalize_path(path))
+            .collect::<io::Result<Vec<PathBuf>>>()?;
+        let canonical_path = canonical_paths.last().cloned().unwrap_or_default();
+        let reader = CachedTailState::from_rotated_paths(
+            canonical_paths,
+            Rc::clone(&self.repository),
+            self.writer.clone(),
+            &self.line_options,
+        )?;
+        reader.len()?;
+        Ok((canonical_path, reader))
+    }
+
+    // Whether any file failed to be opened on startup
+    pub fn has_failed_files(&self) -> bool {
+        self.failed_files > 0
+    }
+
+    // Print the tail of every matched file and start tracking them
+    pub fn tail_initial_files(&mut self, config: &Config) -> io::Result<()> {
+        // Hold all initial output in the buffer and flush it at once
+        if config.batch_initial {
+            self.writer.defer_flush(true);
+        }
+
+        let mut paths = Self::unique_files(self.filter.filtered_files(config), config.verbose);
+        self.add_link_names(&paths);
+        if let Some(max_files) = self.max_files {
+            if paths.len() > max_files {
+                eprintln!(
+                    "following only {}, {} skipped by --max-files",
+                    plural(max_files as u64, "file"),
+                    plural((paths.len() - max_files) as u64, "file")
+                );
+                paths.truncate(max_files);
             }
+        }
+        if let Some(dir) = &config.snapshot {
+            self.write_snapshots(dir, &paths, config);
+        }
+        if self.tag {
+            let canonical_paths = paths
+                .iter()
+                .filter_map(|path| Self::canonicalize_path(path).ok())
+                .collect();
+            self.add_tags(canonical_paths);
+        }
+        if config.no_initial {
+            self.skip_initial_files(paths);
+            self.writer.defer_flush(false);
+            return Ok(());
+        }
+        if let Some(lines) = config.combined_lines() {
+            self.tail_combined_files(paths, lines)?;
+            self.line_options.write_merged(&mut self.writer, true)?;
+            self.writer.defer_flush(false);
+            return self.writer.flush();
+        }
+        let groups = if config.container_logs {
+            group_container_logs(paths)
+        } else if config.rotation {
+            group_rotated(paths)
         } else {
-            let mut prev_reader: Option<&CachedTailState> = None;
-            for path in self.filter.filtered_files(&opt) {
-                if self.selected_file_path.is_some() {
-                    // If there is a previous file and its last byte is not \n,
-                    // put \n for consistent result.
-                    if let Some(reader) = prev_reader {
-                        if !reader.printed_eol() {
-                            println!();
-                        }
+            paths.into_iter().map(|path| vec![path]).collect()
+        };
+        for group in groups {
+            let path = match group.last() {
+                Some(path) => path.to_owned(),
+                None => continue,
+            };
+            let (canonical_path, mut reader) = match self.open_group_or_report(group) {
+                Some(opened) => opened,
+                None => continue,
+            };
+
+            let initial_lines = self.initial_lines(config, &path);
+            // Empty tailing consideration
+            if initial_lines != Some(0) {
+                self.print_initial_header(&path, &canonical_path)?;
+            }
+            match self.resume_offset(&canonical_path, &reader) {
+                // Only the content appended since the last run is shown
+                Some(offset) => {
+                    reader.seek(SeekFrom::Start(offset))?;
+                    reader.dump_to_tail()?;
+                }
+                None => {
+                    let _offset = tail_initial(&mut reader, initial_lines);
+                }
+            }
+            self.file_map.insert(canonical_path, reader);
+        }
+
+        self.line_options.write_merged(&mut self.writer, true)?;
+        self.writer.defer_flush(false);
+        self.writer.flush()
+    }
+
+    // Lines to show on startup, --lines-config overrides the lines but neither --all nor --count
+    fn initial_lines(&self, config: &Config, path: &Path) -> Option<u64> {
+        match config.initial_lines() {
+            Some(lines) if !config.count => Some(self.line_counts.lines_for(path).unwrap_or(lines)),
+            initial_lines => initial_lines,
+        }
+    }
+
+    // Drop all cached handles and walk the directories again to pick up rotated or new files
+    fn reload(&mut self, config: &Config) -> io::Result<()> {
+        (*self.repository).borrow_mut().clear();
+        self.filter.reload_ignores();
+        let followed: Vec<PathBuf> = self.file_map.keys().cloned().collect();
+        for path in followed {
+            if Path::exists(&path) {
+                self.handle_write(path)?;
+            } else {
+                self.handle_remove(&path)?;
+            }
+        }
+        let paths: Vec<PathBuf> = self.filter.filtered_files(&config).collect();
+        for path in paths {
+            // The file may be removed during the walk
+            if let Ok(canonical_path) = Self::canonicalize_path(&path) {
+                if !self.file_map.contains_key(&canonical_path) {
+                    self.handle_write(canonical_path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The latest modified time of the watched directories and the followed files
+    fn last_modified(&self, config: &Config) -> Option<SystemTime> {
+        config
+            .watch_dirs()
+            .iter()
+            .chain(self.file_map.keys())
+            .filter_map(|path| {
+                fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+            })
+            .max()
+    }
+
+    // Remember the links among the paths, so that the later headers show them as well
+    fn add_link_names(&mut self, paths: &[PathBuf]) {
+        for path in paths {
+            let is_symlink = fs::symlink_metadata(path)
+                .map(|metadata| metadata.file_type().is_symlink())
+                .unwrap_or(false);
+            if !is_symlink {
+                continue;
+            }
+            if let Ok(canonical_path) = Self::canonicalize_path(path) {
+                self.link_names.insert(canonical_path, path.to_owned());
+            }
+        }
+    }
+
+    // Keep the first path of the paths resolving to the same file, e.g. a symlink and its target,
+    // otherwise the content is printed twice
+    fn unique_files(paths: impl Iterator<Item = PathBuf>, verbose: bool) -> Vec<PathBuf> {
+        let mut canonical_paths = HashSet::new();
+        paths
+            .filter(|path| match Self::canonicalize_path(path) {
+                Ok(canonical_path) if !canonical_paths.insert(canonical_path.to_owned()) => {
+                    if verbose {
+                        eprintln!(
+                            "skipping duplicate path: {} -> {}",
+                            path.display(),
+                            canonical_path.display()
+                        );
                     }
+                    false
+                }
+                // A path failed to be resolved is reported when it is opened
+                _ => true,
+            })
+            .collect()
+    }
+
+    // A file which cannot be opened is reported and the others are still followed
+    fn open_initial_file_or_report(
+        &mut self,
+        path: &Path,
+    ) -> Option<(PathBuf, CachedTailState<W>)> {
+        match self.open_initial_file(path) {
+            Ok((canonical_path, mut reader)) => {
+                self.prefix_reader(&canonical_path, &mut reader);
+                Some((canonical_path, reader))
+            }
+            Err(error) if is_incomplete_compression(&error) => {
+                Self::notify_incomplete_compression(path);
+                None
+            }
+            Err(error) => {
+                eprintln!("cannot open {}: {}", path.display(), error);
+                self.failed_files += 1;
+                None
+            }
+        }
+    }
+
+    fn notify_incomplete_compression(path: &Path) {
+        eprintln!(
+            "skip {}: the compressed content is incomplete, retry on the next write",
+            path.display()
+        );
+    }
+
+    // Each file is written to the directory as its path below the watched directory,
+    // where the separators are replaced so that the files are never nested
+    fn write_snapshots(&self, dir: &Path, paths: &[PathBuf], config: &Config) {
+        if let Err(error) = fs::create_dir_all(dir) {
+            eprintln!("cannot create {}: {}", dir.display(), error);
+            return;
+        }
+        let tail_count = if config.all { None } else { Some(config.lines) };
+        // The lines are processed as printed, without the decorations or the shared state
+        let mut line_options = (*self.line_options).clone();
+        line_options.highlights.clear();
+        line_options.wrap = None;
+        line_options.count = false;
+        line_options.rate_limiter = None;
+        line_options.alert = None;
+        line_options.merger = None;
+        line_options.tee_target = None;
+        let line_options = Rc::new(line_options);
+        for path in paths {
+            let relative_path = self.filter.relative_path(path).unwrap_or(path);
+            let target = dir.join(format!("{}.tail", snapshot_name(relative_path)));
+            if let Err(error) = write_snapshot(path, &target, tail_count, &line_options) {
+                eprintln!("cannot write the snapshot of {}: {}", path.display(), error);
+            }
+        }
+    }
+
+    fn add_tags(&mut self, paths: Vec<PathBuf>) {
+        for path in paths {
+            self.tags.insert(path, String::new());
+        }
+        self.retag();
+    }
+
+    // A new file may share the suffix with the followed files, and a removed one may have made
+    // the others longer, so every tag is computed again
+    fn retag(&mut self) {
+        self.tags = unique_suffixes(self.tags.keys());
+        let prefixes: Vec<(PathBuf, Vec<u8>)> = self
+            .file_map
+            .keys()
+            .map(|path| (path.clone(), self.line_prefix(path)))
+            .collect();
+        for (path, prefix) in prefixes {
+            if let Some(reader) = self.file_map.get_mut(&path) {
+                reader.set_line_prefix(prefix);
+            }
+        }
+    }
+
+    // Written before each line of the file, the tag with --tag and then the path with --prefix.
+    // It is painted in the color of the header with --multicolor.
+    fn line_prefix(&self, path: &Path) -> Vec<u8> {
+        let mut prefix = String::new();
+        if let Some(tag) = self.tags.get(path) {
+            prefix.push_str(&format!("[{}] ", tag));
+        }
+        let shown_path = self.shown_path(path);
+        if self.prefix {
+            let shown_path = shown_path.to_string_lossy();
+            prefix.push_str(&format!("{}: ", shown_path.trim_start_matches("./")));
+        }
+        if self.colorize && self.multicolor && !prefix.is_empty() {
+            let painted = self.header_style(&shown_path).paint(prefix.trim_end());
+            return format!("{} ", painted).into_bytes();
+        }
+        prefix.into_bytes()
+    }
+
+    fn prefix_reader(&self, path: &Path, reader: &mut CachedTailState<W>) {
+        if self.tag || self.prefix {
+            reader.set_line_prefix(self.line_prefix(path));
+        }
+    }
 
-                    println!();
+    // Follow the files from their end, a pipe streams whatever arrives to it
+    fn skip_initial_files(&mut self, paths: Vec<PathBuf>) {
+        for path in paths {
+            if let Some((canonical_path, mut reader)) = self.open_initial_file_or_report(&path) {
+                if !reader.is_stream() {
+                    if let Err(error) = reader.skip_to_end() {
+                        eprintln!("cannot read {}: {}", path.display(), error);
+                        self.failed_files += 1;
+                        continue;
+                    }
                 }
-                self.print_normalized_path(&path);
-                let canonical_path = Self::canonicalize_path(&path)?;
-                let reader = tail2(
-                    PathBuf::from(&canonical_path),
-                    Rc::clone(&self.repository),
-                    opt.lines,
-                )?;
+                self.file_map.insert(canonical_path, reader);
+            }
+        }
+    }
 
-                self.file_map.insert(canonical_path.to_owned(), reader);
-                prev_reader = Some(&self.file_map[&canonical_path]);
-                self.selected_file_path = Some(canonical_path);
+    fn open_group_or_report(
+        &mut self,
+        group: Vec<PathBuf>,
+    ) -> Option<(PathBuf, CachedTailState<W>)> {
+        if group.len() <= 1 {
+            return self.open_initial_file_or_report(group.first()?);
+        }
+        match self.open_rotated_files(&group) {
+            Ok((canonical_path, mut reader)) => {
+                self.prefix_reader(&canonical_path, &mut reader);
+                Some((canonical_path, reader))
+            }
+            Err(error) => {
+                eprintln!("cannot open {}: {}", group.last()?.display(), error);
+                self.failed_files += 1;
+                None
             }
         }
+    }
 
-        let (tx, rx) = channel();
-        let mut watcher = raw_watcher(tx)?;
-        let watch_path = opt.watch_path();
-        let recursive_mode = opt.recursive_mode();
-        watcher.watch(watch_path.as_os_str(), recursive_mode)?;
+    fn print_initial_header(&mut self, path: &Path, canonical_path: &Path) -> io::Result<()> {
+        self.set_event_source(None);
+        if !self.raw && !self.count {
+            if self.selected_file_path.is_some() {
+                self.interrupt_selected_file()?;
+                self.print_separator()?;
+            }
+            if self.absolute_paths {
+                self.print_normalized_path(canonical_path)?;
+            } else if self.relative_to {
+                let relative_path = self.relative_to_base(canonical_path);
+                self.print_normalized_path(&relative_path)?;
+            } else if self.dereference && path != canonical_path {
+                // The target is shown relative to the current directory as the followed files are
+                let target = self
+                    .current_dir
+                    .as_ref()
+                    .and_then(|current_dir| diff_paths(canonical_path, current_dir))
+                    .unwrap_or_else(|| canonical_path.to_owned());
+                self.print_normalized_path(&target)?;
+            } else {
+                self.print_normalized_path(path)?;
+            }
+        }
+        self.selected_file_path = Some(canonical_path.to_owned());
+        self.set_event_source(Some(canonical_path));
+        Ok(())
+    }
 
-        let mut pending_delete_files = VecDeque::new();
-        loop {
-            match rx.recv_timeout(std::time::Duration::from_secs(1)) {
-                Ok(RawEvent {
-                    path: Some(mut path),
-                    op: Ok(op),
-                    cookie,
-                }) => {
-                    path = Self::normalize_path_for_windows(path);
-
-                    // On MacOS, some simultaneous operation cannot handle correctly.
-                    // This is why the curious handling is required.
-                    if cfg!(target_os = "macos") {
-                        // FSEvents cannot handle renaming and other operations simultaneously.
-                        if op.contains(Op::RENAME) && cookie.is_some() {
-                            // Try to handle renaming correctly at the sacrifice of other operations.
-                            self.handle_rename(path.to_owned(), cookie);
-                        } else {
-                            // Renaming and removing may not happen same time.
-                            // Therefore in the case of Op = REMOVE | RENAME,
-                            // just ignore remove operation to consider REMOVE is stale.
-                            if op.contains(Op::REMOVE) && !op.contains(Op::RENAME) {
-                                self.handle_remove(&path)
-                            }
-                            if op.contains(Op::WRITE) {
-                                self.handle_write(path)?
-                            }
+    // Show the last lines of all the files as a whole, ordered by file then position.
+    // Each file is followed from its end, and only the files sharing the lines get headers.
+    fn tail_combined_files(&mut self, paths: Vec<PathBuf>, lines: u64) -> io::Result<()> {
+        let mut opened = Vec::new();
+        for path in paths {
+            if let Some((canonical_path, reader)) = self.open_initial_file_or_report(&path) {
+                opened.push((path, canonical_path, reader));
+            }
+        }
+
+        // The later files take the lines first
+        let mut remaining = lines;
+        let mut shares = Vec::with_capacity(opened.len());
+        for (_, _, reader) in opened.iter_mut().rev() {
+            let (offset, count) = if reader.is_stream() {
+                (0, 0)
+            } else {
+                tail_within(reader, remaining)?
+            };
+            remaining -= count.min(remaining);
+            shares.push((offset, count));
+        }
+        shares.reverse();
+
+        for ((path, canonical_path, mut reader), (offset, count)) in opened.into_iter().zip(shares)
+        {
+            if count > 0 || reader.is_stream() {
+                self.print_initial_header(&path, &canonical_path)?;
+            }
+            if reader.is_stream() {
+                let _offset = reader.dump_to_tail();
+            } else {
+                let _offset = dump_from_offset(&mut reader, offset);
+            }
+            self.file_map.insert(canonical_path, reader);
+        }
+        Ok(())
+    }
+
+    // Offset saved by the last run, None if the file was replaced or shrank
+    fn resume_offset(&self, path: &Path, reader: &CachedTailState<W>) -> Option<u64> {
+        if reader.is_stream() {
+            return None;
+        }
+        let len = reader.len().ok()?;
+        self.positions.resume_offset(path, reader.inode(), len)
+    }
+
+    // Save the offsets to the state file if changed, a failure is reported and tailing continues
+    fn save_positions(&mut self) {
+        let state_file = match &self.state_file {
+            Some(state_file) => state_file,
+            None => return,
+        };
+        // The released files are resumed from their offsets as well
+        let mut positions = self.released.clone();
+        for (path, reader) in self
+            .file_map
+            .iter()
+            .filter(|(_, reader)| !reader.is_stream())
+        {
+            let position = FilePosition {
+                offset: reader.current_seek(),
+                inode: reader.inode(),
+            };
+            positions.insert(path.to_owned(), position);
+        }
+        if positions == self.positions {
+            return;
+        }
+        match positions.save(state_file) {
+            Ok(()) => self.positions = positions,
+            Err(error) => eprintln!("cannot save state file {}: {}", state_file.display(), error),
+        }
+    }
+
+    fn reaches_max_files(&self) -> bool {
+        match self.max_files {
+            Some(max_files) => self.file_map.len() >= max_files,
+            None => false,
+        }
+    }
+
+    // No more content follows, so the output held by the line processors is written under the
+    // header of each file, followed by the merged lines and the line counts
+    fn end_of_output(&mut self, config: &Config) -> io::Result<()> {
+        let mut paths: Vec<PathBuf> = self
+            .file_map
+            .iter()
+            .filter(|(_, reader)| reader.has_held_output())
+            .map(|(path, _)| path.clone())
+            .collect();
+        // The selected file goes first, which needs no header again
+        paths.sort_by_key(|path| Some(path) != self.selected_file_path.as_ref());
+        for path in paths {
+            self.end_of_file(&path)?;
+        }
+        self.line_options.write_merged(&mut self.writer, true)?;
+        if self.count {
+            // The entire content is counted with --once
+            self.print_line_counts(if config.once { "line" } else { "new line" })?;
+        }
+        self.writer.flush()
+    }
+
+    // Write the output held for the file, the header is printed first if another file is shown
+    fn end_of_file(&mut self, path: &Path) -> io::Result<()> {
+        match self.file_map.get(path) {
+            Some(reader) if reader.has_held_output() => {}
+            _ => return Ok(()),
+        }
+        self.change_selected_file(path)?;
+        match self.file_map.get_mut(path) {
+            Some(reader) => reader.end_of_input(),
+            None => Ok(()),
+        }
+    }
+
+    // Whether the output reached the budget, the initial output also counts
+    fn exceeds_max_output(&self, config: &Config) -> bool {
+        match config.max_output {
+            Some(max_output) => self.writer.written() >= max_output,
+            None => false,
+        }
+    }
+
+    // A raw event, or a rescan forwarded from a signal
+    fn handle_event(
+        &mut self,
+        event: RawEvent,
+        polling: bool,
+        config: &Config,
+    ) -> Result<(), NotifyError> {
+        match event {
+            RawEvent {
+                path: Some(mut path),
+                op: Ok(op),
+                cookie,
+            } => {
+                path = Self::normalize_path_for_windows(path);
+
+                // On MacOS, some simultaneous operation cannot handle correctly.
+                // This is why the curious handling is required.
+                if cfg!(target_os = "macos") {
+                    // FSEvents cannot handle renaming and other operations simultaneously,
+                    // and the both paths of a renaming are not always paired by the cookie.
+                    if op.contains(Op::RENAME) {
+                        // Compare the directory with the followed files instead of guessing
+                        if let Some(dir) = path.parent() {
+                            self.reconcile_directory(dir)?;
                         }
                     } else {
-                        // Except for Mac OS, op can be treated as atomic
-                        if op == Op::WRITE {
-                            self.handle_write(path)?
-                        } else if op == Op::REMOVE {
-                            self.handle_remove(&path)
-                        } else if op == Op::RENAME {
-                            self.handle_rename(path, cookie);
+                        if op.contains(Op::REMOVE) {
+                            self.handle_remove(&path)?
                         }
+                        if op.contains(Op::WRITE) {
+                            self.handle_write(path.to_owned())?
+                        }
+                        if op.contains(Op::CREATE) && Self::is_directory(&path) {
+                            self.handle_new_directory(&path)?
+                        }
+                    }
+                } else {
+                    // Except for Mac OS, op can be treated as atomic
+                    if op == Op::WRITE {
+                        self.handle_write(path)?
+                    } else if op == Op::REMOVE {
+                        self.handle_remove(&path)?
+                    } else if op == Op::RENAME {
+                        self.handle_rename(path, cookie)?;
+                    } else if op == Op::CREATE && Self::is_directory(&path) {
+                        self.handle_new_directory(&path)?;
+                    } else if op == Op::CREATE && polling {
+                        self.handle_write(path)?;
+                    } else if op == Op::CREATE {
+                        self.handle_replaced(&path)?;
+                    }
+                }
+            }
+            // Sent on SIGHUP, or when the OS dropped events
+            RawEvent {
+                path: None,
+                op: Ok(op),
+                ..
+            } if op == Op::RESCAN => {
+                self.reload(config)?;
+            }
+            RawEvent { op: Err(error), .. } => {
+                // Failing to watch a new subdirectory should not stop following the others
+                match watch_error_hint(&error) {
+                    Some(hint) => eprintln!("watch error: {}\n{}", error, hint),
+                    None => return Err(error),
+                }
+            }
+            event => {
+                return Err(NotifyError::Generic(format!("broken event: {:?}", event)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn follow_dir(&mut self, config: &Config) -> Result<(), RegtailError> {
+        // Set to the signal which ended the loop
+        let terminated = Arc::new(AtomicI32::new(0));
+        let result = self.tail_and_follow(config, &terminated);
+        // The output held by --flush-interval is written before the offsets are saved
+        self.writer.defer_flush(false);
+        let _ = self.writer.flush();
+        // Save the last offsets even if the loop ended by an error
+        self.save_positions();
+        // Terminated by the signal as if it was not caught
+        #[cfg(unix)]
+        match terminated.load(Ordering::SeqCst) {
+            0 => {}
+            signal => emulate_default_handler(signal)?,
+        }
+        result.map_err(RegtailError::from)
+    }
+
+    fn tail_and_follow(
+        &mut self,
+        config: &Config,
+        terminated: &Arc<AtomicI32>,
+    ) -> Result<(), NotifyError> {
+        self.tail_initial_files(config)?;
+        // Files may appear later, so keep watching even if the regex is wrong
+        if !config.quiet && self.file_map.is_empty() && self.failed_files == 0 {
+            eprintln!(
+                "warning: no files matched {} under {}",
+                describe_filters(config),
+                config.watch_path().display()
+            );
+        }
+        if config.once || self.exceeds_max_output(config) {
+            self.end_of_output(config)?;
+            return Ok(());
+        }
+
+        // The output is flushed at every tick, so the tick must be short enough.
+        // With the flush interval, the reads only fill the buffer until the interval passes.
+        let interval = config.interval.unwrap_or(TICK_INTERVAL);
+        let (tick_interval, deferred_flush) = match config.flush_interval {
+            Some(flush_interval) if flush_interval.as_millis() == 0 => {
+                self.writer.flush_per_write(true);
+                (interval, None)
+            }
+            Some(flush_interval) => {
+                self.writer.defer_flush(true);
+                (flush_interval.min(interval), Some(flush_interval))
+            }
+            None => (interval, None),
+        };
+        let tick_interval = if config.merge {
+            tick_interval.min(MERGE_WINDOW)
+        } else {
+            tick_interval
+        };
+
+        let (tx, rx) = channel();
+        // Toggled by SIGUSR1
+        let paused = Arc::new(AtomicBool::new(false));
+        // The offsets are saved before the termination only if they are persisted
+        #[cfg(unix)]
+        forward_signals(
+            tx.clone(),
+            paused.clone(),
+            config.reload_on_sighup,
+            self.state_file.as_ref().map(|_| Arc::clone(terminated)),
+        )?;
+        let mut watcher = start_watcher(tx.clone(), config)?;
+        let mut polling = watcher.polling;
+        // The files modified after a quiet interval began indicate the lost events
+        let mut last_event = Instant::now();
+        let mut quiet_since: Option<SystemTime> = None;
+
+        let mut pending_delete_files = VecDeque::new();
+        let mut paused_events = Vec::new();
+        // An initially empty directory should not exit immediately
+        let mut ever_matched = !self.file_map.is_empty();
+        let mut empty_since: Option<Instant> = None;
+        let mut last_count_report = Instant::now();
+        let mut last_save = Instant::now();
+        if config.status {
+            self.print_status();
+        }
+        let heartbeat = if config.quiet { None } else { config.heartbeat };
+        let mut last_heartbeat = Instant::now();
+        let mut last_pending_read = Instant::now();
+        let mut last_flush = Instant::now();
+        loop {
+            let has_stream = self.file_map.values().any(|reader| reader.is_stream());
+            let has_pending = !paused.load(Ordering::SeqCst)
+                && self
+                    .file_map
+                    .values()
+                    .any(|reader| reader.has_pending_read());
+            let timeout = if has_pending {
+                // The rest of the capped reads follows the queued events without waiting
+                Duration::from_millis(0)
+            } else if has_stream {
+                tick_interval.min(STREAM_POLL_INTERVAL)
+            } else {
+                tick_interval
+            };
+            let event = rx.recv_timeout(timeout);
+            if terminated.load(Ordering::SeqCst) != 0 {
+                return Ok(());
+            }
+            if config.watch_events {
+                if let Ok(event) = &event {
+                    log_event(event);
+                }
+            }
+            let queue_empty = event.is_err();
+            if !queue_empty {
+                last_event = Instant::now();
+                quiet_since = None;
+            }
+            let paused = paused.load(Ordering::SeqCst);
+            // The status line is erased while the content is printed, and drawn again after it
+            let redraw_status = config.status && (event.is_ok() || has_stream);
+            if redraw_status {
+                self.clear_status();
+            }
+            match event {
+                // The events are queued while paused and handled in order on resume.
+                // Resuming reloads the files, which covers a rescan and the events beyond the limit.
+                Ok(event) if paused => {
+                    if event.path.is_some() && paused_events.len() < MAX_PAUSED_EVENTS {
+                        paused_events.push(event);
                     }
                 }
                 Ok(event) => {
-                    return Err(NotifyError::Generic(format!("broken event: {:?}", event)));
+                    for queued in paused_events.drain(..) {
+                        self.handle_event(queued, polling, config)?;
+                    }
+                    self.handle_event(event, polling, config)?;
                 }
                 Err(e) => {
                     if e == std::sync::mpsc::RecvTimeoutError::Disconnected {
@@ -363,7 +2072,291 @@ impl DirectoryWatcher<FileReader, BufWriter<Stdout>> {
                     }
                 }
             }
-            self.handle_pending_delete(&mut pending_delete_files);
+            if let Some(self_heal) = config.self_heal {
+                if last_event.elapsed() >= self_heal {
+                    let modified = self.last_modified(config);
+                    // The watch may silently die, e.g. when the watched directory is recreated
+                    if quiet_since.is_some() && modified > quiet_since {
+                        eprintln!(
+                            "no events for {} seconds while the files are modified, watching again",
+                            self_heal.as_secs()
+                        );
+                        drop(watcher);
+                        watcher = start_watcher(tx.clone(), config)?;
+                        polling = watcher.polling;
+                        self.reload(config)?;
+                        quiet_since = None;
+                    } else {
+                        quiet_since = modified;
+                    }
+                    last_event = Instant::now();
+                }
+            }
+            self.handle_pending_delete(&mut pending_delete_files)?;
+            if !paused {
+                self.release_idle_files()?;
+            }
+            if has_stream && !paused {
+                self.handle_streams()?;
+            }
+            // The queued events go first, which are cheap for the files with the pending reads
+            if has_pending
+                && !paused
+                && (queue_empty || last_pending_read.elapsed() >= tick_interval)
+            {
+                self.handle_pending_reads()?;
+                last_pending_read = Instant::now();
+            }
+            self.line_options.write_merged(&mut self.writer, false)?;
+            if self.count && last_count_report.elapsed() >= COUNT_INTERVAL {
+                self.print_line_counts("new line")?;
+                last_count_report = Instant::now();
+            }
+            if let Some(heartbeat) = heartbeat {
+                let idle_since = self.writer.last_write().max(last_heartbeat);
+                if idle_since.elapsed() >= heartbeat {
+                    self.print_heartbeat(heartbeat);
+                    last_heartbeat = Instant::now();
+                }
+            }
+            let save_due = self.state_file.is_some() && last_save.elapsed() >= STATE_SAVE_INTERVAL;
+            match deferred_flush {
+                Some(flush_interval) if save_due || last_flush.elapsed() >= flush_interval => {
+                    self.writer.defer_flush(false);
+                    self.writer.flush()?;
+                    self.writer.defer_flush(true);
+                    last_flush = Instant::now();
+                }
+                Some(_) => {}
+                None => self.writer.flush()?,
+            }
+            self.accept_event_clients();
+            // Saved after the flush, so the saved offsets are always printed
+            if save_due {
+                self.save_positions();
+                last_save = Instant::now();
+            }
+            if redraw_status {
+                self.print_status();
+            }
+            if self.exceeds_max_output(config) {
+                self.end_of_output(config)?;
+                return Ok(());
+            }
+
+            if config.exit_when_empty {
+                if !self.file_map.is_empty() {
+                    ever_matched = true;
+                    empty_since = None;
+                } else if ever_matched {
+                    let since = *empty_since.get_or_insert_with(Instant::now);
+                    if since.elapsed() >= EMPTY_GRACE_PERIOD {
+                        self.end_of_output(config)?;
+                        return Ok(());
+                    }
+                }
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+
+    use super::{
+        file_url, format_size, normalize_windows_path, snapshot_name, stripped_start,
+        truncated_start, unique_suffixes, DirectoryWatcher,
+    };
+    use crate::config::Config;
+
+    fn create_test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("regtail-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_with_writer_captures_output() {
+        let dir = create_test_dir("with-writer");
+        let file_path = dir.join("test.log");
+        fs::write(&file_path, "first\nsecond\n").unwrap();
+
+        let config = Config::builder().path(&dir).lines(1).build();
+        let mut watcher = DirectoryWatcher::with_writer(&config, Vec::new()).unwrap();
+        watcher.tail_initial_files(&config).unwrap();
+
+        let mut file = OpenOptions::new().append(true).open(&file_path).unwrap();
+        file.write_all(b"third\n").unwrap();
+        watcher
+            .handle_write(file_path.canonicalize().unwrap())
+            .unwrap();
+
+        let output = String::from_utf8(watcher.writer.get_ref().clone()).unwrap();
+        assert_eq!(
+            output,
+            format!("==> {} <==\nsecond\nthird\n", file_path.display())
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reconcile_directory() {
+        let dir = create_test_dir("reconcile");
+        fs::write(dir.join("moved.log"), "first\n").unwrap();
+        fs::write(dir.join("removed.log"), "second\n").unwrap();
+
+        let config = Config::builder().path(&dir).build();
+        let mut watcher = DirectoryWatcher::with_writer(&config, Vec::new()).unwrap();
+        watcher.tail_initial_files(&config).unwrap();
+
+        fs::rename(dir.join("moved.log"), dir.join("renamed.log")).unwrap();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir.join("renamed.log"))
+            .unwrap();
+        file.write_all(b"appended\n").unwrap();
+        fs::remove_file(dir.join("removed.log")).unwrap();
+        fs::write(dir.join("created.log"), "third\n").unwrap();
+        let canonical_dir = dir.canonicalize().unwrap();
+        watcher.reconcile_directory(&canonical_dir).unwrap();
+
+        let mut followed: Vec<PathBuf> = watcher.file_map.keys().cloned().collect();
+        followed.sort();
+        assert_eq!(
+            followed,
+            vec![
+                canonical_dir.join("created.log"),
+                canonical_dir.join("renamed.log")
+            ]
+        );
+        let output = String::from_utf8(watcher.writer.get_ref().clone()).unwrap();
+        // The renamed file is followed from the last offset
+        assert_eq!(output.matches("first").count(), 1);
+        assert!(output.contains("created.log <==\nthird\n"));
+        assert!(output.contains("renamed.log <==\nappended\n"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_watch_limit_hint() {
+        use super::watch_error_hint;
+        use notify::Error as NotifyError;
+        use std::io;
+
+        let error = NotifyError::Io(io::Error::from_raw_os_error(libc::ENOSPC));
+        let hint = watch_error_hint(&error).unwrap();
+        assert!(hint.contains("fs.inotify.max_user_watches"));
+
+        let error = NotifyError::Io(io::Error::from_raw_os_error(libc::EMFILE));
+        let hint = watch_error_hint(&error).unwrap();
+        assert!(hint.contains("fs.inotify.max_user_instances"));
+
+        let error = NotifyError::Io(io::Error::from_raw_os_error(libc::ENOENT));
+        assert!(watch_error_hint(&error).is_none());
+        assert!(watch_error_hint(&NotifyError::PathNotFound).is_none());
+    }
+
+    #[test]
+    fn test_snapshot_name() {
+        assert_eq!(snapshot_name(Path::new("app.log")), "app.log");
+        assert_eq!(snapshot_name(Path::new("a/b.log")), "a%2Fb.log");
+        assert_eq!(snapshot_name(Path::new("a_b.log")), "a_b.log");
+        assert_eq!(snapshot_name(Path::new("c:%.log")), "c%3A%25.log");
+    }
+
+    #[test]
+    fn test_truncated_start() {
+        assert_eq!(truncated_start("dir/file.log", 20), 0);
+        assert_eq!(truncated_start("dir/file.log", 12), 0);
+        assert_eq!(truncated_start("dir/file.log", 8), 4);
+        // Never splits a multibyte character
+        assert_eq!(
+            truncated_start("ディレクトリ/ログ", 3),
+            "ディレクトリ".len()
+        );
+        assert_eq!(truncated_start("dir/file.log", 0), 0);
+    }
+
+    #[test]
+    fn test_stripped_start() {
+        let prefix = Path::new("/very/long");
+        assert_eq!(stripped_start("/very/long/dir/file.log", prefix), 11);
+        assert_eq!(stripped_start("/very/long/", Path::new("/very")), 6);
+        // Only the whole components are stripped
+        assert_eq!(stripped_start("/very/longer/file.log", prefix), 0);
+        assert_eq!(stripped_start("/other/file.log", prefix), 0);
+        assert_eq!(stripped_start("/very/long", prefix), 0);
+    }
+
+    #[test]
+    fn test_unique_suffixes() {
+        let paths = [
+            PathBuf::from("/logs/a/current"),
+            PathBuf::from("/logs/b/current"),
+            PathBuf::from("/logs/b/error.log"),
+            PathBuf::from("/var/a/current"),
+        ];
+        let suffixes = unique_suffixes(paths.iter());
+        assert_eq!(suffixes[&paths[0]], "logs/a/current");
+        assert_eq!(suffixes[&paths[1]], "b/current");
+        assert_eq!(suffixes[&paths[2]], "error.log");
+        assert_eq!(suffixes[&paths[3]], "var/a/current");
+    }
+
+    #[test]
+    fn test_file_url() {
+        assert_eq!(
+            file_url(Path::new("/var/log/app 1.log")),
+            "file:///var/log/app%201.log"
+        );
+        assert_eq!(
+            file_url(Path::new("C:\\logs\\app.log")),
+            "file:///C:/logs/app.log"
+        );
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1.0 KiB");
+        assert_eq!(format_size(1258291), "1.2 MiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn test_normalize_windows_path() {
+        // Verbatim disk paths returned by canonicalize
+        assert_eq!(
+            normalize_windows_path(r"\\?\C:\logs\app.log"),
+            r"C:\logs\app.log"
+        );
+        assert_eq!(normalize_windows_path(r"\\?\C:\logs\"), r"C:\logs");
+        assert_eq!(normalize_windows_path(r"\\?\C:\"), r"C:\");
+        // Verbatim UNC paths
+        assert_eq!(
+            normalize_windows_path(r"\\?\UNC\server\share\app.log"),
+            r"\\server\share\app.log"
+        );
+        assert_eq!(
+            normalize_windows_path(r"\\server\share\dir\\"),
+            r"\\server\share\dir"
+        );
+        // Drive relative paths stay relative
+        assert_eq!(normalize_windows_path(r"C:logs\app.log"), r"C:logs\app.log");
+        assert_eq!(normalize_windows_path("C:"), "C:");
+        // Forward and mixed separators
+        assert_eq!(
+            normalize_windows_path("C:/logs/app.log"),
+            r"C:\logs\app.log"
+        );
+        assert_eq!(normalize_windows_path(r"logs/dir\\"), r"logs\dir");
+        assert_eq!(normalize_windows_path(r"\\?\C:\logs/dir/"), r"C:\logs\dir");
+        assert_eq!(normalize_windows_path("/logs//app.log"), r"\logs\app.log");
+    }
+}
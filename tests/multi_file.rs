@@ -14,11 +14,16 @@
  * limitations under the License.
  */
 
-use std::process::Command;
+use std::io::Write;
+use std::process::{Command, Stdio};
 use std::thread;
 use std::time::Duration;
 use thread::sleep;
 
+use bzip2::write::BzEncoder;
+use regex::Regex;
+use xz2::write::XzEncoder;
+
 use utils::KillStatus;
 use utils::RunningCommand;
 use utils::WorkingDir;
@@ -70,6 +75,56 @@ test!(multi_without_eol, |dir: WorkingDir, mut cmd: Command| {
     assert_contains!(output, " <==\ntest2!");
 });
 
+test!(
+    interleaved_partial_lines,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file("file1", "");
+        dir.put_file("file2", "");
+        sleep(WAIT_TIME);
+        let mut child = RunningCommand::create(cmd.arg(dir.path_arg()).spawn().unwrap());
+        sleep(WAIT_TIME);
+        dir.append_file("file1", "first");
+        sleep(WAIT_TIME);
+        dir.append_file("file2", "second");
+        sleep(WAIT_TIME);
+        dir.append_file("file1", "half\n");
+        sleep(WAIT_TIME);
+        dir.append_file("file2", "half\n");
+        sleep(WAIT_TIME);
+        let result = child.exit();
+        assert_eq!(result, KillStatus::Killed);
+        let output = child.output();
+        assert_contains!(output, "file1 <==\nfirst\n\n==>");
+        assert_contains!(output, "file2 <==\nsecond\n\n==>");
+        assert_contains!(output, "file1 <==\nfirsthalf\n\n==>");
+        assert_contains!(output, "file2 <==\nsecondhalf\n");
+        assert_not_contains!(output, "\nhalf\n");
+    }
+);
+
+test!(no_eol_fix, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file1", "");
+    dir.put_file("file2", "");
+    sleep(WAIT_TIME);
+    let mut child =
+        RunningCommand::create(cmd.arg("--no-eol-fix").arg(dir.path_arg()).spawn().unwrap());
+    sleep(WAIT_TIME);
+    dir.append_file("file1", "first");
+    sleep(WAIT_TIME);
+    dir.append_file("file2", "second");
+    sleep(WAIT_TIME);
+    dir.append_file("file1", "half\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "file1 <==\nfirst\n==>");
+    assert_contains!(output, "file2 <==\nsecond\n==>");
+    // The incomplete line is not printed again
+    assert_contains!(output, "file1 <==\nhalf\n");
+    assert_not_contains!(output, "firsthalf");
+});
+
 test!(multi_alread_exist, |dir: WorkingDir, mut cmd: Command| {
     dir.put_file("file1", "test1!\n");
     sleep(WAIT_TIME);
@@ -125,3 +180,1378 @@ test!(rename_back, |dir: WorkingDir, mut cmd: Command| {
     assert_contains!(output, "file2 <==\ntest2\n\n==>");
     assert_contains!(output, "file1 <==\ntest3");
 });
+
+test!(batch_initial, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file1", "test1!\n");
+    dir.put_file("file2", "test2!");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--batch-initial")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.append_file("file1", "test3!\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "file1 <==\ntest1!\n\n==>");
+    assert_contains!(output, "file2 <==\ntest2!\n\n==>");
+    assert_contains!(output, "file1 <==\ntest3!\n");
+});
+
+test!(multicolor, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file1", "test1!\n");
+    dir.put_file("file2", "test2!\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("-c")
+            .arg("always")
+            .arg("--multicolor")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    // The paths are picked to hash into purple and yellow of the default palette
+    let header_of = |name: &str| {
+        output
+            .lines()
+            .find(|line| line.contains("==> ") && line.contains(name))
+            .unwrap()
+            .to_owned()
+    };
+    assert!(header_of("file1").starts_with("\u{1b}[1;35m==> "));
+    assert!(header_of("file2").starts_with("\u{1b}[1;33m==> "));
+});
+
+test!(multicolor_prefix, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file1", "test1!\n");
+    dir.put_file("file2", "test2!\n");
+    let tee_path = format!("{}/captured", dir.display());
+    let output = cmd
+        .arg("--once")
+        .arg("-c")
+        .arg("always")
+        .arg("--multicolor")
+        .arg("--prefix")
+        .arg(format!("--tee={}", tee_path))
+        .arg("file")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    let output = String::from_utf8_lossy(&output.stdout);
+    // The paths are picked to hash into cyan and red, which the prefixes share with the headers
+    let file1 = format!("{}/file1", dir.display());
+    let file2 = format!("{}/file2", dir.display());
+    assert_contains!(output, format!("\u{1b}[1;36m{}:\u{1b}[0m test1!\n", file1));
+    assert_contains!(output, format!("\u{1b}[1;31m{}:\u{1b}[0m test2!\n", file2));
+    assert_contains!(output, "\u{1b}[1;36m==> ");
+    assert_contains!(output, "\u{1b}[1;31m==> ");
+    let captured = std::fs::read_to_string(tee_path).unwrap();
+    assert_contains!(captured, format!("{}: test1!\n", file1));
+    assert_contains!(captured, format!("{}: test2!\n", file2));
+    assert_not_contains!(captured, "\u{1b}[");
+});
+
+test!(hidden_excluded, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file(".hidden.log", "hidden!\n");
+    dir.put_file("visible.log", "visible!\n");
+    dir.put_file(".dir/nested.log", "nested!\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(cmd.arg("-r").arg(dir.path_arg()).spawn().unwrap());
+    sleep(WAIT_TIME);
+    dir.append_file(".hidden.log", "appended!\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "visible!\n");
+    assert_not_contains!(output, "hidden!");
+    assert_not_contains!(output, "appended!");
+    assert_not_contains!(output, "nested!");
+});
+
+test!(hidden_included, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file(".hidden.log", "hidden!\n");
+    dir.put_file("visible.log", "visible!\n");
+    dir.put_file(".dir/nested.log", "nested!\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("-r")
+            .arg("--hidden")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "visible!\n");
+    assert_contains!(output, "hidden!\n");
+    assert_contains!(output, "nested!\n");
+});
+
+test!(new_subdirectory, |dir: WorkingDir, mut cmd: Command| {
+    let mut child = RunningCommand::create(cmd.arg("-r").arg(dir.path_arg()).spawn().unwrap());
+    sleep(WAIT_TIME);
+    // The file is created before the new directories are watched
+    dir.put_file("new/sub/file.log", "created!\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "file.log <==\ncreated!\n");
+    assert_eq!(output.matches("created!").count(), 1);
+});
+
+test!(once, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file1", "1\n2\n3\n");
+    dir.put_file("file2", "test2!\n");
+    let output = cmd
+        .arg("--once")
+        .arg("-l=2")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let output = String::from_utf8_lossy(&output.stdout);
+    assert_contains!(output, "file1 <==\n2\n3\n\n==>");
+    assert_contains!(output, "file2 <==\ntest2!\n");
+});
+
+test!(
+    once_prefix_without_newline,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file("file", "a\nb\nlast");
+        let output = cmd
+            .arg("--once")
+            .arg("--prefix")
+            .arg(dir.path_arg())
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        let output = String::from_utf8_lossy(&output.stdout);
+        // The incomplete last line is written as no more content follows
+        let file = format!("{}/file", dir.display());
+        assert_contains!(output, format!("{0}: b\n{0}: last", file));
+    }
+);
+
+test!(
+    once_squeeze_and_count,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file("file", "a\nb\nb\nb\n");
+        let output = cmd
+            .arg("--once")
+            .arg("--squeeze")
+            .arg(dir.path_arg())
+            .output()
+            .unwrap();
+        let output = String::from_utf8_lossy(&output.stdout);
+        assert_contains!(output, "a\nb\n(repeated 2 times)\n");
+
+        let output = utils::command()
+            .arg("--once")
+            .arg("--count")
+            .arg(dir.path_arg())
+            .output()
+            .unwrap();
+        let output = String::from_utf8_lossy(&output.stdout);
+        assert_eq!(output, format!("{}/file: 4 lines\n", dir.display()));
+    }
+);
+
+#[cfg(unix)]
+test!(unreadable_file, |dir: WorkingDir, mut cmd: Command| {
+    use std::fs::{self, File, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+
+    dir.put_file("readable", "readable!\n");
+    dir.put_file("unreadable", "unreadable!\n");
+    let unreadable = format!("{}/unreadable", dir.display());
+    fs::set_permissions(&unreadable, Permissions::from_mode(0o000)).unwrap();
+    // Permissions are not enforced for a privileged user
+    if File::open(&unreadable).is_ok() {
+        return;
+    }
+    let output = cmd.arg("--once").arg(dir.path_arg()).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_contains!(stdout, "readable <==\nreadable!\n");
+    assert_not_contains!(stdout, "unreadable");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_contains!(stderr, "cannot open");
+});
+
+test!(verbose_binary_skip, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("text.log", "text!\n");
+    dir.put_file("binary.log", b"binary!\0\x01\x02\n");
+    let output = cmd
+        .arg("--once")
+        .arg("--verbose")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_contains!(stdout, "text.log <==\ntext!\n");
+    assert_not_contains!(stdout, "binary!");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_contains!(stderr, "skipping binary file: ");
+    assert_contains!(stderr, "binary.log");
+});
+
+test!(inspect_bytes, |dir: WorkingDir, mut cmd: Command| {
+    // Text in the first KB, but a null byte follows
+    let mut content = "text!\n".repeat(300).into_bytes();
+    content.extend_from_slice(b"late\0binary\n");
+    dir.put_file("mixed.log", content);
+    let output = cmd.arg("--once").arg(dir.path_arg()).output().unwrap();
+    assert_contains!(String::from_utf8_lossy(&output.stdout), "mixed.log <==");
+    let run = |args: &[&str]| {
+        let output = utils::command()
+            .arg("--once")
+            .args(args)
+            .arg(dir.path_arg())
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert_not_contains!(run(&["--inspect-bytes=4096"]), "mixed.log");
+    assert_not_contains!(run(&["--inspect-full"]), "mixed.log");
+    assert_contains!(run(&["--inspect-bytes=16"]), "mixed.log <==");
+});
+
+test!(extension, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("a.log", "log!\n");
+    dir.put_file("a.txt", "txt!\n");
+    dir.put_file("B.LOG", "upper!\n");
+    let output = cmd
+        .arg("--once")
+        .arg("--ext=log")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_contains!(stdout, "a.log <==\nlog!\n");
+    assert_not_contains!(stdout, "txt!");
+    assert_not_contains!(stdout, "upper!");
+});
+
+test!(
+    extension_ignore_case,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file("a.log", "log!\n");
+        dir.put_file("a.txt", "txt!\n");
+        dir.put_file("B.LOG", "upper!\n");
+        let output = cmd
+            .arg("--once")
+            .arg("-i")
+            .arg("--ext=.log,md")
+            .arg(dir.path_arg())
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert_contains!(stdout, "a.log <==\nlog!\n");
+        assert_contains!(stdout, "B.LOG <==\nupper!\n");
+        assert_not_contains!(stdout, "txt!");
+    }
+);
+
+test!(status, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file1", "test1!\n");
+    let mut child = RunningCommand::create(
+        cmd.arg("--status")
+            .arg(dir.path_arg())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.put_file("file2", "test2!\n");
+    sleep(WAIT_TIME);
+    dir.put_file("file3", "test3!\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let stderr = child.error_output();
+    assert_contains!(stderr, "\rwatching 1 file");
+    assert!(stderr.ends_with("\rwatching 3 files"));
+    let output = child.output();
+    assert_contains!(output, "test3!\n");
+});
+
+test!(separator, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file1", "test1!\n");
+    dir.put_file("file2", "test2!\n");
+    let output = cmd
+        .arg("--once")
+        .arg("--separator=-----")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_contains!(stdout, "file1 <==\ntest1!\n-----\n==> ");
+});
+
+test!(empty_separator, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file1", "test1!\n");
+    dir.put_file("file2", "test2!\n");
+    let output = cmd
+        .arg("--once")
+        .arg("--separator=")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_contains!(stdout, "file1 <==\ntest1!\n==> ");
+});
+
+test!(absolute_paths, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file1", "test1!\n");
+    let absolute_dir = std::fs::canonicalize(dir.display().to_string()).unwrap();
+    let mut child = RunningCommand::create(
+        cmd.arg("--absolute-paths")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.put_file("file2", "test2!\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(
+        output,
+        format!("==> {}/file1 <==\ntest1!\n", absolute_dir.display())
+    );
+    assert_contains!(
+        output,
+        format!("==> {}/file2 <==\ntest2!\n", absolute_dir.display())
+    );
+});
+
+test!(relative_to, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("logs/file1", "test1!\n");
+    let mut child = RunningCommand::create(
+        cmd.arg("--relative-to")
+            .arg(dir.display().to_string())
+            .arg(format!("-p={}/logs", dir.display()))
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.put_file("logs/file2", "test2!\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "==> logs/file1 <==\ntest1!\n");
+    assert_contains!(output, "==> logs/file2 <==\ntest2!\n");
+});
+
+test!(glob_path, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("app1/current", "app1!\n");
+    dir.put_file("app2/current", "app2!\n");
+    dir.put_file("other/current", "other!\n");
+    let mut child = RunningCommand::create(
+        cmd.arg(format!("-p={}/app*", dir.display()))
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.append_file("app2/current", "appended!\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "app1/current <==\napp1!\n");
+    assert_contains!(output, "app2/current <==\napp2!\n");
+    assert_contains!(output, "appended!\n");
+    assert_not_contains!(output, "other!");
+});
+
+test!(
+    glob_path_without_match,
+    |dir: WorkingDir, mut cmd: Command| {
+        let output = cmd
+            .arg(format!("-p={}/none*", dir.display()))
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_contains!(stderr, "no directory matches the glob");
+    }
+);
+
+test!(tee, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("logs/file1", "test1!\n");
+    let tee_path = format!("{}/captured", dir.display());
+    let mut child = RunningCommand::create(
+        cmd.arg("-c")
+            .arg("always")
+            .arg(format!("--tee={}", tee_path))
+            .arg(format!("-p={}/logs", dir.display()))
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.put_file("logs/file2", "test2!\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "\u{1b}[");
+    assert_contains!(output, "test1!\n");
+    assert_contains!(output, "test2!\n");
+    let captured = std::fs::read_to_string(tee_path).unwrap();
+    assert_contains!(captured, "file1 <==\ntest1!\n\n==> ");
+    assert_contains!(captured, "file2 <==\ntest2!\n");
+    assert_not_contains!(captured, "\u{1b}[");
+});
+
+test!(header_meta, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("sized", "0123456789\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--header-meta")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    let header = output.lines().next().unwrap();
+    assert!(header.starts_with("==> "), "{}", header);
+    assert!(header.contains("sized (11 B, "), "{}", header);
+    let timestamp = Regex::new(r", \d{4}-\d{2}-\d{2} \d{2}:\d{2}\) <==$").unwrap();
+    assert!(timestamp.is_match(header), "{}", header);
+});
+
+test!(combined_lines, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("a", "a1\na2\na3\n");
+    dir.put_file("b", "b1\nb2\n");
+    dir.put_file("c", "c1\nc2");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--combined-lines=5")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.append_file("a", "a4\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    // Ordered by file then position
+    assert_contains!(output, "/a <==\na3\n\n");
+    assert_contains!(output, "/b <==\nb1\nb2\n\n");
+    assert_contains!(output, "/c <==\nc1\nc2");
+    assert_not_contains!(output, "a2");
+    assert_contains!(output, "/a <==\na4\n");
+});
+
+#[cfg(target_os = "linux")]
+test!(duplicate_symlink, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("target.log", "only once\n");
+    dir.symlink("target.log", "link.log");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--verbose")
+            .arg("log$")
+            .arg(dir.path_arg())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.append_file("target.log", "appended\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_eq!(output.matches("only once").count(), 1, "{}", output);
+    assert_eq!(output.matches("appended").count(), 1, "{}", output);
+    assert_contains!(child.error_output(), "skipping duplicate path");
+});
+
+test!(full_path_match, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("access.log", "access!\n");
+    dir.create_dir("access");
+    dir.put_file("access/other.log", "other!\n");
+    let output = cmd
+        .arg("--once")
+        .arg("-r")
+        .arg("^access")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // The path starts with the watched directory
+    assert_not_contains!(stdout, "access!");
+    assert_not_contains!(stdout, "other!");
+});
+
+test!(basename_match, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("access.log", "access!\n");
+    dir.create_dir("access");
+    dir.put_file("access/other.log", "other!\n");
+    let output = cmd
+        .arg("--once")
+        .arg("-r")
+        .arg("--basename")
+        .arg("-c")
+        .arg("always")
+        .arg("^access")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_contains!(stdout, "access!");
+    assert_not_contains!(stdout, "other!");
+    // Only the file name is colored as the match
+    assert_contains!(stdout, "basename_match/\x1b[0m\x1b[1;32maccess\x1b[0m");
+});
+
+test!(truncate_header, |dir: WorkingDir, mut cmd: Command| {
+    dir.create_dir("very");
+    dir.create_dir("very/long");
+    dir.create_dir("very/long/deeply");
+    dir.put_file("very/long/deeply/nested.log", "nested!\n");
+    let output = cmd
+        .arg("--once")
+        .arg("-r")
+        .arg("--truncate-header=20")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_contains!(stdout, "==> \u{2026}ng/deeply/nested.log <==\nnested!\n");
+});
+
+test!(strip_prefix, |dir: WorkingDir, mut cmd: Command| {
+    dir.create_dir("logs");
+    dir.create_dir("logs/app");
+    dir.put_file("logs/app/app.log", "app!\n");
+    dir.put_file("other.log", "other!\n");
+    let output = cmd
+        .arg("--once")
+        .arg("-r")
+        .arg("--regex=logs/app/")
+        .arg(format!("--strip-prefix={}/logs", dir.display()))
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_contains!(stdout, "==> app/app.log <==\napp!\n");
+    assert_not_contains!(stdout, "other!");
+});
+
+test!(decompress, |dir: WorkingDir, mut cmd: Command| {
+    let mut encoder = XzEncoder::new(Vec::new(), 6);
+    encoder.write_all(b"xz line1\nxz line2\n").unwrap();
+    dir.put_file("archived.log.xz", encoder.finish().unwrap());
+    let mut encoder = BzEncoder::new(Vec::new(), bzip2::Compression::default());
+    encoder.write_all(b"bz2 line1\nbz2 line2\n").unwrap();
+    dir.put_file("archived.log.bz2", encoder.finish().unwrap());
+    sleep(WAIT_TIME);
+    let mut child =
+        RunningCommand::create(cmd.arg("--decompress").arg(dir.path_arg()).spawn().unwrap());
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "archived.log.xz <==\nxz line1\nxz line2\n");
+    assert_contains!(output, "archived.log.bz2 <==\nbz2 line1\nbz2 line2\n");
+});
+
+test!(
+    decompress_incomplete,
+    |dir: WorkingDir, mut cmd: Command| {
+        let mut encoder = XzEncoder::new(Vec::new(), 6);
+        encoder.write_all(b"xz line1\nxz line2\n").unwrap();
+        let compressed = encoder.finish().unwrap();
+        dir.put_file("archived.log.xz", &compressed[..compressed.len() / 2]);
+        dir.put_file("app.log", "app line\n");
+        sleep(WAIT_TIME);
+        let mut child = RunningCommand::create(
+            cmd.arg("--decompress")
+                .arg(dir.path_arg())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap(),
+        );
+        sleep(WAIT_TIME);
+        dir.put_file("archived.log.xz", compressed);
+        sleep(WAIT_TIME);
+        let result = child.exit();
+        assert_eq!(result, KillStatus::Killed);
+        let output = child.output();
+        assert_contains!(output, "app.log <==\napp line\n");
+        assert_contains!(output, "archived.log.xz <==\nxz line1\nxz line2\n");
+        assert_contains!(child.error_output(), "the compressed content is incomplete");
+    }
+);
+
+#[cfg(target_os = "macos")]
+test!(
+    rapid_rename_and_append,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file("app.log", "line1\n");
+        sleep(WAIT_TIME);
+        let mut child = RunningCommand::create(cmd.arg(dir.path_arg()).spawn().unwrap());
+        sleep(WAIT_TIME);
+        dir.rename_file("app.log", "app.log.1");
+        dir.append_file("app.log.1", "line2\n");
+        dir.put_file("app.log", "line3\n");
+        sleep_for_rename();
+        let result = child.exit();
+        assert_eq!(result, KillStatus::Killed);
+        let output = child.output();
+        assert_eq!(output.matches("line1").count(), 1);
+        assert_contains!(output, "app.log.1 <==\nline2\n");
+        assert_contains!(output, "app.log <==\nline3\n");
+    }
+);
+
+test!(follow_renamed, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("app.log", "line1\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--follow-renamed")
+            .arg("log$")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.rename_file("app.log", "app.log.1");
+    sleep_for_rename();
+    dir.append_file("app.log.1", "line2\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "app.log <==\nline1\n\n==>");
+    assert_contains!(output, "app.log.1 <==\nline2\n");
+});
+
+test!(theme, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("app.log", "line1\n");
+    let output = cmd
+        .arg("--once")
+        .arg("-c")
+        .arg("always")
+        .arg("--theme")
+        .arg("light")
+        .arg("app")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_contains!(stdout, "\x1b[1;38;5;18m==> ");
+    assert_contains!(stdout, "\x1b[1;38;5;22mapp\x1b[0m");
+    // Not the blue header and the green match of the default theme
+    assert_not_contains!(stdout, "\x1b[1;34m");
+    assert_not_contains!(stdout, "\x1b[1;32m");
+});
+
+test!(hyperlinks, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("app.log", "line1\n");
+    let canonical_path = std::fs::canonicalize(format!("{}/app.log", dir.display())).unwrap();
+    let output = cmd
+        .arg("--once")
+        .arg("-c")
+        .arg("always")
+        .arg("--hyperlinks")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_contains!(
+        stdout,
+        format!("\x1b]8;;file://{}\x1b\\", canonical_path.display())
+    );
+    // The link ends right after the path
+    assert_contains!(
+        stdout,
+        "app.log\x1b[0m\x1b[1;34m\x1b[0m\x1b]8;;\x1b\\\x1b[1;34m <=="
+    );
+});
+
+test!(tag, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("app1/current", "app1!\n");
+    dir.put_file("app2/current", "app2!\n");
+    let mut child = RunningCommand::create(
+        cmd.arg("-r")
+            .arg("--tag")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.append_file("app2/current", "appended!\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "==> [app1/current] ");
+    assert_contains!(output, "[app1/current] app1!\n");
+    assert_contains!(output, "[app2/current] app2!\n");
+    assert_contains!(output, "[app2/current] appended!\n");
+});
+
+test!(tag_with_prefix, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("app1/current", "app1!\n");
+    dir.put_file("app2/current", "app2!\n");
+    let mut child = RunningCommand::create(
+        cmd.arg("-r")
+            .arg("--tag")
+            .arg("--prefix")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(
+        output,
+        format!("[app1/current] {}/app1/current: app1!\n", dir.display())
+    );
+    assert_contains!(
+        output,
+        format!("[app2/current] {}/app2/current: app2!\n", dir.display())
+    );
+});
+
+test!(tag_after_remove, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("app1/current", "app1!\n");
+    dir.put_file("app2/current", "app2!\n");
+    let mut child = RunningCommand::create(
+        cmd.arg("-r")
+            .arg("--tag")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.remove_file("app2/current");
+    sleep(WAIT_TIME);
+    dir.append_file("app1/current", "alone!\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    // The remaining file is told by the file name alone
+    assert_contains!(output, "[current] alone!\n");
+});
+
+test!(no_matched_files, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("app.log", "line1\n");
+    let mut child = RunningCommand::create(
+        cmd.arg("nomatch")
+            .arg(dir.path_arg())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.put_file("nomatch.log", "line2\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    assert_contains!(child.output(), "line2\n");
+    assert_contains!(
+        child.error_output(),
+        format!("warning: no files matched nomatch under {}", dir.display())
+    );
+});
+
+test!(
+    no_matched_extensions,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file("app.log", "line1\n");
+        let output = cmd
+            .arg("--once")
+            .arg("--ext=txt,md")
+            .arg(dir.path_arg())
+            .stderr(Stdio::piped())
+            .output()
+            .unwrap();
+        assert_contains!(
+            String::from_utf8_lossy(&output.stderr),
+            format!(
+                "warning: no files matched --ext txt,md under {}",
+                dir.display()
+            )
+        );
+    }
+);
+
+test!(content_match, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("a.log", "{\"schema\":\"app-v1\"}\nfirst\n");
+    dir.put_file("b.log", "plain\nsecond\n");
+    dir.put_file("c.log", "");
+    let output = cmd
+        .arg("--once")
+        .arg("--content-match")
+        .arg("\"schema\":\"app-v[0-9]+\"")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_contains!(stdout, "a.log <==\n");
+    assert_contains!(stdout, "first\n");
+    assert_not_contains!(stdout, "b.log");
+    assert_not_contains!(stdout, "second\n");
+    // Too short to be sampled, so it is skipped until written like b.log
+    assert_not_contains!(stdout, "c.log");
+});
+
+test!(
+    content_match_after_write,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file("b.log", "plain\n");
+        dir.put_file("c.log", "");
+        let mut child = RunningCommand::create(
+            cmd.arg("--content-match")
+                .arg("schema")
+                .arg(dir.path_arg())
+                .spawn()
+                .unwrap(),
+        );
+        sleep(WAIT_TIME);
+        dir.append_file("b.log", "second\n");
+        dir.append_file("c.log", "third\n");
+        sleep(WAIT_TIME);
+        let result = child.exit();
+        assert_eq!(result, KillStatus::Killed);
+        // The skipped files are followed as new files once written
+        let output = child.output();
+        assert_contains!(output, "b.log <==\nplain\nsecond\n");
+        assert_contains!(output, "c.log <==\nthird\n");
+    }
+);
+
+test!(snapshot, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("app.log", "1\n2\n3\n4\n5\n");
+    dir.put_file("sub/other.log", "a\nb\nc\n");
+    dir.put_file("sub_other.log", "x\r\ny\r\nz\r\n");
+    let snapshot_dir = format!("{}/snapshot", dir.display());
+    let mut child = RunningCommand::create(
+        cmd.arg("-r")
+            .arg("-l")
+            .arg("2")
+            .arg("--strip-cr")
+            .arg("--snapshot")
+            .arg(&snapshot_dir)
+            .arg(r"\.log$")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.append_file("app.log", "6\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    // The following continues after the snapshot
+    assert_contains!(child.output(), "app.log <==\n6\n");
+    let read = |name: &str| std::fs::read_to_string(format!("{}/{}", snapshot_dir, name)).unwrap();
+    assert_eq!(read("app.log.tail"), "4\n5\n");
+    assert_eq!(read("sub%2Fother.log.tail"), "b\nc\n");
+    // The lines are processed as printed
+    assert_eq!(read("sub_other.log.tail"), "y\nz\n");
+});
+
+test!(reverse_sort, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("a.log", "a\n");
+    dir.put_file("b.log", "b\n");
+    dir.put_file("c.log", "c\n");
+    let output = cmd
+        .arg("--once")
+        .arg("--reverse-sort")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let position = |name: &str| stdout.find(&format!("{} <==", name)).unwrap();
+    assert!(position("c.log") < position("b.log"));
+    assert!(position("b.log") < position("a.log"));
+});
+
+test!(lines_config, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("logs/access.log", "a1\na2\na3\na4\n");
+    dir.put_file("logs/error.log", "e1\ne2\ne3\ne4\n");
+    dir.put_file("logs/app.log", "p1\np2\np3\np4\n");
+    dir.put_file("lines.conf", "# per file\naccess\\.log 3\nerror\\.log 1\n");
+    let output = cmd
+        .arg("--once")
+        .arg("-l")
+        .arg("2")
+        .arg(format!("--lines-config={}/lines.conf", dir.display()))
+        .arg(format!("-p={}/logs", dir.display()))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_contains!(stdout, "access.log <==\na2\na3\na4\n");
+    assert_not_contains!(stdout, "a1");
+    assert_contains!(stdout, "error.log <==\ne4\n");
+    assert_not_contains!(stdout, "e3");
+    // Falls back to -l
+    assert_contains!(stdout, "app.log <==\np3\np4\n");
+    assert_not_contains!(stdout, "p2");
+});
+
+test!(tee_bom, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("logs/file1", "test1!\n");
+    let tee_path = format!("{}/captured", dir.display());
+    let mut child = RunningCommand::create(
+        cmd.arg("--bom")
+            .arg(format!("--tee={}", tee_path))
+            .arg(format!("-p={}/logs", dir.display()))
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.put_file("logs/file2", "test2!\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    assert_not_contains!(child.output(), "\u{feff}");
+    let captured = std::fs::read(tee_path).unwrap();
+    assert!(captured.starts_with(b"\xef\xbb\xbf==> "));
+    // Only once, even though the followed file is switched
+    assert_eq!(
+        String::from_utf8_lossy(&captured)
+            .matches('\u{feff}')
+            .count(),
+        1
+    );
+    assert_contains!(String::from_utf8_lossy(&captured), "file2 <==\ntest2!\n");
+});
+
+#[cfg(unix)]
+test!(owner_and_mode, |dir: WorkingDir, mut cmd: Command| {
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    dir.put_file("shared.log", "shared!\n");
+    dir.put_file("private.log", "private!\n");
+    let path = |name: &str| format!("{}/{}", dir.display(), name);
+    fs::set_permissions(path("shared.log"), Permissions::from_mode(0o644)).unwrap();
+    fs::set_permissions(path("private.log"), Permissions::from_mode(0o600)).unwrap();
+    let uid = fs::metadata(path("shared.log")).unwrap().uid();
+    let output = cmd
+        .arg("--once")
+        .arg(format!("--owner={}", uid))
+        .arg("--mode=044")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_contains!(stdout, "shared!\n");
+    assert_not_contains!(stdout, "private!");
+
+    let output = utils::command()
+        .arg("--once")
+        .arg(format!("--owner={}", uid + 1))
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_not_contains!(stdout, "shared!");
+});
+
+test!(self_heal, |dir: WorkingDir, mut cmd: Command| {
+    dir.create_dir("logs");
+    dir.put_file("logs/old.log", "old\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--self-heal")
+            .arg("1")
+            .arg(format!("-p={}/logs", dir.display()))
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    // The watch on the removed directory does not cover the recreated one
+    std::fs::remove_dir_all(format!("{}/logs", dir.display())).unwrap();
+    dir.create_dir("logs");
+    sleep(Duration::from_millis(1500));
+    // Modified after the quiet interval began
+    dir.put_file("logs/new.log", "recovered\n");
+    sleep(Duration::from_secs(3));
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    assert_contains!(child.output(), "recovered\n");
+    assert_contains!(child.error_output(), "watching again");
+});
+
+test!(rotation, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("app.log.2", "line1\n");
+    dir.put_file("app.log.1", "line2\n");
+    dir.put_file("app.log", "line3\n");
+    sleep(WAIT_TIME);
+    let mut child =
+        RunningCommand::create(cmd.arg("--rotation").arg(dir.path_arg()).spawn().unwrap());
+    sleep(WAIT_TIME);
+    dir.append_file("app.log", "line4\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "app.log <==\nline1\nline2\nline3\nline4\n");
+    assert_eq!(output.matches("==> ").count(), 1);
+});
+
+test!(max_files, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file1", "line1\n");
+    dir.put_file("file2", "line2\n");
+    dir.put_file("file3", "line3\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--max-files=2")
+            .arg(dir.path_arg())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.append_file("file3", "appended3\n");
+    dir.put_file("file4", "line4\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "file1 <==\nline1\n");
+    assert_contains!(output, "file2 <==\nline2\n");
+    assert_not_contains!(output, "line3");
+    assert_not_contains!(output, "line4");
+    assert_contains!(
+        child.error_output(),
+        "following only 2 files, 1 file skipped"
+    );
+});
+
+test!(merge_by_timestamp, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file(
+        "logs/a.log",
+        "2019-01-02 03:04:01 a1\n2019-01-02 03:04:04 a2\n  continued a2\n",
+    );
+    dir.put_file(
+        "logs/b.log",
+        "2019-01-02 03:04:02 b1\n2019-01-02 03:04:03 b2\n2019-01-02 03:04:05 b3\n",
+    );
+    let output = cmd
+        .arg("--once")
+        .arg("--merge")
+        .arg(format!("-p={}/logs", dir.display()))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // The line without a timestamp follows the line of its file
+    assert_eq!(
+        stdout,
+        "2019-01-02 03:04:01 a1\n2019-01-02 03:04:02 b1\n2019-01-02 03:04:03 b2\n\
+         2019-01-02 03:04:04 a2\n  continued a2\n2019-01-02 03:04:05 b3\n"
+    );
+});
+
+test!(merge_appended_lines, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("logs/a.log", "");
+    dir.put_file("logs/b.log", "");
+    let mut child = RunningCommand::create(
+        cmd.arg("--merge")
+            .arg("--timestamp-regex=^\\[(\\d+-\\d+-\\d+ [\\d:]+)\\]")
+            .arg(format!("-p={}/logs", dir.display()))
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.append_file("logs/b.log", "[2019-01-02 03:04:06] b1\n");
+    dir.append_file("logs/a.log", "[2019-01-02 03:04:05] a1\n");
+    dir.append_file("logs/b.log", "[2019-01-02 03:04:08] b2\n");
+    dir.append_file("logs/a.log", "[2019-01-02 03:04:07] a2\n");
+    sleep(WAIT_TIME * 3);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    assert_eq!(
+        child.output(),
+        "[2019-01-02 03:04:05] a1\n[2019-01-02 03:04:06] b1\n\
+         [2019-01-02 03:04:07] a2\n[2019-01-02 03:04:08] b2\n"
+    );
+});
+
+test!(max_read_per_event, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("logs/busy.log", "");
+    dir.put_file("logs/quiet.log", "");
+    let mut child = RunningCommand::create(
+        cmd.arg("--max-read-per-event=4096")
+            .arg("--grep=^quiet")
+            .arg(format!("-p={}/logs", dir.display()))
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    let busy_path = format!("{}/logs/busy.log", dir.display());
+    let writer = thread::spawn(move || {
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(busy_path)
+            .unwrap();
+        let chunk = "busy!\n".repeat(10000);
+        let started = std::time::Instant::now();
+        while started.elapsed() < WAIT_TIME * 3 {
+            file.write_all(chunk.as_bytes()).unwrap();
+            sleep(Duration::from_millis(1));
+        }
+    });
+    sleep(WAIT_TIME);
+    dir.append_file("logs/quiet.log", "quiet!\n");
+    sleep(WAIT_TIME);
+    // Shown while the busy file keeps growing
+    let result = child.exit();
+    writer.join().unwrap();
+    assert_eq!(result, KillStatus::Killed);
+    assert_contains!(child.output(), "quiet!\n");
+});
+
+test!(eof_marker, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("logs/removed.log", "removed!\n");
+    dir.put_file("logs/renamed.log", "renamed!\n");
+    let mut child = RunningCommand::create(
+        cmd.arg("--eof-marker")
+            .arg("--regex=\\.log$")
+            .arg(format!("-p={}/logs", dir.display()))
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.remove_file("logs/removed.log");
+    sleep(WAIT_TIME);
+    dir.rename_file("logs/renamed.log", "logs/renamed.old");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "removed!\n");
+    let marker = Regex::new(r"(?m)^<== EOF \S*logs/removed\.log ==>$").unwrap();
+    assert!(marker.is_match(&output), "{}", output);
+    let marker = Regex::new(r"(?m)^<== EOF \S*logs/renamed\.log ==>$").unwrap();
+    assert!(marker.is_match(&output), "{}", output);
+});
+
+test!(container_logs, |dir: WorkingDir, mut cmd: Command| {
+    dir.create_dir("containers");
+    dir.put_file("pods/ns_pod_uid/app/0.log.20240101-000000", "rotated!\n");
+    dir.put_file("pods/ns_pod_uid/app/0.log", "first run!\n");
+    dir.put_file("pods/ns_pod_uid/app/1.log", "restarted!\n");
+    dir.symlink("pods/ns_pod_uid/app", "containers/app");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--container-logs")
+            .arg(format!("-p={}/containers", dir.display()))
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.append_file("pods/ns_pod_uid/app/1.log", "appended!\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(
+        output,
+        "containers/app <==\nrotated!\nfirst run!\nrestarted!\nappended!\n"
+    );
+    assert_eq!(output.matches("==> ").count(), 1);
+});
+
+test!(regtailignore, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file(".regtailignore", "# noisy\n*.debug.log\n");
+    dir.put_file("app.log", "app!\n");
+    dir.put_file("app.debug.log", "debug!\n");
+    dir.put_file("nested/worker.debug.log", "nested debug!\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("-e")
+            .arg("\\.log$")
+            .arg("--recursive")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.append_file("app.debug.log", "appended debug!\n");
+    dir.append_file("app.log", "appended app!\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "app!\nappended app!\n");
+    assert!(!output.contains("debug!"), "{}", output);
+});
+
+#[cfg(unix)]
+test!(
+    verbose_unreadable_dir,
+    |dir: WorkingDir, mut cmd: Command| {
+        use std::fs::{self, Permissions};
+        use std::os::unix::fs::PermissionsExt;
+
+        dir.put_file("readable/app.log", "readable!\n");
+        dir.put_file("private/app.log", "private!\n");
+        let private = format!("{}/private", dir.display());
+        fs::set_permissions(&private, Permissions::from_mode(0o000)).unwrap();
+        // Permissions are not enforced for a privileged user
+        let enforced = fs::read_dir(&private).is_err();
+        let output = cmd
+            .arg("--once")
+            .arg("--verbose")
+            .arg("--recursive")
+            .arg(dir.path_arg())
+            .output()
+            .unwrap();
+        // Restored so that the directory can be cleaned up
+        fs::set_permissions(&private, Permissions::from_mode(0o755)).unwrap();
+        if !enforced {
+            return;
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert_contains!(stdout, "readable!\n");
+        assert_not_contains!(stdout, "private!");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_contains!(stderr, "cannot access ");
+        assert_contains!(stderr, "private: permission denied");
+    }
+);
+
+test!(idle_timeout, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("idle.log", "idle!\n");
+    dir.put_file("active.log", "active!\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--idle-timeout=1")
+            .arg("--verbose")
+            .arg(dir.path_arg())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    for i in 0..6 {
+        sleep(Duration::from_millis(400));
+        dir.append_file("active.log", &format!("active {}\n", i));
+    }
+    // Followed again from where it was released
+    dir.append_file("idle.log", "woke up!\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "idle.log <==\nwoke up!\n");
+    assert_eq!(output.matches("idle!").count(), 1);
+    assert_contains!(output, "active 5\n");
+    let error_output = child.error_output();
+    assert_contains!(error_output, "released idle file: ");
+    assert_contains!(error_output, "idle.log");
+    assert_not_contains!(error_output, "active.log");
+});
+
+test!(
+    idle_timeout_partial_line,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file("app.log", "");
+        sleep(WAIT_TIME);
+        let mut child = RunningCommand::create(
+            cmd.arg("--idle-timeout=1")
+                .arg("--grep=abc")
+                .arg(dir.path_arg())
+                .spawn()
+                .unwrap(),
+        );
+        sleep(WAIT_TIME);
+        dir.append_file("app.log", "abc");
+        sleep(Duration::from_millis(2500));
+        dir.append_file("app.log", "def\n");
+        sleep(WAIT_TIME);
+        let result = child.exit();
+        assert_eq!(result, KillStatus::Killed);
+        assert_contains!(child.output(), "abcdef\n");
+    }
+);
+
+test!(depth_after_startup, |dir: WorkingDir, mut cmd: Command| {
+    dir.create_dir("a");
+    dir.create_dir("a/b");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("-r")
+            .arg("-d")
+            .arg("2")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.put_file("a/b/deep.log", "too deep!\n");
+    dir.put_file("a/shallow.log", "shallow!\n");
+    sleep(WAIT_TIME);
+    dir.append_file("a/b/deep.log", "still too deep!\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "shallow!\n");
+    assert_not_contains!(output, "too deep!");
+});
+
+#[cfg(target_os = "linux")]
+test!(dereference, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("real/target.log", "target!\n");
+    dir.put_file("other.log", "other!\n");
+    dir.symlink("real/target.log", "link.log");
+    sleep(WAIT_TIME);
+    // The target is walked as well, and followed once under the link found first
+    let follow = |command: &mut Command| {
+        let mut child = RunningCommand::create(
+            command
+                .arg("-r")
+                .arg("log$")
+                .arg(dir.path_arg())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap(),
+        );
+        sleep(WAIT_TIME);
+        dir.append_file("real/target.log", "appended!\n");
+        sleep(WAIT_TIME);
+        let result = child.exit();
+        assert_eq!(result, KillStatus::Killed);
+        child.output()
+    };
+    // The link is shown on startup and on the later writes alike
+    let output = follow(&mut cmd);
+    assert_contains!(output, "link.log <==\ntarget!\n");
+    assert_contains!(output, "link.log <==\nappended!\n");
+    assert_not_contains!(output, "target.log <==");
+
+    let output = follow(utils::command().arg("--dereference"));
+    assert_contains!(output, "real/target.log <==\ntarget!\n");
+    assert_contains!(output, "real/target.log <==\nappended!\n");
+    assert_not_contains!(output, "link.log <==");
+});
@@ -0,0 +1,330 @@
+/*
+ * Copyright 2019 StoneDot (Hiroaki Goto)
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::process::Command;
+use std::thread::sleep;
+use std::time::Duration;
+
+use utils::RunningCommand;
+use utils::WorkingDir;
+
+#[macro_use]
+mod macros;
+mod utils;
+
+const WAIT_TIME: Duration = Duration::from_millis(400);
+
+test!(check_valid_config, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file", "line1\n");
+    let output = cmd
+        .arg("--check-config")
+        .arg("-e=file$")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_contains!(stdout, "configuration is valid");
+    assert_not_contains!(stdout, "line1");
+});
+
+test!(check_invalid_regex, |dir: WorkingDir, mut cmd: Command| {
+    let output = cmd
+        .arg("--check-config")
+        .arg("-e=file(")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_contains!(stderr, "invalid regex supplied");
+});
+
+test!(
+    check_regex_size_limit,
+    |dir: WorkingDir, mut cmd: Command| {
+        // Unicode word classes repeated many times exceed the default limit
+        let output = cmd
+            .arg("--check-config")
+            .arg(r"-e=\w{200}")
+            .arg(dir.path_arg())
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_contains!(stderr, "too big regex");
+    }
+);
+
+test!(
+    raised_regex_size_limit,
+    |dir: WorkingDir, mut cmd: Command| {
+        let output = cmd
+            .arg("--check-config")
+            .arg("--regex-size-limit=100000000")
+            .arg(r"-e=\w{200}")
+            .arg(dir.path_arg())
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+    }
+);
+
+test!(check_missing_path, |_dir: WorkingDir, mut cmd: Command| {
+    let output = cmd
+        .arg("--check-config")
+        .arg("-p=integration_tests/check_missing_path/none")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_contains!(stderr, "supplied path does not exist");
+});
+
+test!(config_file_lines, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("config/regtail/config.toml", "lines = 5\n");
+    dir.put_file("file", "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n");
+    let mut child = RunningCommand::create(
+        cmd.env("XDG_CONFIG_HOME", format!("{}/config", dir.display()))
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    child.exit();
+    let output = child.output();
+    assert_contains!(output, "<==\n8\n9\n10\n11\n12\n");
+    assert_not_contains!(output, "7\n");
+});
+
+test!(
+    config_file_lines_overridden,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file("config/regtail/config.toml", "lines = 5\n");
+        dir.put_file("file", "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n");
+        let mut child = RunningCommand::create(
+            cmd.env("XDG_CONFIG_HOME", format!("{}/config", dir.display()))
+                .arg("-l")
+                .arg("20")
+                .arg(dir.path_arg())
+                .spawn()
+                .unwrap(),
+        );
+        sleep(WAIT_TIME);
+        child.exit();
+        let output = child.output();
+        assert_contains!(output, "<==\n1\n2\n3\n");
+    }
+);
+
+test!(
+    config_file_flag_negated,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file(
+            "config/regtail/config.toml",
+            "recursive = true\nno-initial = true\n",
+        );
+        let output = cmd
+            .env("XDG_CONFIG_HOME", format!("{}/config", dir.display()))
+            .arg("--print-config")
+            .arg("--no-recursive")
+            .arg("--initial")
+            .arg(dir.path_arg())
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        let config: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(config["recursive"], false);
+        assert_eq!(config["no-initial"], false);
+    }
+);
+
+test!(invalid_config_file, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("config/regtail/config.toml", "color = \"sometimes\"\n");
+    let output = cmd
+        .env("XDG_CONFIG_HOME", format!("{}/config", dir.display()))
+        .arg("--check-config")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_contains!(stderr, "invalid config file");
+});
+
+test!(print_config, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("config/regtail/config.toml", "lines = 5\ndepth = 3\n");
+    let output = cmd
+        .env("XDG_CONFIG_HOME", format!("{}/config", dir.display()))
+        .env("REGTAIL_REGEX", r"\.log$")
+        .env("REGTAIL_COLOR", "always")
+        .arg("--print-config")
+        .arg("-r")
+        .arg("-l")
+        .arg("20")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let config: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    // The command line overrides the config file
+    assert_eq!(config["lines"], 20);
+    assert_eq!(config["depth"], 3);
+    assert_eq!(config["recursive"], true);
+    assert_eq!(config["regex"], r"\.log$");
+    assert_eq!(config["colorize"], true);
+    assert_eq!(config["path"], dir.display().to_string());
+    assert_eq!(config["theme"], "default");
+});
+
+test!(env_lines, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file", "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n");
+    let mut child = RunningCommand::create(
+        cmd.env("REGTAIL_LINES", "2")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    child.exit();
+    let output = child.output();
+    assert_contains!(output, "<==\n11\n12\n");
+    assert_not_contains!(output, "10\n");
+});
+
+test!(env_color, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file", "line1\n");
+    let mut child = RunningCommand::create(
+        cmd.env("REGTAIL_COLOR", "always")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    child.exit();
+    let output = child.output();
+    assert_contains!(output, "\u{1b}[");
+});
+
+test!(env_regex, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("matched.log", "matched\n");
+    dir.put_file("ignored.txt", "ignored\n");
+    let mut child = RunningCommand::create(
+        cmd.env("REGTAIL_REGEX", r"\.log$")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    child.exit();
+    let output = child.output();
+    assert_contains!(output, "matched\n");
+    assert_not_contains!(output, "ignored\n");
+});
+
+test!(env_path, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file", "line1\n");
+    let mut child = RunningCommand::create(
+        cmd.env("REGTAIL_PATH", dir.display().to_string())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    child.exit();
+    let output = child.output();
+    assert_contains!(output, "file <==\nline1\n");
+});
+
+test!(cli_overrides_env, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file", "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n");
+    let mut child = RunningCommand::create(
+        cmd.env("REGTAIL_LINES", "2")
+            .env("REGTAIL_COLOR", "always")
+            .arg("-l")
+            .arg("3")
+            .arg("-c")
+            .arg("never")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    child.exit();
+    let output = child.output();
+    assert_contains!(output, "<==\n10\n11\n12\n");
+    assert_not_contains!(output, "\u{1b}[");
+});
+
+test!(
+    env_overrides_config_file,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file("config/regtail/config.toml", "lines = 5\n");
+        dir.put_file("file", "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n");
+        let mut child = RunningCommand::create(
+            cmd.env("XDG_CONFIG_HOME", format!("{}/config", dir.display()))
+                .env("REGTAIL_LINES", "2")
+                .arg(dir.path_arg())
+                .spawn()
+                .unwrap(),
+        );
+        sleep(WAIT_TIME);
+        child.exit();
+        let output = child.output();
+        assert_contains!(output, "<==\n11\n12\n");
+    }
+);
+
+test!(delimiter, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file", "rec1\nmore\x0crec2\nmore\x0c");
+    let mut child = RunningCommand::create(
+        cmd.arg("-l")
+            .arg("1")
+            .arg("--delimiter=\\f")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    child.exit();
+    let output = child.output();
+    assert_contains!(output, "<==\nrec2\nmore\x0c");
+    assert_not_contains!(output, "rec1");
+});
+
+test!(invalid_delimiter, |dir: WorkingDir, mut cmd: Command| {
+    let output = cmd
+        .arg("--check-config")
+        .arg("--delimiter=0xzz")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_contains!(stderr, "invalid hex byte");
+});
+
+test!(invalid_wrap_indent, |dir: WorkingDir, mut cmd: Command| {
+    let output = cmd
+        .arg("--check-config")
+        .arg("--wrap=8")
+        .arg("--wrap-indent=8")
+        .arg(dir.path_arg())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_contains!(stderr, "invalid wrap indent");
+});
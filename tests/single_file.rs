@@ -14,7 +14,8 @@
  * limitations under the License.
  */
 
-use std::process::Command;
+use std::io::Write;
+use std::process::{Command, Stdio};
 use std::thread;
 use std::time::Duration;
 use thread::sleep;
@@ -90,6 +91,25 @@ test!(double_append, |dir: WorkingDir, mut cmd: Command| {
     assert_contains!(output, "double <==\nline1\nline2appended\n");
 });
 
+test!(
+    partial_truncate_append,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file("truncated", "line1\nline2\nline3\n");
+        sleep(WAIT_TIME);
+        let mut child = RunningCommand::create(cmd.arg(dir.path_arg()).spawn().unwrap());
+        sleep(WAIT_TIME);
+        dir.truncate_file("truncated", 6);
+        sleep(WAIT_TIME);
+        dir.append_file("truncated", "line4\n");
+        sleep(WAIT_TIME);
+        let result = child.exit();
+        assert_eq!(result, KillStatus::Killed);
+        let output = child.output();
+        assert_contains!(output, "truncated <==\nline1\nline2\nline3\nline4\n");
+        assert_not_contains!(output, "line3\nline1");
+    }
+);
+
 test!(shrink_append, |dir: WorkingDir, mut cmd: Command| {
     dir.put_file("replaced", "line1\n");
     sleep(WAIT_TIME);
@@ -163,6 +183,22 @@ test!(binary_file, |dir: WorkingDir, mut cmd: Command| {
     assert_not_contains!(output, "yeah!");
 });
 
+test!(sanitize, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("binfile", "This is mostly\0text\n");
+    sleep(WAIT_TIME);
+    let mut child =
+        RunningCommand::create(cmd.arg("--sanitize").arg(dir.path_arg()).spawn().unwrap());
+    sleep(WAIT_TIME);
+    dir.append_file("binfile", "bell\x07rang\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "This is mostly\u{fffd}text\n");
+    assert_contains!(output, "bell\u{fffd}rang\n");
+    assert_not_contains!(output, "\0");
+});
+
 test!(show_binary_file, |dir: WorkingDir, mut cmd: Command| {
     dir.put_file("binfile", "This is not binary\0yeah!");
     sleep(WAIT_TIME);
@@ -243,3 +279,858 @@ test!(symlink, |dir: WorkingDir, mut cmd: Command| {
     let output = child.output();
     assert_contains!(output, "file <==\ninitial contents\nappended");
 });
+
+#[cfg(target_os = "linux")]
+test!(replaced_by_rename, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file", "old contents\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(cmd.arg("file$").arg(dir.path_arg()).spawn().unwrap());
+    sleep(WAIT_TIME);
+    dir.put_file("file.swp", "new contents\n");
+    dir.rename_file("file.swp", "file");
+    sleep(WAIT_TIME);
+    dir.append_file("file", "appended\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "file <==\nold contents\n\n==>");
+    assert_contains!(output, "file <==\nnew contents\nappended\n");
+});
+
+test!(
+    replaced_by_directory,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file("file", "line1\n");
+        sleep(WAIT_TIME);
+        let mut child = RunningCommand::create(
+            cmd.arg(dir.path_arg())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap(),
+        );
+        sleep(WAIT_TIME);
+        dir.remove_file("file");
+        dir.create_dir("file");
+        sleep(WAIT_TIME);
+        dir.put_file("other", "line2\n");
+        sleep(WAIT_TIME);
+        let result = child.exit();
+        assert_eq!(result, KillStatus::Killed);
+        let output = child.output();
+        assert_contains!(output, "file <==\nline1\n\n==>");
+        assert_contains!(output, "other <==\nline2\n");
+        let error_output = child.error_output();
+        assert_contains!(error_output, "has been replaced by a directory");
+    }
+);
+
+test!(squeeze, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file", "line1\nline2\nline2\n");
+    sleep(WAIT_TIME);
+    let mut child =
+        RunningCommand::create(cmd.arg("--squeeze").arg(dir.path_arg()).spawn().unwrap());
+    sleep(WAIT_TIME);
+    dir.append_file("file", "line2\nline3\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(
+        output,
+        "file <==\nline1\nline2\n(repeated 2 times)\nline3\n"
+    );
+});
+
+test!(
+    squeeze_until_removed,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file("file", "line1\nline2\nline2\n");
+        sleep(WAIT_TIME);
+        let mut child =
+            RunningCommand::create(cmd.arg("--squeeze").arg(dir.path_arg()).spawn().unwrap());
+        sleep(WAIT_TIME);
+        dir.remove_file("file");
+        sleep(WAIT_TIME);
+        let result = child.exit();
+        assert_eq!(result, KillStatus::Killed);
+        // The run ends with the file
+        let output = child.output();
+        assert_contains!(output, "file <==\nline1\nline2\n(repeated 1 time)\n");
+    }
+);
+
+test!(grep_context, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file", "info\nerror1\nok\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--grep=error")
+            .arg("-B1")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.append_file("file", "debug\nwarn\nerror2\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "file <==\ninfo\nerror1\n--\nwarn\nerror2\n");
+});
+
+test!(count, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file", "old1\nold2\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(cmd.arg("--count").arg(dir.path_arg()).spawn().unwrap());
+    sleep(WAIT_TIME);
+    dir.append_file("file", "new1\nnew2\nnew3\n");
+    sleep(Duration::from_millis(1500));
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "file: 3 new lines\n");
+    assert_not_contains!(output, "new1");
+    assert_not_contains!(output, "==>");
+});
+
+test!(heartbeat, |dir: WorkingDir, mut cmd: Command| {
+    let mut child = RunningCommand::create(
+        cmd.arg("--heartbeat=1")
+            .arg(dir.path_arg())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(Duration::from_millis(2500));
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    assert_contains!(child.error_output(), "(no new data for 1s)");
+});
+
+test!(heartbeat_quiet, |dir: WorkingDir, mut cmd: Command| {
+    let mut child = RunningCommand::create(
+        cmd.arg("--heartbeat=1")
+            .arg("--quiet")
+            .arg(dir.path_arg())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(Duration::from_millis(2500));
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    assert_not_contains!(child.error_output(), "no new data");
+});
+
+test!(max_rate, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file", "");
+    sleep(WAIT_TIME);
+    let mut child =
+        RunningCommand::create(cmd.arg("--max-rate=5").arg(dir.path_arg()).spawn().unwrap());
+    sleep(WAIT_TIME);
+    let flood: String = (0..1000).map(|i| format!("line{}\n", i)).collect();
+    dir.append_file("file", &flood);
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "line4\n");
+    assert_not_contains!(output, "line999\n");
+    assert_contains!(output, "(995 lines suppressed)");
+});
+
+test!(single_file_path, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("target", "target line1\n");
+    dir.put_file("sibling", "sibling line1\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg(format!("-p={}/target", dir.display()))
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.append_file("target", "target line2\n");
+    sleep(WAIT_TIME);
+    dir.append_file("sibling", "sibling line2\n");
+    sleep(WAIT_TIME);
+    dir.append_file("target", "target line3\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(
+        output,
+        "target <==\ntarget line1\ntarget line2\ntarget line3\n"
+    );
+    assert_not_contains!(output, "sibling");
+});
+
+test!(exit_when_empty, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("removed_file", "line1\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--exit-when-empty")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.remove_file("removed_file");
+    sleep(Duration::from_secs(3));
+    assert!(child.has_exited());
+    let output = child.output();
+    assert_contains!(output, "removed_file <==\nline1\n");
+});
+
+test!(empty_dir_not_exit, |dir: WorkingDir, mut cmd: Command| {
+    let mut child = RunningCommand::create(
+        cmd.arg("--exit-when-empty")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(Duration::from_secs(3));
+    assert!(!child.has_exited());
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+});
+
+test!(max_output, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file", "initial\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--max-output=100")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    assert!(!child.has_exited());
+    dir.append_file("file", &"0123456789\n".repeat(20));
+    sleep(WAIT_TIME);
+    assert!(child.has_exited());
+    let output = child.output();
+    assert_contains!(output, "file <==\ninitial\n0123456789\n");
+});
+
+test!(flush_interval, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file", "line1\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--flush-interval=50")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.append_file("file", "line2\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "file <==\nline1\nline2\n");
+});
+
+test!(
+    flush_interval_holds_output,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file("file", "line1\n");
+        sleep(WAIT_TIME);
+        let mut child = RunningCommand::create(
+            cmd.arg("--flush-interval=10000")
+                .arg(dir.path_arg())
+                .spawn()
+                .unwrap(),
+        );
+        sleep(WAIT_TIME);
+        dir.append_file("file", "line2\n");
+        sleep(WAIT_TIME);
+        // Killed before the interval passes, so the appended line is still in the buffer
+        let result = child.exit();
+        assert_eq!(result, KillStatus::Killed);
+        let output = child.output();
+        assert_contains!(output, "file <==\nline1\n");
+        assert_not_contains!(output, "line2");
+    }
+);
+
+test!(flush_every_write, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file", "line1\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--flush-interval=0")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.append_file("file", "line2\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "file <==\nline1\nline2\n");
+});
+
+test!(poll, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file", "line1\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(cmd.arg("--poll").arg(dir.path_arg()).spawn().unwrap());
+    // The polling watcher detects modification by the timestamp in seconds
+    sleep(Duration::from_millis(1100));
+    dir.append_file("file", "line2\n");
+    dir.put_file("new_file", "new line1\n");
+    sleep(Duration::from_millis(1500));
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "file <==\nline1\nline2\n");
+    assert_contains!(output, "new_file <==\nnew line1\n");
+});
+
+#[cfg(target_os = "linux")]
+test!(poll_on_emfile, |dir: WorkingDir, mut cmd: Command| {
+    use std::os::unix::process::CommandExt;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    dir.put_file("file", "line1\n");
+    let limit = Arc::new(AtomicU64::new(0));
+    let child_limit = Arc::clone(&limit);
+    cmd.arg("--poll-on-error")
+        .arg("file$")
+        .arg(dir.path_arg())
+        .stderr(Stdio::piped());
+    unsafe {
+        cmd.pre_exec(move || {
+            let value = child_limit.load(Ordering::SeqCst);
+            let rlimit = libc::rlimit {
+                rlim_cur: value,
+                rlim_max: value,
+            };
+            if libc::setrlimit(libc::RLIMIT_NOFILE, &rlimit) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    // Some limit leaves the descriptors enough for the startup but not for inotify
+    let fell_back = (4..16).any(|value| {
+        limit.store(value, Ordering::SeqCst);
+        let mut child = RunningCommand::create(cmd.spawn().unwrap());
+        sleep(WAIT_TIME);
+        child.exit();
+        let error_output = child.error_output();
+        error_output.contains("raise fs.inotify.max_user_instances")
+            && error_output.contains("falling back to polling")
+    });
+    assert!(fell_back);
+});
+
+test!(all_lines, |dir: WorkingDir, mut cmd: Command| {
+    let content: String = (1..=15).map(|i| format!("line{}\n", i)).collect();
+    dir.put_file("file", &content);
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(cmd.arg("--all").arg(dir.path_arg()).spawn().unwrap());
+    sleep(WAIT_TIME);
+    dir.append_file("file", "line16\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, format!("file <==\n{}line16\n", content));
+});
+
+test!(
+    all_lines_by_lines_option,
+    |dir: WorkingDir, mut cmd: Command| {
+        let content: String = (1..=15).map(|i| format!("line{}\n", i)).collect();
+        dir.put_file("file", &content);
+        sleep(WAIT_TIME);
+        let mut child = RunningCommand::create(
+            cmd.arg("-l")
+                .arg("all")
+                .arg(dir.path_arg())
+                .spawn()
+                .unwrap(),
+        );
+        sleep(WAIT_TIME);
+        let result = child.exit();
+        assert_eq!(result, KillStatus::Killed);
+        let output = child.output();
+        assert_contains!(output, format!("file <==\n{}", content));
+    }
+);
+
+#[cfg(unix)]
+test!(fifo, |dir: WorkingDir, mut cmd: Command| {
+    dir.mkfifo("pipe");
+    let mut child = RunningCommand::create(cmd.arg(dir.path_arg()).spawn().unwrap());
+    sleep(WAIT_TIME);
+    dir.write_fifo("pipe", "line1\nline2\n");
+    sleep(WAIT_TIME);
+    dir.write_fifo("pipe", "line3\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "pipe <==\nline1\nline2\nline3\n");
+});
+
+test!(stdin, |_dir: WorkingDir, mut cmd: Command| {
+    let mut child = cmd
+        .arg("-l=2")
+        .arg("--stdin")
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"line1\nline2\nline3\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "line2\nline3\n");
+});
+
+// Nothing on the standard input, as for a service, does not replace the current directory
+test!(stdin_not_implied, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("app.log", "app!\n");
+    let output = cmd
+        .arg("--once")
+        .current_dir(dir.display().to_string())
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_contains!(String::from_utf8_lossy(&output.stdout), "app!\n");
+});
+
+test!(stdin_all, |_dir: WorkingDir, mut cmd: Command| {
+    let mut child = cmd
+        .arg("--grep=match")
+        .arg("-p=-")
+        .arg("--all")
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"match1\nother\nmatch2\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "match1\nmatch2\n");
+});
+
+test!(debounce, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("debounced", "line0\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--debounce=100")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    for i in 1..=20 {
+        dir.append_file("debounced", &format!("line{}\n", i));
+    }
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let expected: String = (0..=20).map(|i| format!("line{}\n", i)).collect();
+    assert_contains!(child.output(), expected);
+});
+
+#[cfg(target_os = "linux")]
+test!(reload_on_sighup, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("app.log", "before rotation\n");
+    sleep(WAIT_TIME);
+    // The events are held much longer than the test, so only the reload shows the new lines
+    let mut child = RunningCommand::create(
+        cmd.arg("--reload-on-sighup")
+            .arg("--debounce=60000")
+            .arg("app.log$")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.rename_file("app.log", "app.log.1");
+    dir.put_file("app.log", "after rotation\n");
+    child.signal(libc::SIGHUP);
+    sleep(WAIT_TIME);
+    dir.append_file("app.log", "appended\n");
+    child.signal(libc::SIGHUP);
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "after rotation\nappended\n");
+    assert_eq!(output.matches("after rotation").count(), 1);
+});
+
+#[cfg(unix)]
+test!(
+    sighup_without_reload,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file("app.log", "line\n");
+        sleep(WAIT_TIME);
+        let mut child =
+            RunningCommand::create(cmd.arg("app.log$").arg(dir.path_arg()).spawn().unwrap());
+        sleep(WAIT_TIME);
+        child.signal(libc::SIGHUP);
+        sleep(WAIT_TIME);
+        assert!(child.has_exited());
+    }
+);
+
+#[cfg(unix)]
+test!(pause_on_sigusr1, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("app.log", "before pause\n");
+    sleep(WAIT_TIME);
+    let tee_path = format!("{}/captured", dir.display());
+    let mut child = RunningCommand::create(
+        cmd.arg("app.log$")
+            .arg(format!("--tee={}", tee_path))
+            .arg(dir.path_arg())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    child.signal(libc::SIGUSR1);
+    sleep(WAIT_TIME);
+    dir.append_file("app.log", "while paused\n");
+    sleep(WAIT_TIME);
+    let captured = std::fs::read_to_string(&tee_path).unwrap();
+    assert_contains!(captured, "before pause\n");
+    assert_not_contains!(captured, "while paused");
+    child.signal(libc::SIGUSR1);
+    sleep(WAIT_TIME);
+    dir.append_file("app.log", "resumed\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "before pause\nwhile paused\nresumed\n");
+    assert_eq!(output.matches("while paused").count(), 1);
+    assert_contains!(child.error_output(), "output paused");
+});
+
+#[cfg(target_os = "linux")]
+test!(rename_while_paused, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("app.log", "before pause\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--follow-renamed")
+            .arg("app.log$")
+            .arg(dir.path_arg())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    child.signal(libc::SIGUSR1);
+    sleep(WAIT_TIME);
+    // The renamed file no longer matches, so only the queued rename keeps it followed
+    dir.rename_file("app.log", "app.old");
+    dir.append_file("app.old", "while paused\n");
+    sleep(WAIT_TIME);
+    child.signal(libc::SIGUSR1);
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "app.old <==\nwhile paused\n");
+});
+
+test!(
+    remove_while_appending,
+    |dir: WorkingDir, mut cmd: Command| {
+        dir.put_file("rotating", "line1\n");
+        sleep(WAIT_TIME);
+        let mut child = RunningCommand::create(cmd.arg(dir.path_arg()).spawn().unwrap());
+        sleep(WAIT_TIME);
+        for i in 2..10 {
+            dir.append_file("rotating", &format!("line{}\n", i));
+            dir.remove_file("rotating");
+            dir.put_file("rotating", "");
+        }
+        dir.remove_file("rotating");
+        sleep(WAIT_TIME);
+        dir.put_file("next", "still running\n");
+        sleep(WAIT_TIME);
+        dir.remove_file("next");
+        sleep(WAIT_TIME);
+        let result = child.exit();
+        assert_eq!(result, KillStatus::Killed);
+        assert_contains!(child.output(), "next <==\nstill running\n");
+    }
+);
+
+test!(state_file, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("app.log", "line1\nline2\n");
+    sleep(WAIT_TIME);
+    let state_file = format!("{}/regtail.state", dir.display());
+    cmd.arg("--state-file")
+        .arg(&state_file)
+        .arg("log$")
+        .arg(dir.path_arg());
+    let mut child = RunningCommand::create(cmd.spawn().unwrap());
+    sleep(WAIT_TIME);
+    dir.append_file("app.log", "line3\n");
+    sleep(Duration::from_millis(1500));
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    assert_contains!(child.output(), "line1\nline2\nline3\n");
+
+    // Resume from the offset saved by the first run
+    dir.append_file("app.log", "line4\n");
+    let mut child = RunningCommand::create(cmd.spawn().unwrap());
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "app.log <==\nline4\n");
+    assert_not_contains!(output, "line3");
+
+    // Tail from the end again if the file was rotated
+    dir.put_file("app.log.new", "rotated1\nrotated2\n");
+    dir.rename_file("app.log.new", "app.log");
+    let mut child = RunningCommand::create(cmd.spawn().unwrap());
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    assert_contains!(child.output(), "rotated1\nrotated2\n");
+});
+
+#[cfg(unix)]
+test!(
+    state_file_on_sigterm,
+    |dir: WorkingDir, mut cmd: Command| {
+        use std::os::unix::process::ExitStatusExt;
+
+        dir.put_file("app.log", "line1\n");
+        sleep(WAIT_TIME);
+        let state_file = format!("{}/regtail.state", dir.display());
+        let mut child = RunningCommand::create(
+            cmd.arg("--state-file")
+                .arg(&state_file)
+                .arg("log$")
+                .arg(dir.path_arg())
+                .spawn()
+                .unwrap(),
+        );
+        // Terminated before the offsets are saved periodically
+        sleep(Duration::from_millis(300));
+        child.signal(libc::SIGTERM);
+        let status = child.wait();
+        assert_eq!(status.signal(), Some(libc::SIGTERM));
+        assert_contains!(child.output(), "line1\n");
+        let saved = std::fs::read_to_string(&state_file).unwrap();
+        assert_contains!(saved, "\"offset\":6");
+    }
+);
+
+test!(shift_jis, |dir: WorkingDir, mut cmd: Command| {
+    // "日本語\n" in Shift_JIS
+    let content: &[u8] = b"\x93\xfa\x96\x7b\x8c\xea\n";
+    dir.put_file("sjis.log", content);
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--encoding=shift_jis")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.append_file("sjis.log", "ascii\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    assert_contains!(child.output(), "sjis.log <==\n日本語\nascii\n");
+});
+
+test!(short_interval, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("removed_file", "line1\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--interval=50")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.remove_file("removed_file");
+    sleep(Duration::from_millis(100));
+    dir.put_file("removed_file", "line2\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "line1\n\n==>");
+    assert_contains!(output, "removed_file <==\nline2");
+});
+
+#[cfg(unix)]
+test!(bell, |dir: WorkingDir, mut cmd: Command| {
+    use std::io::Read;
+
+    dir.put_file("alerted", "");
+    sleep(WAIT_TIME);
+    let tee_path = format!("{}/captured", dir.display());
+    // The bell rings on a terminal whether colorized or not
+    let (mut terminal, slave) = utils::open_pty();
+    let mut child = RunningCommand::create(
+        cmd.arg("--bell")
+            .arg("--grep=ERROR")
+            .args(["-c", "never"])
+            .arg(format!("--tee={}", tee_path))
+            .arg(dir.path_arg())
+            .stdout(slave)
+            .spawn()
+            .unwrap(),
+    );
+    // Close the slave here, so that the terminal ends with the command
+    cmd.stdout(Stdio::null());
+    sleep(WAIT_TIME);
+    dir.append_file("alerted", "fine\nERROR first\nERROR second\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let mut output = Vec::new();
+    let _ = terminal.read_to_end(&mut output);
+    let output = String::from_utf8_lossy(&output);
+    // The terminal translates the newlines
+    assert_contains!(output, "ERROR first\r\n\x07ERROR second\r\n");
+    // Rate limited
+    assert_eq!(output.matches('\x07').count(), 1);
+    let captured = std::fs::read_to_string(&tee_path).unwrap();
+    assert_contains!(captured, "ERROR first\nERROR second\n");
+    assert_not_contains!(captured, "\x07");
+});
+
+test!(no_bell_when_piped, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("alerted", "");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--bell")
+            .arg("--grep=ERROR")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    dir.append_file("alerted", "ERROR first\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "ERROR first\n");
+    assert_not_contains!(output, "\x07");
+});
+
+#[cfg(unix)]
+test!(event_socket, |dir: WorkingDir, mut cmd: Command| {
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+
+    dir.put_file("app.log", "line1\n");
+    sleep(WAIT_TIME);
+    let socket_path = format!("{}/regtail.sock", dir.display());
+    let mut child = RunningCommand::create(
+        cmd.arg("--socket")
+            .arg(&socket_path)
+            .arg("log$")
+            .arg(dir.path_arg())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    let mut client = UnixStream::connect(&socket_path).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    sleep(WAIT_TIME);
+    dir.append_file("app.log", "line2\n");
+    let mut length = [0u8; 4];
+    client.read_exact(&mut length).unwrap();
+    let mut message = vec![0u8; u32::from_be_bytes(length) as usize];
+    client.read_exact(&mut message).unwrap();
+    let message: serde_json::Value = serde_json::from_slice(&message).unwrap();
+    assert_eq!(message["line"], "line2");
+    assert!(message["path"].as_str().unwrap().ends_with("app.log"));
+
+    // A disconnected client does not stop the output
+    drop(client);
+    dir.append_file("app.log", "line3\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    assert_contains!(child.output(), "line1\nline2\nline3\n");
+});
+
+test!(raw, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("raw.log", "line1\r\nno eol");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(cmd.arg("--raw").arg(dir.path_arg()).spawn().unwrap());
+    sleep(WAIT_TIME);
+    dir.append_file("raw.log", " continued\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    assert_eq!(child.output(), "line1\r\nno eol continued\n");
+});
+
+test!(no_initial, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("file", "initial\n");
+    sleep(WAIT_TIME);
+    cmd.arg("--no-initial").arg(dir.path_arg());
+    let mut child = RunningCommand::create(cmd.spawn().unwrap());
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    // Not even the header is printed
+    assert_eq!(child.output(), "");
+
+    let mut child = RunningCommand::create(cmd.spawn().unwrap());
+    sleep(WAIT_TIME);
+    dir.append_file("file", "appended\n");
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    let output = child.output();
+    assert_contains!(output, "file <==\nappended\n");
+    assert_not_contains!(output, "initial\n");
+});
+
+test!(watch_events, |dir: WorkingDir, mut cmd: Command| {
+    dir.put_file("chmod.log", "line1\n");
+    sleep(WAIT_TIME);
+    let mut child = RunningCommand::create(
+        cmd.arg("--watch-events")
+            .arg(dir.path_arg())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    sleep(WAIT_TIME);
+    let path = format!("{}/chmod.log", dir.display());
+    let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+    permissions.set_readonly(true);
+    std::fs::set_permissions(&path, permissions).unwrap();
+    sleep(WAIT_TIME);
+    let result = child.exit();
+    assert_eq!(result, KillStatus::Killed);
+    // The operation reported differs by the platform
+    let error_output = child.error_output();
+    assert_contains!(error_output, "event: ");
+    assert_contains!(error_output, "chmod.log");
+});
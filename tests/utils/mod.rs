@@ -23,14 +23,19 @@ pub fn setup(test_directory: &str) -> (WorkingDir, Command) {
     let dir = PathBuf::from(format!("integration_tests/{}", test_directory));
     let _ = fs::remove_dir_all(&dir);
     fs::create_dir_all(&dir).unwrap();
+    let working_dir = WorkingDir::create(dir);
+    (working_dir, command())
+}
+
+// A fresh command for a test that runs regtail more than once
+pub fn command() -> Command {
     let test_exec_path = std::env::current_exe().unwrap();
     let exec_dir = test_exec_path.parent().unwrap().parent().unwrap();
     let mut exec_path = exec_dir.to_path_buf();
     exec_path.push("regtail");
     let mut command = Command::new(dbg!(exec_path));
     command.stdout(Stdio::piped());
-    let working_dir = WorkingDir::create(dir);
-    (working_dir, command)
+    command
 }
 
 pub struct WorkingDir {
@@ -79,6 +84,13 @@ impl WorkingDir {
         fs::remove_file(remove_file_path).expect("Cannot remove file");
     }
 
+    #[allow(dead_code)]
+    pub fn create_dir(self: &Self, relative_path: &str) {
+        let mut dir_path = self.parent_path.clone();
+        dir_path.push(relative_path);
+        fs::create_dir_all(dir_path).expect("Cannot create directory");
+    }
+
     #[allow(dead_code)]
     pub fn rename_file(self: &Self, src_relative_path: &str, dest_relative_path: &str) {
         let mut src_file_path = self.parent_path.clone();
@@ -101,6 +113,45 @@ impl WorkingDir {
         fh.sync_all().expect("Failed to sync");
     }
 
+    #[allow(dead_code)]
+    pub fn truncate_file(self: &Self, relative_path: &str, len: u64) {
+        let mut truncate_file_path = self.parent_path.clone();
+        truncate_file_path.push(relative_path);
+        let file_path_str = truncate_file_path.display().to_string();
+        let fh = OpenOptions::new()
+            .write(true)
+            .open(truncate_file_path)
+            .unwrap_or_else(|_| panic!("Failed to open '{}' with write mode", file_path_str));
+        fh.set_len(len).expect("Cannot truncate file");
+        fh.sync_all().expect("Failed to sync");
+    }
+
+    #[allow(dead_code)]
+    #[cfg(unix)]
+    pub fn mkfifo(self: &Self, relative_path: &str) {
+        let mut fifo_path = self.parent_path.clone();
+        fifo_path.push(relative_path);
+        let status = Command::new("mkfifo")
+            .arg(&fifo_path)
+            .status()
+            .expect("Failed to run mkfifo");
+        assert!(status.success(), "Failed to make a named pipe");
+    }
+
+    // A named pipe cannot be synced, so it is just written
+    #[allow(dead_code)]
+    #[cfg(unix)]
+    pub fn write_fifo(self: &Self, relative_path: &str, content: &str) {
+        let mut fifo_path = self.parent_path.clone();
+        fifo_path.push(relative_path);
+        let mut fh = OpenOptions::new()
+            .write(true)
+            .open(fifo_path)
+            .expect("Failed to open the named pipe");
+        fh.write_all(content.as_bytes())
+            .expect("Cannot write the named pipe");
+    }
+
     #[allow(dead_code)]
     #[cfg(target_os = "linux")]
     pub fn symlink(self: &Self, relative_src: &str, relative_dest: &str) {
@@ -123,6 +174,27 @@ impl WorkingDir {
     }
 }
 
+// The master and the slave of a pseudo terminal, to run the command on a terminal
+#[allow(dead_code)]
+#[cfg(unix)]
+pub fn open_pty() -> (File, File) {
+    use std::os::unix::io::FromRawFd;
+
+    let mut master = 0;
+    let mut slave = 0;
+    let result = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    assert_eq!(result, 0, "Failed to open a pseudo terminal");
+    unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) }
+}
+
 pub struct RunningCommand {
     child: Child,
 }
@@ -148,6 +220,23 @@ impl RunningCommand {
         kill_result
     }
 
+    #[cfg(unix)]
+    #[allow(dead_code)]
+    pub fn signal(self: &Self, signal: i32) {
+        let result = unsafe { libc::kill(self.child.id() as i32, signal) };
+        assert_eq!(result, 0, "Failed to send the signal");
+    }
+
+    #[allow(dead_code)]
+    pub fn wait(self: &mut Self) -> std::process::ExitStatus {
+        self.child.wait().unwrap()
+    }
+
+    #[allow(dead_code)]
+    pub fn has_exited(self: &mut Self) -> bool {
+        self.child.try_wait().unwrap().is_some()
+    }
+
     pub fn output(self: &mut Self) -> String {
         let mut output = String::new();
         let _size = self
@@ -158,4 +247,16 @@ impl RunningCommand {
             .read_to_string(&mut output);
         output
     }
+
+    #[allow(dead_code)]
+    pub fn error_output(self: &mut Self) -> String {
+        let mut output = String::new();
+        let _size = self
+            .child
+            .stderr
+            .as_mut()
+            .expect("stderr is not piped")
+            .read_to_string(&mut output);
+        output
+    }
 }